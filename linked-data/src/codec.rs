@@ -0,0 +1,97 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+use cid::Cid;
+
+/// Multicodec code of DAG-JSON.
+pub const DAG_JSON: u64 = 0x0129;
+
+/// Multicodec code of DAG-CBOR.
+pub const DAG_CBOR: u64 = 0x71;
+
+/// IPLD codec used to encode dag nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+    DagJson,
+    DagCbor,
+}
+
+/// DAG-JSON, as nodes were stored before the codec could be chosen.
+impl Default for Codec {
+    fn default() -> Self {
+        Self::DagJson
+    }
+}
+
+impl Codec {
+    /// Multicodec name, as expected by the IPFS API.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DagJson => "dag-json",
+            Self::DagCbor => "dag-cbor",
+        }
+    }
+
+    /// Multicodec code.
+    pub fn code(&self) -> u64 {
+        match self {
+            Self::DagJson => DAG_JSON,
+            Self::DagCbor => DAG_CBOR,
+        }
+    }
+
+    /// Codec of the node this CID links to, if supported.
+    pub fn from_cid(cid: &Cid) -> Option<Self> {
+        match cid.codec() {
+            DAG_JSON => Some(Self::DagJson),
+            DAG_CBOR => Some(Self::DagCbor),
+            _ => None,
+        }
+    }
+
+    /// Serialize a node.
+    pub fn encode<T>(&self, node: &T) -> Result<Vec<u8>, Error>
+    where
+        T: Serialize,
+    {
+        match self {
+            Self::DagJson => Ok(serde_json::to_vec(node)?),
+            Self::DagCbor => Ok(serde_cbor::to_vec(node)?),
+        }
+    }
+
+    /// Deserialize a node.
+    pub fn decode<T>(&self, data: &[u8]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Self::DagJson => Ok(serde_json::from_slice(data)?),
+            Self::DagCbor => Ok(serde_cbor::from_slice(data)?),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dag-json" => Ok(Self::DagJson),
+            "dag-cbor" => Ok(Self::DagCbor),
+            _ => Err(format!("Unknown codec {}", s)),
+        }
+    }
+}
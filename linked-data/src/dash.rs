@@ -0,0 +1,280 @@
+use crate::video::{SetupNode, Track};
+
+use std::fmt::Write;
+
+use cid::Cid;
+
+/// Milliseconds.
+const TIMESCALE: u64 = 1000;
+
+/// Consecutive video segments sharing the same setup.
+pub struct Period {
+    pub setup: SetupNode,
+
+    /// VideoNode CIDs and their duration in seconds.
+    pub segments: Vec<(Cid, f64)>,
+}
+
+/// Generate a static MPEG-DASH manifest addressing segments through an IPFS gateway.
+///
+/// Each period is a DASH Period, video tracks are representations of one adaptation set
+/// and the audio track has its own.
+pub fn media_presentation(periods: &[Period], gateway: &str) -> String {
+    let gateway = gateway.trim_end_matches('/');
+
+    let total: f64 = periods
+        .iter()
+        .flat_map(|period| period.segments.iter())
+        .map(|(_, duration)| duration)
+        .sum();
+
+    let max = periods
+        .iter()
+        .flat_map(|period| period.segments.iter())
+        .fold(1.0_f64, |max, (_, duration)| max.max(*duration));
+
+    let mut mpd = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let _ = writeln!(
+        mpd,
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-main:2011" type="static" mediaPresentationDuration="{}" minBufferTime="{}" maxSegmentDuration="{}">"#,
+        iso_duration(total),
+        iso_duration(max),
+        iso_duration(max),
+    );
+
+    let mut start = 0.0;
+
+    for (id, period) in periods.iter().enumerate() {
+        let duration: f64 = period.segments.iter().map(|(_, duration)| duration).sum();
+
+        let _ = writeln!(
+            mpd,
+            r#"  <Period id="{}" start="{}" duration="{}">"#,
+            id,
+            iso_duration(start),
+            iso_duration(duration)
+        );
+
+        let (audio, video): (Vec<&Track>, Vec<&Track>) = period
+            .setup
+            .tracks
+            .iter()
+            .partition(|track| track.name == "audio");
+
+        adaptation_set(&mut mpd, "video", &video, &period.segments, gateway);
+        adaptation_set(&mut mpd, "audio", &audio, &period.segments, gateway);
+
+        mpd.push_str("  </Period>\n");
+
+        start += duration;
+    }
+
+    mpd.push_str("</MPD>\n");
+
+    mpd
+}
+
+fn adaptation_set(
+    mpd: &mut String,
+    content_type: &str,
+    tracks: &[&Track],
+    segments: &[(Cid, f64)],
+    gateway: &str,
+) {
+    let first = match tracks.first() {
+        Some(track) => track,
+        None => return,
+    };
+
+    let (mime, _) = split_mime(&first.codec);
+
+    let _ = writeln!(
+        mpd,
+        r#"    <AdaptationSet contentType="{}" mimeType="{}" segmentAlignment="true">"#,
+        content_type,
+        escape(mime)
+    );
+
+    for track in tracks {
+        let (_, codecs) = split_mime(&track.codec);
+
+        let _ = writeln!(
+            mpd,
+            r#"      <Representation id="{}" codecs="{}" bandwidth="{}">"#,
+            escape(&track.name),
+            escape(codecs),
+            track.bandwidth
+        );
+
+        let _ = writeln!(mpd, r#"        <SegmentList timescale="{}">"#, TIMESCALE);
+
+        let _ = writeln!(
+            mpd,
+            r#"          <Initialization sourceURL="{}/ipfs/{}"/>"#,
+            escape(gateway),
+            track.initialization_segment.link
+        );
+
+        mpd.push_str("          <SegmentTimeline>\n");
+
+        for (_, duration) in segments {
+            let _ = writeln!(
+                mpd,
+                r#"            <S d="{}"/>"#,
+                (duration * TIMESCALE as f64).round() as u64
+            );
+        }
+
+        mpd.push_str("          </SegmentTimeline>\n");
+
+        for (cid, _) in segments {
+            let _ = writeln!(
+                mpd,
+                r#"          <SegmentURL media="{}/ipfs/{}/track/{}"/>"#,
+                escape(gateway),
+                cid,
+                escape(&track.name)
+            );
+        }
+
+        mpd.push_str("        </SegmentList>\n");
+        mpd.push_str("      </Representation>\n");
+    }
+
+    mpd.push_str("    </AdaptationSet>\n");
+}
+
+/// Split a MIME type into its type and codecs. video/mp4; codecs="avc1.42c01f" => (video/mp4, avc1.42c01f)
+fn split_mime(mime: &str) -> (&str, &str) {
+    match mime.split_once(';') {
+        Some((mime, params)) => {
+            let codecs = match params.split_once("codecs=") {
+                Some((_, codecs)) => codecs.trim().trim_matches('"'),
+                None => "",
+            };
+
+            (mime.trim(), codecs)
+        }
+        None => (mime.trim(), ""),
+    }
+}
+
+/// Seconds to ISO 8601 duration.
+fn iso_duration(seconds: f64) -> String {
+    format!("PT{:.3}S", seconds)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    const GATEWAY: &str = "http://127.0.0.1:8080/";
+
+    fn cid(cid: &str) -> Cid {
+        Cid::try_from(cid).expect("Invalid Cid")
+    }
+
+    fn track(name: &str, codec: &str, init: &str) -> Track {
+        Track {
+            name: name.into(),
+            codec: codec.into(),
+            initialization_segment: cid(init).into(),
+            bandwidth: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn periods_follow_segments() {
+        let audio = "bafyreihq4jyy2g4uqkxdvngo4c3a47pmw64q77wcycmeidc365k5322xk4";
+        let first = "bafyreibxlmepwy2oekextlldmpzpr2hdjopvhkxer62akslrhxo7ohc3pi";
+        let second = "bafyreiduwevgejse3dolgn5mukzrfoc665jlsuf5mabwqcu74urge2dy6i";
+        let third = "bafyreictaxyji2qe67shey3iehfow7t4vyyqzky4ductapl673m2w544va";
+
+        let periods = vec![
+            Period {
+                setup: SetupNode {
+                    tracks: vec![
+                        track(
+                            "720p30",
+                            r#"video/mp4; codecs="avc1.42c01f""#,
+                            "bafyreiblilr5oqryu5eanq2c3qegbju3qlzhfvcoibegbcvup2y7zxsgqy",
+                        ),
+                        track("audio", r#"audio/mp4; codecs="mp4a.40.2""#, audio),
+                    ],
+                },
+                segments: vec![(cid(first), 2.0), (cid(second), 1.5)],
+            },
+            Period {
+                setup: SetupNode {
+                    tracks: vec![
+                        track(
+                            "1080p60",
+                            r#"video/mp4; codecs="avc1.64002a""#,
+                            "bafyreicxpbqj3gnqwl2rjph2xkww3nqdja22qyadk4pf4b3n5vazlfxn4y",
+                        ),
+                        track("audio", r#"audio/mp4; codecs="mp4a.40.2""#, audio),
+                    ],
+                },
+                segments: vec![(cid(third), 4.0)],
+            },
+        ];
+
+        let mpd = media_presentation(&periods, GATEWAY);
+
+        assert!(mpd.contains(r#"mediaPresentationDuration="PT7.500S""#));
+        assert!(mpd.contains(r#"maxSegmentDuration="PT4.000S""#));
+
+        let blocks: Vec<&str> = mpd.split("<Period ").skip(1).collect();
+        assert_eq!(blocks.len(), 2);
+
+        let (one, two) = (blocks[0], blocks[1]);
+
+        assert!(one.starts_with(r#"id="0" start="PT0.000S" duration="PT3.500S""#));
+        assert!(two.starts_with(r#"id="1" start="PT3.500S" duration="PT4.000S""#));
+
+        // One timeline per representation, video and audio
+        assert_eq!(one.matches(r#"<S d="2000"/>"#).count(), 2);
+        assert_eq!(one.matches(r#"<S d="1500"/>"#).count(), 2);
+        assert_eq!(one.matches("<S ").count(), 4);
+        assert_eq!(two.matches(r#"<S d="4000"/>"#).count(), 2);
+        assert_eq!(two.matches("<S ").count(), 2);
+
+        let url = |cid: &str, track: &str| {
+            format!(
+                r#"<SegmentURL media="http://127.0.0.1:8080/ipfs/{}/track/{}"/>"#,
+                cid, track
+            )
+        };
+
+        for segment in [first, second].iter() {
+            assert!(one.contains(&url(segment, "720p30")));
+            assert!(one.contains(&url(segment, "audio")));
+            assert!(!two.contains(*segment));
+        }
+
+        assert!(two.contains(&url(third, "1080p60")));
+        assert!(two.contains(&url(third, "audio")));
+        assert!(!one.contains(third));
+
+        // Segment URLs in playback order
+        let index = |cid: &str| one.find(&url(cid, "720p30")).expect("Missing segment");
+        assert!(index(first) < index(second));
+
+        assert!(one.contains(&format!(
+            r#"<Initialization sourceURL="http://127.0.0.1:8080/ipfs/{}"/>"#,
+            audio
+        )));
+        assert!(one.contains(r#"codecs="avc1.42c01f""#));
+        assert!(two.contains(r#"codecs="avc1.64002a""#));
+    }
+}
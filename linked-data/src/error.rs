@@ -0,0 +1,70 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid CID.
+    Cid(cid::Error),
+
+    /// DAG-JSON (de)serialization failed.
+    Json(serde_json::Error),
+
+    /// DAG-CBOR (de)serialization failed.
+    Cbor(serde_cbor::Error),
+
+    /// Malformed signature, message or public key.
+    Secp256k1(libsecp256k1::Error),
+
+    /// Signatures are 65 bytes long.
+    SignatureLength(usize),
+
+    /// The signature was not made by this address.
+    AddressMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cid(e) => write!(f, "CID: {}", e),
+            Self::Json(e) => write!(f, "DAG-JSON: {}", e),
+            Self::Cbor(e) => write!(f, "DAG-CBOR: {}", e),
+            Self::Secp256k1(e) => write!(f, "Secp256k1: {}", e),
+            Self::SignatureLength(len) => write!(f, "Signature: {} bytes long not 65", len),
+            Self::AddressMismatch => write!(f, "Signature: address mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Cid(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Cbor(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<cid::Error> for Error {
+    fn from(error: cid::Error) -> Self {
+        Self::Cid(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<serde_cbor::Error> for Error {
+    fn from(error: serde_cbor::Error) -> Self {
+        Self::Cbor(error)
+    }
+}
+
+impl From<libsecp256k1::Error> for Error {
+    fn from(error: libsecp256k1::Error) -> Self {
+        Self::Secp256k1(error)
+    }
+}
@@ -1,8 +1,13 @@
 use crate::blog::{FullPost, MicroPost};
-use crate::video::VideoMetadata;
+use crate::video::{ClipNode, VideoMetadata};
 use crate::IPLDLink;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Schema version of newly created feed entries.
+/// Untagged entries predating the version field are considered version 0.
+pub const SCHEMA_VERSION: u32 = 1;
 
 /// Content feed in chronological order.
 /// Direct pin.
@@ -11,12 +16,17 @@ pub struct Feed {
     pub content: Vec<IPLDLink>,
 }
 
-#[derive(Deserialize, Clone, PartialEq)]
-#[serde(untagged)]
+/// Any feed entry.
+/// Serialized with a type discriminator and schema version.
+#[derive(Clone, PartialEq)]
 pub enum Media {
     Statement(MicroPost),
     Blog(FullPost),
     Video(VideoMetadata),
+    Clip(ClipNode),
+
+    /// Entry of a type or schema version this build does not know about.
+    Unknown(UnknownMedia),
 }
 
 impl Default for Media {
@@ -24,3 +34,278 @@ impl Default for Media {
         Self::Statement(MicroPost::default())
     }
 }
+
+/// Type discriminator and schema version of a feed entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaHeader {
+    #[serde(rename = "type")]
+    pub media_type: String,
+
+    pub version: u32,
+}
+
+/// Feed entry kept as decoded so that rewriting the feed does not lose it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownMedia {
+    pub header: MediaHeader,
+
+    raw: RawNode,
+}
+
+/// Node decoded without schema, in the codec it was read with.
+#[derive(Debug, Clone, PartialEq)]
+enum RawNode {
+    Json(serde_json::Value),
+    Cbor(serde_cbor::Value),
+}
+
+impl RawNode {
+    fn decode<T>(&self) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Self::Json(value) => serde_json::from_value(value.clone()).map_err(|e| e.to_string()),
+            Self::Cbor(value) => {
+                serde_cbor::value::from_value(value.clone()).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Serialize for RawNode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Json(value) => value.serialize(serializer),
+            Self::Cbor(value) => value.serialize(serializer),
+        }
+    }
+}
+
+/// Type discriminators of the media this build can decode.
+const KNOWN_TYPES: [&str; 4] = ["statement", "blog", "video", "clip"];
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TaggedMediaRef<'a> {
+    Statement(&'a MicroPost),
+    Blog(&'a FullPost),
+    Video(&'a VideoMetadata),
+    Clip(&'a ClipNode),
+}
+
+#[derive(Serialize)]
+struct MediaNodeRef<'a> {
+    version: u32,
+
+    #[serde(flatten)]
+    media: TaggedMediaRef<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TaggedMedia {
+    Statement(MicroPost),
+    Blog(FullPost),
+    Video(VideoMetadata),
+    Clip(ClipNode),
+}
+
+/// Entries created before the type discriminator was introduced.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyMedia {
+    Statement(MicroPost),
+    Blog(FullPost),
+    Video(VideoMetadata),
+}
+
+impl Serialize for Media {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let media = match self {
+            Self::Statement(post) => TaggedMediaRef::Statement(post),
+            Self::Blog(post) => TaggedMediaRef::Blog(post),
+            Self::Video(video) => TaggedMediaRef::Video(video),
+            Self::Clip(clip) => TaggedMediaRef::Clip(clip),
+            Self::Unknown(media) => return media.raw.serialize(serializer),
+        };
+
+        let node = MediaNodeRef {
+            version: SCHEMA_VERSION,
+            media,
+        };
+
+        node.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Media {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = if deserializer.is_human_readable() {
+            RawNode::Json(serde_json::Value::deserialize(deserializer)?)
+        } else {
+            RawNode::Cbor(serde_cbor::Value::deserialize(deserializer)?)
+        };
+
+        Self::from_raw(raw).map_err(de::Error::custom)
+    }
+}
+
+impl Media {
+    /// Unknown types and versions newer than this build are kept as is.
+    fn from_raw(raw: RawNode) -> Result<Self, String> {
+        let header = match raw.decode::<MediaHeader>() {
+            Ok(header) => header,
+            Err(_) => {
+                let media = match raw.decode::<LegacyMedia>()? {
+                    LegacyMedia::Statement(post) => Self::Statement(post),
+                    LegacyMedia::Blog(post) => Self::Blog(post),
+                    LegacyMedia::Video(video) => Self::Video(video),
+                };
+
+                return Ok(media);
+            }
+        };
+
+        if header.version > SCHEMA_VERSION || !KNOWN_TYPES.contains(&header.media_type.as_str()) {
+            return Ok(Self::Unknown(UnknownMedia { header, raw }));
+        }
+
+        let media = match raw.decode::<TaggedMedia>() {
+            Ok(TaggedMedia::Statement(post)) => Self::Statement(post),
+            Ok(TaggedMedia::Blog(post)) => Self::Blog(post),
+            Ok(TaggedMedia::Video(video)) => Self::Video(video),
+            Ok(TaggedMedia::Clip(clip)) => Self::Clip(clip),
+            Err(e) => return Err(format!("Malformed {} media {}", header.media_type, e)),
+        };
+
+        Ok(media)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::codec::Codec;
+
+    use std::convert::TryFrom;
+
+    use cid::Cid;
+
+    const CODECS: [Codec; 2] = [Codec::DagJson, Codec::DagCbor];
+
+    fn cid() -> Cid {
+        Cid::try_from("bafyreigdmqpykrgxyaxtlafqpqhzrb7qy2rh75nldvfd4tucqmqqme5yye")
+            .expect("Invalid Cid")
+    }
+
+    /// Entry of a type added after this build.
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Podcast {
+        #[serde(rename = "type")]
+        media_type: String,
+        version: u32,
+        episode: IPLDLink,
+        title: String,
+    }
+
+    #[test]
+    fn legacy_entries_decode() {
+        for codec in CODECS.iter() {
+            let post = MicroPost {
+                timestamp: 1,
+                content: "Hello".into(),
+            };
+
+            let data = codec.encode(&post).expect("Encode failed");
+
+            match codec.decode(&data).expect("Decode failed") {
+                Media::Statement(decoded) => assert!(decoded == post),
+                _ => panic!("Legacy statement not decoded as statement"),
+            }
+
+            let post = FullPost::create("Title".into(), cid(), cid());
+
+            let data = codec.encode(&post).expect("Encode failed");
+
+            match codec.decode(&data).expect("Decode failed") {
+                Media::Blog(decoded) => assert!(decoded == post),
+                _ => panic!("Legacy blog not decoded as blog"),
+            }
+        }
+    }
+
+    #[test]
+    fn tagged_entries_round_trip() {
+        for codec in CODECS.iter() {
+            let media = Media::Video(VideoMetadata::create("Title".into(), 3.5, cid(), cid()));
+
+            let data = codec.encode(&media).expect("Encode failed");
+
+            let header: MediaHeader = codec.decode(&data).expect("Missing header");
+
+            assert_eq!(header.media_type, "video");
+            assert_eq!(header.version, SCHEMA_VERSION);
+
+            let decoded: Media = codec.decode(&data).expect("Decode failed");
+
+            assert!(decoded == media);
+        }
+    }
+
+    #[test]
+    fn unknown_entries_are_kept() {
+        for codec in CODECS.iter() {
+            let podcast = Podcast {
+                media_type: "podcast".into(),
+                version: 1,
+                episode: cid().into(),
+                title: "Episode 1".into(),
+            };
+
+            let data = codec.encode(&podcast).expect("Encode failed");
+
+            let media: Media = codec.decode(&data).expect("Decode failed");
+
+            match &media {
+                Media::Unknown(unknown) => assert_eq!(unknown.header.media_type, "podcast"),
+                _ => panic!("Unknown type not decoded as unknown"),
+            }
+
+            let data = codec.encode(&media).expect("Encode failed");
+
+            let decoded: Podcast = codec.decode(&data).expect("Body lost");
+
+            assert_eq!(decoded, podcast);
+        }
+    }
+
+    #[test]
+    fn future_versions_are_unknown() {
+        for codec in CODECS.iter() {
+            let video = VideoMetadata::create("Title".into(), 3.5, cid(), cid());
+
+            let node = MediaNodeRef {
+                version: SCHEMA_VERSION + 1,
+                media: TaggedMediaRef::Video(&video),
+            };
+
+            let data = codec.encode(&node).expect("Encode failed");
+
+            match codec.decode(&data).expect("Decode failed") {
+                Media::Unknown(unknown) => assert_eq!(unknown.header.version, SCHEMA_VERSION + 1),
+                _ => panic!("Future version decoded as current"),
+            }
+        }
+    }
+}
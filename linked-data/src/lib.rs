@@ -1,16 +1,27 @@
 pub mod beacon;
 pub mod blog;
 pub mod chat;
+pub mod codec;
 pub mod comments;
+pub mod dash;
+mod error;
 pub mod feed;
 pub mod moderation;
 pub mod signature;
 pub mod video;
 
+pub use error::Error;
+
 use std::convert::TryFrom;
+use std::fmt;
 
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use serde_bytes::Bytes;
+use serde_cbor::tags::Tagged;
+
 use cid::Cid;
 
 /// Ethereum address
@@ -19,11 +30,10 @@ pub type Address = [u8; 20];
 /// GossipSub Peer ID
 pub type PeerId = String;
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+/// Link to another node.
+/// Serialized as {"/": "<cid>"} in DAG-JSON and as a tag 42 binary CID in DAG-CBOR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
 pub struct IPLDLink {
-    #[serde(rename = "/")]
-    #[serde(serialize_with = "serialize_cid")]
-    #[serde(deserialize_with = "deserialize_cid")]
     pub link: Cid,
 }
 
@@ -33,20 +43,83 @@ impl From<Cid> for IPLDLink {
     }
 }
 
-fn serialize_cid<S>(cid: &Cid, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_str(&cid.to_string())
+/// CBOR tag reserved for CIDs.
+const CBOR_CID_TAG: u64 = 42;
+
+impl Serialize for IPLDLink {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry("/", &self.link.to_string())?;
+            return map.end();
+        }
+
+        // Binary CIDs are prefixed with the multibase identity prefix.
+        let mut bytes = vec![0u8];
+        bytes.extend_from_slice(&self.link.to_bytes());
+
+        Tagged::new(Some(CBOR_CID_TAG), Bytes::new(&bytes)).serialize(serializer)
+    }
 }
 
-fn deserialize_cid<'de, D>(deserializer: D) -> Result<Cid, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let cid_str: &str = Deserialize::deserialize(deserializer)?;
+impl<'de> Deserialize<'de> for IPLDLink {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(IPLDLinkVisitor)
+    }
+}
+
+struct IPLDLinkVisitor;
+
+impl<'de> Visitor<'de> for IPLDLinkVisitor {
+    type Value = IPLDLink;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an IPLD link")
+    }
+
+    /// DAG-JSON link.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let key: String = match map.next_key()? {
+            Some(key) => key,
+            None => return Err(de::Error::missing_field("/")),
+        };
+
+        if key != "/" {
+            return Err(de::Error::unknown_field(&key, &["/"]));
+        }
 
-    let cid = Cid::try_from(cid_str).expect("Deserialize string to CID failed");
+        let cid_str: String = map.next_value()?;
+
+        let cid = Cid::try_from(cid_str).map_err(de::Error::custom)?;
+
+        Ok(cid.into())
+    }
 
-    Ok(cid)
+    /// DAG-CBOR tagged link.
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(self)
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let bytes = bytes.strip_prefix(&[0]).unwrap_or(bytes);
+
+        let cid = Cid::try_from(bytes).map_err(de::Error::custom)?;
+
+        Ok(cid.into())
+    }
 }
@@ -1,4 +1,4 @@
-use crate::Address;
+use crate::{Address, Error};
 
 use serde::{Deserialize, Serialize};
 
@@ -22,15 +22,13 @@ impl<T> SignedMessage<T>
 where
     T: Serialize,
 {
-    pub fn verify(&self) -> bool {
+    /// Verify that the data was signed by this address.
+    pub fn verify(&self) -> Result<(), Error> {
         if self.signature.len() != 65 {
-            return false;
+            return Err(Error::SignatureLength(self.signature.len()));
         }
 
-        let message = match serde_json::to_vec(&self.data) {
-            Ok(msg) => msg,
-            Err(_) => return false,
-        };
+        let message = serde_json::to_vec(&self.data)?;
 
         let mut eth_message =
             format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
@@ -38,31 +36,23 @@ where
 
         let hash = keccak256(&eth_message);
 
-        let msg = match Message::parse_slice(&hash) {
-            Ok(msg) => msg,
-            Err(_) => return false,
-        };
+        let msg = Message::parse_slice(&hash)?;
 
-        let sig = match Signature::parse_standard_slice(&self.signature[0..64]) {
-            Ok(sig) => sig,
-            Err(_) => return false,
-        };
+        let sig = Signature::parse_standard_slice(&self.signature[0..64])?;
 
-        let rec_id = match RecoveryId::parse_rpc(self.signature[64]) {
-            Ok(id) => id,
-            Err(_) => return false,
-        };
+        let rec_id = RecoveryId::parse_rpc(self.signature[64])?;
 
-        let public_key = match recover(&msg, &sig, &rec_id) {
-            Ok(data) => data.serialize(),
-            Err(_) => return false,
-        };
+        let public_key = recover(&msg, &sig, &rec_id)?.serialize();
 
         // The public key returned is 65 bytes long, that is because it is prefixed by `0x04` to indicate an uncompressed public key.
         let hash = keccak256(&public_key[1..]);
 
         // The public address is defined as the low 20 bytes of the keccak hash of the public key.
-        hash[12..] == self.address
+        if hash[12..] != self.address {
+            return Err(Error::AddressMismatch);
+        }
+
+        Ok(())
     }
 }
 
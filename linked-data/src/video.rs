@@ -5,6 +5,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
+use cid::multihash::Multihash;
 use cid::Cid;
 
 /// Metadata for video thumbnail and playback.
@@ -73,12 +74,58 @@ impl VideoMetadata {
     }
 }
 
+/// Time range of an existing video.
+/// Recursive pin.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct ClipNode {
+    /// Timestamp at the time of publication in Unix time.
+    pub timestamp: u64,
+
+    /// Link to TimecodeNode of the original video.
+    pub video: IPLDLink,
+
+    /// Start timecode in seconds.
+    pub start: f64,
+
+    /// End timecode in seconds.
+    pub end: f64,
+
+    /// Title of this clip.
+    pub title: Option<String>,
+}
+
+impl ClipNode {
+    pub fn create(title: Option<String>, video: Cid, start: f64, end: f64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+            .as_secs();
+
+        Self {
+            title,
+            video: video.into(),
+            start,
+            end,
+            timestamp,
+        }
+    }
+
+    /// Duration in seconds.
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
 /// Root CID.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TimecodeNode {
     /// ../time/..
     #[serde(rename = "time")]
     pub timecode: IPLDLink,
+
+    /// Exact duration in seconds, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
 }
 
 /// Links all hour nodes for multiple hours of video.
@@ -106,7 +153,7 @@ pub struct MinuteNode {
 }
 
 /// Links video and chat nodes.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SecondNode {
     /// ../time/hour/3/minute/59/second/48/video/..
     #[serde(rename = "video")]
@@ -117,6 +164,25 @@ pub struct SecondNode {
     pub links_to_chat: Vec<IPLDLink>,
 }
 
+/// Raw binary codec.
+const RAW: u64 = 0x55;
+
+/// Identity multihash, the digest is the data itself.
+const IDENTITY: u64 = 0x00;
+
+/// Stands in the timecode structure for a node that could not be added to IPFS.
+/// Links to the empty identity-hashed block, which resolves without being stored.
+pub fn gap_link() -> IPLDLink {
+    let hash = Multihash::wrap(IDENTITY, &[]).expect("Empty Identity Digest");
+
+    Cid::new_v1(RAW, hash).into()
+}
+
+/// True if this link stands for a node that could not be added.
+pub fn is_gap(link: &IPLDLink) -> bool {
+    *link == gap_link()
+}
+
 /// Links all stream variants, allowing selection of video quality. Also link to the previous video node.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VideoNode {
@@ -131,6 +197,10 @@ pub struct VideoNode {
     /// ../time/hour/0/minute/36/second/12/video/previous/..
     #[serde(rename = "previous")]
     pub previous: Option<IPLDLink>,
+
+    /// Duration of the segments in seconds, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
 }
 
 /// Contains initialization data for video stream.
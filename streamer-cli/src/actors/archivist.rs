@@ -1,25 +1,65 @@
+use crate::backend::{DagStore, Ipfs};
+use crate::utils::config::ArchiveConfig;
 use crate::utils::dag_nodes::ipfs_dag_put_node_async;
+use crate::utils::feed::add_content_to_feed;
+use crate::utils::journal::{Journal, JournalEntry};
+use crate::utils::outbox::Outbox;
 
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::collections::VecDeque;
+use std::time::Instant;
 
-use ipfs_api::IpfsClient;
+use tokio::sync::mpsc::Receiver;
 
-use linked_data::video::{DayNode, HourNode, MinuteNode, SecondNode, TimecodeNode};
+use linked_data::codec::Codec;
+use linked_data::feed::Media;
+use linked_data::video::{
+    gap_link, DayNode, HourNode, MinuteNode, SecondNode, TimecodeNode, VideoMetadata,
+};
+use linked_data::IPLDLink;
+
+use serde::Serialize;
 
 use cid::Cid;
 
+/// Segments waiting for a second of their own, above which the oldest is dropped.
+const MAX_UNLINKED: usize = 4;
+
 pub enum Archive {
     Chat(Cid),
-    Video(Cid),
+    Video((Cid, f64)),
     Finalize,
 }
 
+/// Node of the timecode structure waiting in the outbox.
+/// Seconds are kept to be saved beside the journal.
+enum Level {
+    Second(SecondNode),
+    Minute,
+    Hour,
+    Day,
+    Timecode,
+}
+
 pub struct Archivist {
-    ipfs: IpfsClient,
+    ipfs: Ipfs,
+    outbox: Outbox<Level>,
+
+    archive_rx: Receiver<Archive>,
+
+    config: ArchiveConfig,
+    codec: Codec,
+
+    journal: Option<Journal>,
+
+    /// SecondNodes of the latest segment and when it was received.
+    video_chat_buffer: Vec<SecondNode>,
+    buffer_start: Instant,
 
-    archive_rx: UnboundedReceiver<Archive>,
+    /// Duration archived so far in seconds.
+    time: f64,
 
-    video_chat_buffer: Option<SecondNode>,
+    /// Segments not linked by any second yet, oldest first.
+    unlinked: VecDeque<Cid>,
 
     minute_node: MinuteNode,
     hour_node: HourNode,
@@ -27,13 +67,30 @@ pub struct Archivist {
 }
 
 impl Archivist {
-    pub fn new(ipfs: IpfsClient, archive_rx: UnboundedReceiver<Archive>) -> Self {
+    pub fn new(
+        ipfs: Ipfs,
+        archive_rx: Receiver<Archive>,
+        config: ArchiveConfig,
+        codec: Codec,
+        journal: Option<Journal>,
+    ) -> Self {
         Self {
+            outbox: Outbox::new(ipfs.clone(), codec),
             ipfs,
 
             archive_rx,
 
-            video_chat_buffer: None,
+            config,
+            codec,
+
+            journal,
+
+            video_chat_buffer: Vec::with_capacity(4),
+            buffer_start: Instant::now(),
+
+            time: 0.0,
+
+            unlinked: VecDeque::with_capacity(MAX_UNLINKED + 1),
 
             minute_node: MinuteNode {
                 links_to_seconds: Vec::with_capacity(60),
@@ -49,23 +106,80 @@ impl Archivist {
         }
     }
 
+    /// Rebuild the nodes of a previous archive from its journal then queue its pending seconds.
+    pub async fn resume(&mut self, entries: Vec<JournalEntry>, pending: Vec<SecondNode>) {
+        for entry in entries {
+            match entry {
+                JournalEntry::Second(link) => self.minute_node.links_to_seconds.push(link),
+                JournalEntry::Minute(link) => {
+                    self.minute_node.links_to_seconds.clear();
+                    self.hour_node.links_to_minutes.push(link);
+                }
+                JournalEntry::Hour(link) => {
+                    self.hour_node.links_to_minutes.clear();
+                    self.day_node.links_to_hours.push(link);
+                }
+            }
+        }
+
+        // Counted before full parents take the links of their children
+        self.time = (self.day_node.links_to_hours.len() * 3600
+            + self.hour_node.links_to_minutes.len() * 60
+            + self.minute_node.links_to_seconds.len()
+            + pending.len()) as f64;
+
+        // Parents filled before being written
+        if self.minute_node.links_to_seconds.len() >= 60 {
+            self.collect_minute();
+        }
+
+        if self.hour_node.links_to_minutes.len() >= 60 {
+            self.collect_hour();
+        }
+
+        self.queue_seconds(pending).await;
+
+        self.write_nodes().await;
+
+        println!(
+            "Archive Resumed At Timecode {}:{}:{}",
+            self.day_node.links_to_hours.len(),
+            self.hour_node.links_to_minutes.len(),
+            self.minute_node.links_to_seconds.len()
+        );
+    }
+
     pub async fn start(&mut self) {
         println!("✅ Archive System Online");
 
-        while let Some(event) = self.archive_rx.recv().await {
+        loop {
+            let event = tokio::select! {
+                event = self.archive_rx.recv() => event,
+                _ = self.outbox.retry_due() => {
+                    self.write_nodes().await;
+                    continue;
+                }
+            };
+
             match event {
-                Archive::Chat(cid) => self.archive_chat_message(cid),
-                Archive::Video(cid) => self.archive_video_segment(cid).await,
-                Archive::Finalize => self.finalize().await,
+                Some(Archive::Chat(cid)) => self.archive_chat_message(cid),
+                Some(Archive::Video((cid, duration))) => {
+                    self.archive_video_segment(cid, duration).await
+                }
+                Some(Archive::Finalize) => self.finalize().await,
+                None => break,
             }
         }
 
         println!("❌ Archive System Offline");
     }
 
-    /// Link chat message to SecondNodes.
+    /// Link chat message to the SecondNode matching the time it was received.
     fn archive_chat_message(&mut self, msg_cid: Cid) {
-        let node = match self.video_chat_buffer.as_mut() {
+        let index = self.buffer_start.elapsed().as_secs() as usize;
+        let index = index.min(self.video_chat_buffer.len().saturating_sub(1));
+
+        let node = match self.video_chat_buffer.get_mut(index) {
             Some(node) => node,
             None => return,
         };
@@ -73,79 +187,233 @@ impl Archivist {
         node.links_to_chat.push(msg_cid.into());
     }
 
-    /// Buffers SecondNodes, waiting for chat messages to be linked.
-    async fn archive_video_segment(&mut self, cid: Cid) {
-        let second_node = SecondNode {
-            link_to_video: cid.into(),
-            links_to_chat: Vec::with_capacity(5),
-        };
+    /// Buffers one SecondNode per second of the segment, waiting for chat messages to be linked.
+    /// A segment ending in the same second it started is linked by the next second instead.
+    async fn archive_video_segment(&mut self, cid: Cid, duration: f64) {
+        let start = self.time.floor();
+        self.time += duration;
+        let count = (self.time.floor() - start) as usize;
+
+        self.unlinked.push_back(cid);
+
+        let mut second_nodes = Vec::with_capacity(count);
+
+        // Waiting segments get one second each, the latest all remaining seconds
+        while second_nodes.len() < count {
+            let cid = match self.unlinked.pop_front() {
+                Some(cid) => cid,
+                None => break,
+            };
+
+            let seconds = if self.unlinked.is_empty() {
+                count - second_nodes.len()
+            } else {
+                1
+            };
+
+            for _ in 0..seconds {
+                second_nodes.push(SecondNode {
+                    link_to_video: cid.into(),
+                    links_to_chat: Vec::with_capacity(5),
+                });
+            }
+        }
 
-        let node = self.video_chat_buffer.take();
+        if self.unlinked.len() > MAX_UNLINKED {
+            self.unlinked.pop_front();
 
-        self.video_chat_buffer = Some(second_node);
+            eprintln!("❗ Archive: segments shorter than a second, one was not linked");
+        }
 
-        let node = match node {
-            Some(node) => node,
-            None => return,
-        };
+        if second_nodes.is_empty() {
+            return;
+        }
+
+        let nodes = std::mem::replace(&mut self.video_chat_buffer, second_nodes);
+        self.buffer_start = Instant::now();
+
+        self.collect_seconds(nodes).await;
+    }
+
+    /// Queue SecondNodes in order then write as many nodes as possible.
+    async fn collect_seconds(&mut self, nodes: Vec<SecondNode>) {
+        self.queue_seconds(nodes).await;
 
-        self.collect_second(node).await;
+        self.write_nodes().await;
+    }
 
-        if self.minute_node.links_to_seconds.len() < 60 {
+    /// Queue and save SecondNodes in order, the oldest given up if the outbox is full.
+    async fn queue_seconds(&mut self, nodes: Vec<SecondNode>) {
+        if nodes.is_empty() {
             return;
         }
 
-        self.collect_minute().await;
+        for node in nodes {
+            match self.outbox.push_back(Level::Second(node.clone()), &node) {
+                Ok(Some(level)) => self.on_written(level, None).await,
+                Ok(None) => {}
+                Err(e) => eprintln!("❗ Archive: {}", e),
+            }
+        }
 
-        if self.hour_node.links_to_minutes.len() < 60 {
-            return;
+        self.write_pending().await;
+    }
+
+    /// Write queued nodes until the outbox is empty or waiting to retry.
+    async fn write_nodes(&mut self) {
+        let mut written = false;
+
+        while let Some((level, cid)) = self.outbox.write_next().await {
+            self.on_written(level, cid).await;
+            written = true;
         }
 
-        self.collect_hour().await;
+        if written {
+            self.write_pending().await;
+        }
     }
 
-    /// Create DAG node containing a link to video segment and all chat messages.
-    /// MinuteNode is then appended with the CID.
-    async fn collect_second(&mut self, node: SecondNode) {
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &node).await {
-            Ok(cid) => cid,
-            Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
+    /// Write all queued nodes, waiting for retries.
+    async fn flush_nodes(&mut self) {
+        let mut written = false;
+
+        while let Some((level, cid)) = self.outbox.write_now().await {
+            self.on_written(level, cid).await;
+            written = true;
+        }
+
+        if written {
+            self.write_pending().await;
+        }
+    }
+
+    /// Link the written node in its parent, a gap marker if it was given up.
+    /// Parent nodes are created as they fill up.
+    async fn on_written(&mut self, level: Level, cid: Option<Cid>) {
+        let link = match (&level, cid) {
+            (_, Some(cid)) => cid.into(),
+            (Level::Day, None) | (Level::Timecode, None) => {
+                eprintln!("❗ Archive: final nodes not written, journal kept to resume later");
                 return;
             }
+            (_, None) => {
+                eprintln!("❗ Archive: node given up, gap marker linked instead");
+                gap_link()
+            }
         };
 
-        self.minute_node.links_to_seconds.push(cid.into());
-    }
+        match level {
+            Level::Second(_) => {
+                self.minute_node.links_to_seconds.push(link);
 
-    /// Create DAG node containing 60 SecondNode links. HourNode is then appended with the CID.
-    async fn collect_minute(&mut self) {
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &self.minute_node).await {
-            Ok(cid) => cid,
-            Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
-                return;
+                self.write_journal(JournalEntry::Second(link)).await;
+
+                if self.minute_node.links_to_seconds.len() >= 60 {
+                    self.collect_minute();
+                }
+            }
+            Level::Minute => {
+                self.hour_node.links_to_minutes.push(link);
+
+                self.write_journal(JournalEntry::Minute(link)).await;
+
+                if self.hour_node.links_to_minutes.len() >= 60 {
+                    self.collect_hour();
+                }
             }
+            Level::Hour => {
+                self.day_node.links_to_hours.push(link);
+
+                self.write_journal(JournalEntry::Hour(link)).await;
+            }
+            Level::Day => self.collect_timecode(link),
+            Level::Timecode => self.pin_timecode(link.link).await,
+        }
+    }
+
+    /// Queue DAG node containing 60 SecondNode links, written before the next seconds.
+    fn collect_minute(&mut self) {
+        let links = std::mem::take(&mut self.minute_node.links_to_seconds);
+
+        self.push_front(
+            Level::Minute,
+            &MinuteNode {
+                links_to_seconds: links,
+            },
+        );
+    }
+
+    /// Queue DAG node containing 60 MinuteNode links, written before the next seconds.
+    fn collect_hour(&mut self) {
+        let links = std::mem::take(&mut self.hour_node.links_to_minutes);
+
+        self.push_front(
+            Level::Hour,
+            &HourNode {
+                links_to_minutes: links,
+            },
+        );
+    }
+
+    /// Queue the root node once the DayNode is written.
+    fn collect_timecode(&mut self, link: IPLDLink) {
+        let stream = TimecodeNode {
+            timecode: link,
+            duration: Some(self.time),
         };
 
-        self.minute_node.links_to_seconds.clear();
+        self.push_front(Level::Timecode, &stream);
+    }
 
-        self.hour_node.links_to_minutes.push(cid.into());
+    fn push_front<T: Serialize>(&mut self, level: Level, node: &T) {
+        if let Err(e) = self.outbox.push_front(level, node) {
+            eprintln!("❗ Archive: {}", e);
+        }
     }
 
-    /// Create DAG node containing 60 MinuteNode links. DayNode is then appended with the CID.
-    async fn collect_hour(&mut self) {
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &self.hour_node).await {
-            Ok(cid) => cid,
-            Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
-                return;
-            }
+    /// Save progress in case the archive is not finalized.
+    async fn write_journal(&mut self, entry: JournalEntry) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
         };
 
-        self.hour_node.links_to_minutes.clear();
+        if let Err(e) = journal.write(&entry).await {
+            eprintln!("❗ Journal: write failed {}", e);
+        }
+    }
+
+    /// Save the queued seconds so that a restart does not lose them.
+    async fn write_pending(&mut self) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
 
-        self.day_node.links_to_hours.push(cid.into());
+        let nodes = self
+            .outbox
+            .keys()
+            .filter_map(|level| match level {
+                Level::Second(node) => Some(node),
+                _ => None,
+            })
+            .collect();
+
+        if let Err(e) = journal.write_pending(nodes).await {
+            eprintln!("❗ Journal: outbox write failed {}", e);
+        }
+    }
+
+    /// Delete the journal since the archive is complete.
+    async fn remove_journal(&mut self) {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        if let Err(e) = journal.remove().await {
+            eprintln!("❗ Journal: remove failed {}", e);
+        }
     }
 
     /// Create all remaining DAG nodes then pin and print the final CID.
@@ -154,36 +422,74 @@ impl Archivist {
 
         println!("Collecting Nodes...");
 
-        if let Some(node) = self.video_chat_buffer.take() {
-            self.collect_second(node).await;
+        let mut nodes = std::mem::take(&mut self.video_chat_buffer);
+
+        // Segments still waiting get a second each
+        for cid in self.unlinked.drain(..) {
+            nodes.push(SecondNode {
+                link_to_video: cid.into(),
+                links_to_chat: Vec::new(),
+            });
         }
 
+        self.collect_seconds(nodes).await;
+        self.flush_nodes().await;
+
         if !self.minute_node.links_to_seconds.is_empty() {
-            self.collect_minute().await;
+            self.collect_minute();
+            self.flush_nodes().await;
         }
 
         if !self.hour_node.links_to_minutes.is_empty() {
-            self.collect_hour().await;
+            self.collect_hour();
+            self.flush_nodes().await;
         }
 
         if self.day_node.links_to_hours.is_empty() {
             println!("0 Nodes Found");
+            self.remove_journal().await;
             return;
         }
 
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &self.day_node).await {
-            Ok(cid) => cid,
+        if let Err(e) = self.outbox.push_front(Level::Day, &self.day_node) {
+            eprintln!("❗ Archive: {}", e);
+            return;
+        }
+
+        self.flush_nodes().await;
+    }
+
+    /// Pin the final node then publish the video.
+    async fn pin_timecode(&mut self, cid: Cid) {
+        println!("Pinning Nodes...");
+
+        match self.ipfs.pin_add(&cid, true).await {
+            Ok(_) => println!("Final Timecode-addressable Node => {}", &cid.to_string()),
             Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
+                eprintln!("❗ IPFS: pin add failed {}", e);
                 return;
             }
-        };
+        }
 
-        let stream = TimecodeNode {
-            timecode: cid.into(),
+        self.remove_journal().await;
+
+        self.publish_video(cid).await;
+    }
+
+    /// Add the video to the content feed if a title and thumbnail were provided.
+    async fn publish_video(&self, video: Cid) {
+        let (title, image) = match (self.config.title.as_ref(), self.config.image.as_ref()) {
+            (Some(title), Some(image)) => (title.clone(), *image),
+            (None, None) => return,
+            _ => {
+                eprintln!("❗ Archive: title and image are both required to publish the video");
+                return;
+            }
         };
 
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &stream).await {
+        let metadata = Media::Video(VideoMetadata::create(title, self.time, image, video));
+
+        let cid = match ipfs_dag_put_node_async(&self.ipfs, &metadata, self.codec).await {
             Ok(cid) => cid,
             Err(e) => {
                 eprintln!("❗ IPFS: dag put failed {}", e);
@@ -191,11 +497,11 @@ impl Archivist {
             }
         };
 
-        println!("Pinning Nodes...");
+        println!("New Post CID => {}", &cid.to_string());
 
-        match self.ipfs.pin_add(&cid.to_string(), true).await {
-            Ok(_) => println!("Final Timecode-addressable Node => {}", &cid.to_string()),
-            Err(e) => eprintln!("❗ IPFS: pin add failed {}", e),
+        match add_content_to_feed(&self.ipfs, cid, self.codec).await {
+            Ok(index) => println!("✅ Video Post Added In Content Feed At Index {}", index),
+            Err(e) => eprintln!("❗ {}", e),
         }
     }
 }
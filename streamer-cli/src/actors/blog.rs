@@ -1,4 +1,5 @@
 use crate::actors::VideoData;
+use crate::backend::Ipfs;
 use crate::utils::dag_nodes::ipfs_dag_put_node_async;
 
 use std::collections::HashMap;
@@ -7,18 +8,16 @@ use std::path::PathBuf;
 
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use ipfs_api::IpfsClient;
-
 use linked_data::IPLDLink;
 
 use cid::Cid;
 
 pub struct BlogAggregator {
-    ipfs: IpfsClient,
+    ipfs: Ipfs,
 }
 
 impl BlogAggregator {
-    pub fn new(ipfs: IpfsClient) -> Self {
+    pub fn new(ipfs: Ipfs) -> Self {
         Self { ipfs }
     }
 
@@ -1,23 +1,29 @@
 use crate::actors::archivist::Archive;
+use crate::backend::{Ipfs, Naming, PubSub, PubSubMessage};
 use crate::cli::moderation::BANS_KEY;
+use crate::error::{Error, Policy};
 use crate::utils::config::ChatConfig;
-use crate::utils::dag_nodes::{ipfs_dag_get_node_async, ipfs_dag_put_node_async, update_ipns};
+use crate::utils::dag_nodes::{ipfs_dag_get_node_async, update_ipns};
+use crate::utils::outbox::Outbox;
 
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::Sender;
 use tokio_stream::StreamExt;
 
-use ipfs_api::response::PubsubSubResponse;
-use ipfs_api::IpfsClient;
-
 use linked_data::chat::{ChatId, Message, MessageType, UnsignedMessage};
+use linked_data::codec::Codec;
 use linked_data::moderation::{Ban, Bans, ChatModerationCache, Moderators};
 use linked_data::signature::SignedMessage;
 use linked_data::PeerId;
 
 pub struct ChatAggregator {
-    ipfs: IpfsClient,
+    ipfs: Ipfs,
+
+    /// Retries messages in the order they were received.
+    outbox: Outbox<()>,
 
-    archive_tx: UnboundedSender<Archive>,
+    archive_tx: Sender<Archive>,
+
+    codec: Codec,
 
     mod_db: ChatModerationCache,
 
@@ -32,35 +38,29 @@ pub struct ChatAggregator {
 
 impl ChatAggregator {
     pub async fn new(
-        ipfs: IpfsClient,
-        archive_tx: UnboundedSender<Archive>,
+        ipfs: Ipfs,
+        archive_tx: Sender<Archive>,
         config: ChatConfig,
-    ) -> Self {
+        codec: Codec,
+    ) -> Result<Self, Error> {
         let ChatConfig { topic, mods, bans } = config;
 
-        let res = ipfs
-            .name_resolve(Some(&mods), false, false)
-            .await
-            .expect("Invalid Mods Link");
+        let cid = ipfs.name_resolve(&mods).await?;
 
-        let mods = ipfs_dag_get_node_async(&ipfs, &res.path)
-            .await
-            .expect("Invalid Moderators Node");
+        let mods = ipfs_dag_get_node_async(&ipfs, &cid.to_string()).await?;
 
-        let res = ipfs
-            .name_resolve(Some(&bans), false, false)
-            .await
-            .expect("Invalid Mods Link");
+        let cid = ipfs.name_resolve(&bans).await?;
 
-        let bans = ipfs_dag_get_node_async(&ipfs, &res.path)
-            .await
-            .expect("Invalid Moderators Node");
+        let bans = ipfs_dag_get_node_async(&ipfs, &cid.to_string()).await?;
 
-        Self {
+        Ok(Self {
+            outbox: Outbox::new(ipfs.clone(), codec),
             ipfs,
 
             archive_tx,
 
+            codec,
+
             mod_db: ChatModerationCache::new(100, 0),
 
             topic,
@@ -70,15 +70,28 @@ impl ChatAggregator {
             new_ban_count: 0,
 
             mods,
-        }
+        })
     }
 
     pub async fn start(&mut self) {
-        let mut stream = self.ipfs.pubsub_sub(&self.topic, true);
+        let mut stream = self.ipfs.pubsub_sub(&self.topic);
 
         println!("✅ Chat System Online");
 
-        while let Some(result) = stream.next().await {
+        loop {
+            let result = tokio::select! {
+                result = stream.next() => result,
+                _ = self.outbox.retry_due() => {
+                    self.archive_messages().await;
+                    continue;
+                }
+            };
+
+            let result = match result {
+                Some(result) => result,
+                None => break,
+            };
+
             if self.archive_tx.is_closed() {
                 //Hacky way to shutdown
                 break;
@@ -86,10 +99,7 @@ impl ChatAggregator {
 
             match result {
                 Ok(response) => self.on_pubsub_message(response).await,
-                Err(error) => {
-                    eprintln!("{}", error);
-                    continue;
-                }
+                Err(error) => self.on_error(error.into()),
             }
         }
 
@@ -99,7 +109,7 @@ impl ChatAggregator {
                 self.new_ban_count
             );
 
-            if let Err(e) = update_ipns(&self.ipfs, &BANS_KEY, &self.bans).await {
+            if let Err(e) = update_ipns(&self.ipfs, &BANS_KEY, &self.bans, self.codec).await {
                 eprintln!("❗ IPNS Update Failed. {}", e);
             }
         }
@@ -107,7 +117,7 @@ impl ChatAggregator {
         println!("❌ Chat System Offline");
     }
 
-    async fn on_pubsub_message(&mut self, msg: PubsubSubResponse) {
+    async fn on_pubsub_message(&mut self, msg: PubSubMessage) {
         let peer = match msg.from {
             Some(from) => from,
             None => return,
@@ -155,7 +165,8 @@ impl ChatAggregator {
             return;
         }
 
-        if !sign_msg.verify() {
+        if let Err(e) = sign_msg.verify() {
+            eprintln!("❗ Signed Message Verification Failed. {}", e);
             self.mod_db.ban_peer(&peer);
             return;
         }
@@ -177,23 +188,47 @@ impl ChatAggregator {
     }
 
     async fn mint_and_archive(&mut self, msg: UnsignedMessage) {
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, &msg).await {
-            Ok(cid) => cid,
-            Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
-                return;
-            }
-        };
+        if let Err(e) = self.outbox.push_back((), &msg) {
+            eprintln!("❗ Chat: {}", e);
+        }
+
+        self.archive_messages().await
+    }
 
-        let msg = Archive::Chat(cid);
+    /// Archive queued messages until the outbox is empty or waiting to retry.
+    /// Given up messages are left out of the archive.
+    async fn archive_messages(&mut self) {
+        while let Some((_, cid)) = self.outbox.write_next().await {
+            let cid = match cid {
+                Some(cid) => cid,
+                None => {
+                    eprintln!("❗ Chat: message given up");
+                    continue;
+                }
+            };
 
-        if let Err(error) = self.archive_tx.send(msg) {
-            eprintln!("❗ Archive receiver hung up. {}", error);
+            let msg = Archive::Chat(cid);
+
+            if self.archive_tx.send(msg).await.is_err() {
+                return self.on_error(Error::ChannelClosed("Archive"));
+            }
+        }
+    }
+
+    /// Messages stay queued on failure. Chat stops with the next message once the archive hung up.
+    fn on_error(&self, error: Error) {
+        match error.policy() {
+            Policy::Retry => eprintln!("❗ Chat: {}. Retrying on next message", error),
+            Policy::Skip => eprintln!("❗ Chat: {}. Skipped", error),
+            Policy::Shutdown => eprintln!("❗ Chat: {}. Shutting down", error),
         }
     }
 
     fn update_bans(&mut self, peer: &str, ban: Ban) {
-        let address = self.mod_db.get_address(peer).unwrap();
+        let address = match self.mod_db.get_address(peer) {
+            Some(addrs) => addrs,
+            None => return,
+        };
 
         if !self.mods.mods.contains(address) {
             return;
@@ -0,0 +1,368 @@
+use crate::utils::config::EncoderConfig;
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::signal::ctrl_c;
+use tokio::time::sleep;
+
+/// Delay before restarting after a failure, doubled each time up to the maximum.
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+pub enum EncoderInput {
+    /// Listen for a live stream, restarting after each disconnection or failure until CTRL+C.
+    Live,
+
+    /// Process a file once.
+    File(PathBuf),
+}
+
+pub struct Encoder {
+    config: EncoderConfig,
+    input: EncoderInput,
+
+    /// Ingest server URL segments are sent to.
+    output: String,
+}
+
+impl Encoder {
+    pub fn new(
+        config: EncoderConfig,
+        input: EncoderInput,
+        addr: SocketAddr,
+        stream_key: Option<String>,
+    ) -> Self {
+        let output = match stream_key {
+            Some(key) => format!("http://{}/{}", addr, key),
+            None => format!("http://{}", addr),
+        };
+
+        Self {
+            config,
+            input,
+            output,
+        }
+    }
+
+    pub async fn start(&mut self) {
+        if self.config.renditions.is_empty() {
+            eprintln!("❗ Encoder: no renditions configured");
+            return;
+        }
+
+        println!("✅ Encoder System Online");
+
+        let mut backoff = BACKOFF_MIN;
+
+        loop {
+            let failed = match Command::new(&self.config.command)
+                .args(self.arguments())
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()
+            {
+                Ok(mut child) => {
+                    let status = tokio::select! {
+                        status = child.wait() => status,
+                        res = ctrl_c() => {
+                            res.expect("Failed to install CTRL+C signal handler");
+                            break;
+                        }
+                    };
+
+                    match (status, &self.input) {
+                        // Streamer disconnected, wait for the next one.
+                        (Ok(status), EncoderInput::Live) if status.success() => {
+                            println!("Encoder: Restarting...");
+                            false
+                        }
+                        (Ok(status), EncoderInput::File(_)) if status.success() => break,
+                        (Ok(status), _) => {
+                            eprintln!("❗ Encoder: exited with {}", status);
+                            true
+                        }
+                        (Err(e), _) => {
+                            eprintln!("❗ Encoder: {}", e);
+                            true
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("❗ Encoder: {} failed to start {}", self.config.command, e);
+                    true
+                }
+            };
+
+            if !failed {
+                backoff = BACKOFF_MIN;
+                continue;
+            }
+
+            // Files are processed once, live input is listened for until CTRL+C
+            if let EncoderInput::File(_) = self.input {
+                break;
+            }
+
+            println!("Encoder: Restarting in {} seconds...", backoff.as_secs());
+
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                res = ctrl_c() => {
+                    res.expect("Failed to install CTRL+C signal handler");
+                    break;
+                }
+            }
+
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+
+        println!("❌ Encoder System Offline");
+    }
+
+    /// Arguments producing HLS with fmp4 segments, one folder per rendition and a standalone audio track.
+    pub fn arguments(&self) -> Vec<String> {
+        let EncoderConfig {
+            segment_length,
+            renditions,
+            live_input,
+            ..
+        } = &self.config;
+
+        let mut args: Vec<String> = Vec::with_capacity(64);
+
+        let preset = match &self.input {
+            EncoderInput::Live => {
+                args.extend(
+                    [
+                        "-listen",
+                        "1",
+                        "-rtmp_live",
+                        "live",
+                        "-rtmp_buffer",
+                        "8000",
+                        "-i",
+                        live_input.as_str(),
+                    ]
+                    .iter()
+                    .map(|s| s.to_string()),
+                );
+
+                "ultrafast"
+            }
+            EncoderInput::File(path) => {
+                args.push("-i".into());
+                args.push(path.to_string_lossy().into_owned());
+
+                "fast"
+            }
+        };
+
+        let mut filter = format!("[0:v]split={}", renditions.len());
+
+        for i in 0..renditions.len() {
+            let _ = write!(filter, "[in{}]", i);
+        }
+
+        for (i, rendition) in renditions.iter().enumerate() {
+            let _ = write!(
+                filter,
+                "; [in{}]fps={},scale=w={}:h={}[{}]",
+                i, rendition.fps, rendition.width, rendition.height, rendition.name
+            );
+        }
+
+        args.push("-filter_complex".into());
+        args.push(filter);
+
+        args.push("-preset".into());
+        args.push(preset.into());
+
+        let mut stream_map = String::new();
+
+        for (i, rendition) in renditions.iter().enumerate() {
+            // Key frames at segment boundaries
+            let frames = rendition.fps * segment_length;
+
+            args.push("-map".into());
+            args.push(format!("[{}]", rendition.name));
+            args.push(format!("-c:v:{}", i));
+            args.push(rendition.codec.clone());
+            args.push(format!("-b:v:{}", i));
+            args.push(format!("{}k", rendition.bitrate));
+            args.push(format!("-rc-lookahead:{}", i));
+            args.push(frames.to_string());
+            args.push(format!("-g:{}", i));
+            args.push((frames * 2).to_string());
+            args.push(format!("-keyint_min:{}", i));
+            args.push(frames.to_string());
+            args.push(format!("-force_key_frames:{}", i));
+            args.push(format!("expr:eq(mod(n,{}),0)", frames));
+
+            let _ = write!(stream_map, "v:{},name:{} ", i, rendition.name);
+        }
+
+        stream_map.push_str("a:0,name:audio");
+
+        let segment_length = segment_length.to_string();
+
+        args.extend(
+            [
+                "-map",
+                "a:0",
+                "-c:a:0",
+                "copy",
+                "-f",
+                "hls",
+                "-var_stream_map",
+                stream_map.as_str(),
+                "-hls_init_time",
+                segment_length.as_str(),
+                "-hls_time",
+                segment_length.as_str(),
+                "-hls_flags",
+                "independent_segments",
+                "-master_pl_name",
+                "master.m3u8",
+                "-hls_segment_type",
+                "fmp4",
+                "-hls_segment_filename",
+                format!("{}/%v/%d.m4s", self.output).as_str(),
+                "-http_persistent",
+                "0",
+                "-ignore_io_errors",
+                "1",
+                "-method",
+                "PUT",
+                format!("{}/%v/index.m3u8", self.output).as_str(),
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        );
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::config::Rendition;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::time::{timeout, Instant};
+
+    const OUTPUT: &str = "http://127.0.0.1:2526/key";
+
+    fn encoder(command: &str, input: EncoderInput) -> Encoder {
+        let config = EncoderConfig {
+            command: command.to_owned(),
+            live_input: "rtmp://localhost:2525".to_owned(),
+            segment_length: 4,
+            renditions: vec![Rendition {
+                name: "720p30".to_owned(),
+                width: 1280,
+                height: 720,
+                fps: 30,
+                bitrate: 3000,
+                codec: "libx264".to_owned(),
+            }],
+        };
+
+        let addr = "127.0.0.1:2526".parse().expect("Invalid address");
+
+        Encoder::new(config, input, addr, Some("key".to_owned()))
+    }
+
+    #[test]
+    fn live_arguments() {
+        let args = encoder("ffmpeg", EncoderInput::Live).arguments();
+
+        let input = [
+            "-listen",
+            "1",
+            "-rtmp_live",
+            "live",
+            "-rtmp_buffer",
+            "8000",
+            "-i",
+            "rtmp://localhost:2525",
+        ];
+
+        assert_eq!(args[..input.len()], input);
+
+        let filter = "[0:v]split=1[in0]; [in0]fps=30,scale=w=1280:h=720[720p30]";
+
+        assert!(args.iter().any(|arg| arg == filter));
+        assert!(args.iter().any(|arg| arg == "expr:eq(mod(n,120),0)"));
+        assert!(args
+            .iter()
+            .any(|arg| arg == "v:0,name:720p30 a:0,name:audio"));
+
+        let segments = format!("{}/%v/%d.m4s", OUTPUT);
+        let playlist = format!("{}/%v/index.m3u8", OUTPUT);
+
+        assert!(args.contains(&segments));
+        assert_eq!(args.last(), Some(&playlist));
+    }
+
+    #[tokio::test]
+    async fn file_processed_once() {
+        let input = EncoderInput::File(PathBuf::from("video.mp4"));
+
+        let mut encoder = encoder("true", input);
+
+        let args = encoder.arguments();
+
+        assert_eq!(args[..3], ["-i", "video.mp4", "-filter_complex"]);
+
+        timeout(Duration::from_secs(5), encoder.start())
+            .await
+            .expect("Encoder not stopped");
+    }
+
+    #[tokio::test]
+    async fn live_restarted_with_backoff() {
+        let dir = std::env::temp_dir().join(format!("encoder-test-{}", std::process::id()));
+
+        fs::create_dir_all(&dir).expect("Directory failed");
+
+        let calls = dir.join("calls");
+        let command = dir.join("ffmpeg");
+
+        // Record each start and fail
+        let script = format!("#!/bin/sh\necho \"$@\" >> {}\nexit 1\n", calls.display());
+
+        fs::write(&command, script).expect("Script failed");
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).expect("Chmod failed");
+
+        let mut encoder = encoder(command.to_str().unwrap(), EncoderInput::Live);
+
+        let start = Instant::now();
+
+        // Started at 0, 1 and 3 seconds, the next start is 4 seconds later
+        let result = timeout(Duration::from_millis(3500), encoder.start()).await;
+
+        assert!(result.is_err(), "Encoder stopped");
+        assert!(start.elapsed() >= Duration::from_millis(3500));
+
+        let calls = fs::read_to_string(&calls).expect("Encoder not started");
+
+        fs::remove_dir_all(&dir).expect("Directory failed");
+
+        let lines: Vec<&str> = calls.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+
+        for line in lines {
+            assert_eq!(line, encoder.arguments().join(" "));
+        }
+    }
+}
@@ -1,10 +1,12 @@
 mod archivist;
 mod chat;
+mod encoder;
 mod setup;
 mod video;
 
 pub use archivist::Archive;
 pub use archivist::Archivist;
 pub use chat::ChatAggregator;
+pub use encoder::{Encoder, EncoderInput};
 pub use setup::{SetupAggregator, SetupData};
 pub use video::{VideoAggregator, VideoData};
@@ -1,14 +1,15 @@
 use crate::actors::VideoData;
-use crate::utils::dag_nodes::ipfs_dag_put_node_async;
+use crate::backend::Ipfs;
+use crate::error::{Error, Policy};
+use crate::utils::outbox::Outbox;
+use crate::utils::segment_names::{path_variant, playlist_variant};
 
 use std::collections::HashMap;
-use std::path::Path;
 use std::path::PathBuf;
 
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-
-use ipfs_api::IpfsClient;
+use tokio::sync::mpsc::{Receiver, Sender};
 
+use linked_data::codec::Codec;
 use linked_data::video::{SetupNode, Track};
 use linked_data::IPLDLink;
 
@@ -25,10 +26,11 @@ pub enum SetupData {
 }
 
 pub struct SetupAggregator {
-    ipfs: IpfsClient,
+    /// Retries the setup node until written.
+    outbox: Outbox<()>,
 
-    service_rx: UnboundedReceiver<SetupData>,
-    video_tx: UnboundedSender<VideoData>,
+    service_rx: Receiver<SetupData>,
+    video_tx: Sender<VideoData>,
 
     track_len: usize,
 
@@ -37,12 +39,13 @@ pub struct SetupAggregator {
 
 impl SetupAggregator {
     pub fn new(
-        ipfs: IpfsClient,
-        service_rx: UnboundedReceiver<SetupData>,
-        video_tx: UnboundedSender<VideoData>,
+        ipfs: Ipfs,
+        service_rx: Receiver<SetupData>,
+        video_tx: Sender<VideoData>,
+        codec: Codec,
     ) -> Self {
         Self {
-            ipfs,
+            outbox: Outbox::new(ipfs, codec),
 
             service_rx,
             video_tx,
@@ -56,25 +59,51 @@ impl SetupAggregator {
     pub async fn start(&mut self) {
         println!("✅ Setup System Online");
 
-        while let Some(msg) = self.service_rx.recv().await {
-            match msg {
+        loop {
+            let msg = tokio::select! {
+                msg = self.service_rx.recv() => msg,
+                _ = self.outbox.retry_due() => {
+                    if let Err(e) = self.try_mint_setup_node().await {
+                        self.on_error(e);
+                    }
+
+                    continue;
+                }
+            };
+
+            let msg = match msg {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            let result = match msg {
                 SetupData::Playlist(pl) => self.process_master_playlist(pl).await,
                 SetupData::Segment((path, cid)) => self.init_seg(path, cid).await,
+            };
+
+            if let Err(e) = result {
+                self.on_error(e);
             }
         }
 
         println!("❌ Setup System Offline");
     }
 
+    /// Tracks are kept on failure so that minting is attempted again with the next message.
+    fn on_error(&mut self, error: Error) {
+        match error.policy() {
+            Policy::Retry => eprintln!("❗ Setup: {}. Retrying on next message", error),
+            Policy::Skip => eprintln!("❗ Setup: {}. Skipped", error),
+            Policy::Shutdown => {
+                eprintln!("❗ Setup: {}. Shutting down", error);
+                self.service_rx.close();
+            }
+        }
+    }
+
     /// Update track with initialization segments then try to mint node.
-    async fn init_seg(&mut self, path: PathBuf, cid: Cid) {
-        let name = path
-            .parent()
-            .expect("Orphan path!")
-            .file_name()
-            .expect("Dir with no name!")
-            .to_str()
-            .expect("Invalid Unicode");
+    async fn init_seg(&mut self, path: PathBuf, cid: Cid) -> Result<(), Error> {
+        let name = path_variant(&path)?;
 
         let link = Some(cid.into());
 
@@ -84,26 +113,24 @@ impl SetupAggregator {
             self.map.insert(name.to_owned(), (None, None, link));
         }
 
-        self.try_mint_setup_node().await;
+        self.try_mint_setup_node().await
     }
 
     /// Create or update tracks based on master playlist then try to mint node.
-    async fn process_master_playlist(&mut self, pl: MasterPlaylist) {
+    async fn process_master_playlist(&mut self, pl: MasterPlaylist) -> Result<(), Error> {
         #[cfg(debug_assertions)]
         println!("{:#?}", pl);
 
         self.track_len = pl.variants.len();
 
         for variant in pl.variants.into_iter().rev() {
-            let path = Path::new(&variant.uri);
-
-            let v_name = path
-                .parent()
-                .expect("Orphan path!")
-                .file_name()
-                .expect("Dir with no name!")
-                .to_str()
-                .expect("Invalid Unicode");
+            let v_name = match playlist_variant(&variant.uri) {
+                Some(name) => name,
+                None => {
+                    eprintln!("❗ Setup: unknown variant {}", variant.uri);
+                    continue;
+                }
+            };
 
             let v_codec = match variant.codecs {
                 Some(codec) => {
@@ -127,34 +154,27 @@ impl SetupAggregator {
             }
         }
 
-        self.try_mint_setup_node().await;
+        self.try_mint_setup_node().await
     }
 
     /// Mint SetupNode if it meets all requirements.
-    async fn try_mint_setup_node(&mut self) {
-        if self.map.is_empty() {
-            return;
-        }
-
-        if self.map.len() != self.track_len {
-            return;
-        }
-
-        for (codec, bandwidth, init_seg) in self.map.values() {
-            if codec.is_none() || bandwidth.is_none() || init_seg.is_none() {
-                return;
-            }
+    async fn try_mint_setup_node(&mut self) -> Result<(), Error> {
+        if self.map.is_empty() || self.map.len() != self.track_len {
+            return Ok(());
         }
 
         let mut tracks = Vec::with_capacity(self.track_len);
 
-        for (name, (codec, bandwidth, init_seg)) in self.map.drain() {
-            let codec = codec.unwrap();
-            let bandwidth = bandwidth.unwrap();
-            let initialization_segment = init_seg.unwrap();
+        for (name, track) in self.map.iter() {
+            let (codec, bandwidth, initialization_segment) = match track {
+                (Some(codec), Some(bandwidth), Some(init_seg)) => {
+                    (codec.clone(), *bandwidth, *init_seg)
+                }
+                _ => return Ok(()),
+            };
 
             let track = Track {
-                name,
+                name: name.clone(),
                 codec,
                 initialization_segment,
                 bandwidth,
@@ -167,18 +187,30 @@ impl SetupAggregator {
 
         let setup_node = SetupNode { tracks };
 
-        let cid = ipfs_dag_put_node_async(&self.ipfs, &setup_node)
-            .await
-            .expect("IPFS: SetupNode dag put failed"); // Panic because can't be recovered from anyway
+        if self.outbox.is_empty() {
+            self.outbox.push_back((), &setup_node)?;
+        }
+
+        let cid = match self.outbox.write_next().await {
+            Some((_, Some(cid))) => cid,
+            Some((_, None)) => {
+                eprintln!("❗ Setup: node given up. Minting again with the next message");
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
 
         println!("Setup Node Minted => {}", &cid.to_string());
 
+        self.map.clear();
+        self.service_rx.close();
+
         let msg = VideoData::Setup((cid.into(), self.track_len));
 
-        if let Err(error) = self.video_tx.send(msg) {
-            eprintln!("❗ Video receiver hung up! Error: {}", error);
+        if self.video_tx.send(msg).await.is_err() {
+            return Err(Error::ChannelClosed("Video"));
         }
 
-        self.service_rx.close();
+        Ok(())
     }
 }
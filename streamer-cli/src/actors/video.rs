@@ -1,24 +1,41 @@
 use crate::actors::archivist::Archive;
+use crate::backend::{Ipfs, PubSub};
+use crate::error::{Error, Policy};
 use crate::utils::config::VideoConfig;
-use crate::utils::dag_nodes::ipfs_dag_put_node_async;
+use crate::utils::outbox::Outbox;
+use crate::utils::segment_names::{media_index, path_variant};
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::PathBuf;
 
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 
-use ipfs_api::IpfsClient;
-
-use linked_data::video::VideoNode;
+use linked_data::codec::Codec;
+use linked_data::video::{gap_link, is_gap, VideoNode};
 use linked_data::IPLDLink;
 
 use cid::Cid;
 
+use m3u8_rs::playlist::MediaPlaylist;
+
+/// Segments further ahead of the next node to mint are refused.
+const MAX_BUFFERED: usize = 64;
+
+/// Later segments received before the duration of a node missing from the playlists is guessed.
+const DURATION_WAIT: usize = 3;
+
+/// Later segments received before a node still missing tracks is given up.
+const TRACK_WAIT: usize = 8;
+
 pub struct VideoAggregator {
-    ipfs: IpfsClient,
+    ipfs: Ipfs,
+
+    /// Retries the node in front of the queue, each node links to the previous one.
+    outbox: Outbox<()>,
 
-    service_rx: UnboundedReceiver<VideoData>,
-    archive_tx: Option<UnboundedSender<Archive>>,
+    service_rx: Receiver<VideoData>,
+    archive_tx: Option<Sender<Archive>>,
 
     config: VideoConfig,
 
@@ -28,23 +45,40 @@ pub struct VideoAggregator {
     node_mint_count: usize,
     video_nodes: VecDeque<VideoNode>,
 
+    /// Added to ffmpeg segment indices after it restarts numbering.
+    index_offset: usize,
+
+    /// Segment durations from media playlists, by absolute index.
+    durations: HashMap<usize, f64>,
+
+    /// Maximum segment duration of the latest media playlist.
+    target_duration: Option<f64>,
+
     previous: Option<IPLDLink>,
+
+    /// Variants whose media playlist ended.
+    ended: HashSet<String>,
+    end_tx: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
 pub enum VideoData {
     Segment((PathBuf, Cid)),
     Setup((IPLDLink, usize)),
+    Playlist((PathBuf, MediaPlaylist)),
 }
 
 impl VideoAggregator {
     pub fn new(
-        ipfs: IpfsClient,
-        service_rx: UnboundedReceiver<VideoData>,
-        archive_tx: Option<UnboundedSender<Archive>>,
+        ipfs: Ipfs,
+        service_rx: Receiver<VideoData>,
+        archive_tx: Option<Sender<Archive>>,
         config: VideoConfig,
+        codec: Codec,
+        end_tx: Option<oneshot::Sender<()>>,
     ) -> Self {
         Self {
+            outbox: Outbox::new(ipfs.clone(), codec),
             ipfs,
 
             service_rx,
@@ -57,128 +91,326 @@ impl VideoAggregator {
 
             node_mint_count: 0,
             video_nodes: VecDeque::with_capacity(5),
+            index_offset: 0,
+            durations: HashMap::with_capacity(5),
+            target_duration: None,
             previous: None,
+
+            ended: HashSet::with_capacity(4),
+            end_tx,
         }
     }
 
     pub async fn start(&mut self) {
         println!("✅ Video System Online");
 
-        while let Some(msg) = self.service_rx.recv().await {
-            match msg {
+        loop {
+            let msg = tokio::select! {
+                msg = self.service_rx.recv() => msg,
+                _ = self.outbox.retry_due() => {
+                    if let Err(e) = self.mint_video_nodes().await {
+                        self.on_error(e).await;
+                    }
+
+                    continue;
+                }
+            };
+
+            let msg = match msg {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            let result = match msg {
                 VideoData::Segment((path, cid)) => self.media_seg(path, cid).await,
                 VideoData::Setup((link, len)) => {
                     self.track_len = len;
                     self.setup_link = Some(link);
+
+                    // Segments received before the setup can now be minted
+                    self.mint_video_nodes().await
                 }
+                VideoData::Playlist((path, playlist)) => self.media_playlist(path, playlist).await,
+            };
+
+            if let Err(e) = result {
+                self.on_error(e).await;
             }
         }
 
         println!("❌ Video System Offline");
     }
 
-    /// Update or create VideoNode in queue then try to mint one.
-    async fn media_seg(&mut self, path: PathBuf, cid: Cid) {
-        let quality = path
-            .parent()
-            .expect("Orphan path!")
-            .file_name()
-            .expect("Dir with no name!")
-            .to_str()
-            .expect("Invalid Unicode");
-
-        //absolute index from ffmpeg
-        let index = path
-            .file_stem()
-            .expect("Not file stem")
-            .to_str()
-            .expect("Invalid Unicode")
-            .parse::<usize>()
-            .expect("Not a number");
+    /// Nodes stay queued on failure so that minting is attempted again with the next message.
+    async fn on_error(&mut self, error: Error) {
+        match error.policy() {
+            Policy::Retry => eprintln!("❗ Video: {}. Retrying on next message", error),
+            Policy::Skip => eprintln!("❗ Video: {}. Skipped", error),
+            Policy::Shutdown => {
+                eprintln!("❗ Video: {}. Shutting down", error);
+                self.shutdown().await;
+            }
+        }
+    }
 
-        // relative index for in memory video nodes
-        let buffer_index = index - self.node_mint_count;
+    /// Finalize the archive and signal the end of the stream then stop receiving.
+    async fn shutdown(&mut self) {
+        if let Some(archive_tx) = self.archive_tx.take() {
+            if archive_tx.send(Archive::Finalize).await.is_err() {
+                eprintln!("❗ Video: archive receiver hung up!");
+            }
+        }
 
-        if let Some(node) = self.video_nodes.get_mut(buffer_index) {
-            node.tracks.insert(quality.to_owned(), cid.into());
+        if let Some(end_tx) = self.end_tx.take() {
+            if end_tx.send(()).is_err() {
+                eprintln!("❗ Video: end of stream receiver hung up!");
+            }
+        }
 
-            node.setup = self.setup_link;
+        self.service_rx.close();
+    }
 
-            // Set previous field only for the next node to be minted
-            if buffer_index == 0 {
-                node.previous = self.previous;
-            }
-        } else {
-            let mut tracks = HashMap::with_capacity(4);
+    /// Update or create VideoNode in queue then try to mint one.
+    async fn media_seg(&mut self, path: PathBuf, cid: Cid) -> Result<(), Error> {
+        //index from ffmpeg
+        let index = match path.to_str().and_then(media_index) {
+            Some(index) => index,
+            None => return Err(Error::Path(path)),
+        };
 
-            tracks.insert(quality.to_owned(), cid.into());
+        let quality = path_variant(&path)?.to_owned();
 
-            let setup = self.setup_link;
+        if index + self.index_offset < self.node_mint_count {
+            self.restart_numbering();
+        }
+
+        // relative index for in memory video nodes
+        let buffer_index = index + self.index_offset - self.node_mint_count;
 
-            let previous = None;
+        if buffer_index >= MAX_BUFFERED {
+            return Err(Error::Lagging(index + self.index_offset));
+        }
 
+        // Segments can arrive before the previous ones
+        while self.video_nodes.len() <= buffer_index {
             let node = VideoNode {
-                tracks,
-                setup,
-                previous,
+                tracks: HashMap::with_capacity(4),
+                setup: self.setup_link,
+                previous: None,
+                duration: None,
             };
 
             self.video_nodes.push_back(node);
         }
 
+        let node = &mut self.video_nodes[buffer_index];
+
+        node.tracks.insert(quality, cid.into());
+
+        node.setup = self.setup_link;
+
+        #[cfg(debug_assertions)]
+        println!("Video: {} buffered nodes", self.video_nodes.len());
+
+        self.mint_video_nodes().await
+    }
+
+    /// Ffmpeg restarted, nodes of the previous numbering will never be complete.
+    fn restart_numbering(&mut self) {
+        println!("Video: Segment Numbering Restarted");
+
+        if !self.video_nodes.is_empty() {
+            eprintln!(
+                "❗ Video: {} incomplete nodes dropped",
+                self.video_nodes.len()
+            );
+        }
+
+        self.video_nodes.clear();
+        self.outbox.clear();
+        self.durations.clear();
+        self.index_offset = self.node_mint_count;
+    }
+
+    /// Save segment durations then try to mint nodes.
+    /// Signal the end of the stream once all variants ended.
+    async fn media_playlist(
+        &mut self,
+        path: PathBuf,
+        playlist: MediaPlaylist,
+    ) -> Result<(), Error> {
+        self.target_duration = Some(playlist.target_duration as f64);
+
+        for segment in playlist.segments.iter() {
+            let index = match media_index(&segment.uri) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let index = index + self.index_offset;
+
+            if index < self.node_mint_count {
+                continue;
+            }
+
+            if segment.discontinuity && !self.durations.contains_key(&index) {
+                println!("Video: Discontinuity At Segment {}", index);
+            }
+
+            self.durations.insert(index, segment.duration as f64);
+        }
+
+        // The end of stream is signaled even if minting failed
+        if let Err(e) = self.mint_video_nodes().await {
+            self.on_error(e).await;
+        }
+
+        if !playlist.end_list {
+            return Ok(());
+        }
+
+        let variant = path_variant(&path)?;
+
+        self.ended.insert(variant.to_owned());
+
+        if self.track_len == 0 || self.ended.len() < self.track_len {
+            return Ok(());
+        }
+
+        let end_tx = match self.end_tx.take() {
+            Some(end_tx) => end_tx,
+            None => return Ok(()),
+        };
+
+        println!("Video: End Of Stream");
+
+        if !self.video_nodes.is_empty() {
+            eprintln!(
+                "❗ Video: {} incomplete nodes dropped",
+                self.video_nodes.len()
+            );
+        }
+
+        if end_tx.send(()).is_err() {
+            eprintln!("❗ Video: end of stream receiver hung up!");
+        }
+
+        Ok(())
+    }
+
+    /// Mint as many nodes as possible then send them to the archive and pubsub.
+    async fn mint_video_nodes(&mut self) -> Result<(), Error> {
         // try to mint in case something failed previously
-        while let Some(cid) = self.mint_video_node().await {
+        while let Some((cid, duration)) = self.mint_video_node().await {
             if let Some(archive_tx) = self.archive_tx.as_ref() {
-                let msg = Archive::Video(cid);
+                let msg = Archive::Video((cid, duration));
+
+                if archive_tx.send(msg).await.is_err() {
+                    self.archive_tx = None;
 
-                if let Err(error) = archive_tx.send(msg) {
-                    eprintln!("❗ Archive receiver hung up! Error: {}", error);
+                    return Err(Error::ChannelClosed("Archive"));
                 }
             }
 
-            if self.config.pubsub_enable {
+            if self.config.pubsub_enable && !is_gap(&cid.into()) {
                 let topic = &self.config.pubsub_topic;
 
+                // Live viewers can't use a late node, no retry
                 if let Err(e) = self.ipfs.pubsub_pub(topic, &cid.to_string()).await {
                     eprintln!("❗ IPFS: pubsub pub failed {}", e);
                 }
             }
         }
 
-        #[cfg(debug_assertions)]
-        println!("Video: {} buffered nodes", self.video_nodes.len());
+        Ok(())
+    }
+
+    /// Duration of the next node to mint. Sliding window playlists can skip a segment,
+    /// once later segments arrived its duration is the next known one or the target duration.
+    fn next_duration(&self) -> Option<f64> {
+        if let Some(duration) = self.durations.get(&self.node_mint_count) {
+            return Some(*duration);
+        }
+
+        if self.video_nodes.len() <= DURATION_WAIT {
+            return None;
+        }
+
+        let next = (self.node_mint_count..self.node_mint_count + self.video_nodes.len())
+            .find_map(|index| self.durations.get(&index))
+            .copied();
+
+        next.or(self.target_duration)
     }
 
     /// Mint the first VideoNode in queue if it meets all requirements.
-    async fn mint_video_node(&mut self) -> Option<Cid> {
+    /// Given up nodes are replaced by a gap marker and left out of the chain,
+    /// as are nodes still missing tracks once later segments arrived.
+    async fn mint_video_node(&mut self) -> Option<(Cid, f64)> {
+        let duration = self.next_duration()?;
+
+        let waited = self.video_nodes.len() > TRACK_WAIT;
+
         let node = self.video_nodes.front_mut()?;
 
+        node.duration = Some(duration);
+
         node.setup = self.setup_link;
 
-        node.setup?;
+        let complete = node.setup.is_some() && node.tracks.len() == self.track_len;
 
-        if node.tracks.len() != self.track_len {
+        if !complete && !waited {
             return None;
         }
 
-        if node.previous.is_none() && self.node_mint_count > 0 {
-            return None;
-        }
+        let cid = if complete {
+            node.previous = self.previous;
+
+            if self.outbox.is_empty() {
+                if let Err(e) = self.outbox.push_back((), node) {
+                    eprintln!("❗ Video: {}", e);
+                }
+            }
 
-        let cid = match ipfs_dag_put_node_async(&self.ipfs, node).await {
-            Ok(res) => res,
-            Err(e) => {
-                eprintln!("❗ IPFS: dag put failed {}", e);
-                return None;
+            // Nothing queued if the node could not be encoded
+            if self.outbox.is_empty() {
+                None
+            } else {
+                let (_, cid) = self.outbox.write_next().await?;
+                cid
             }
+        } else {
+            eprintln!(
+                "❗ Video: segment {} incomplete, tracks never received",
+                self.node_mint_count
+            );
+
+            None
         };
 
         self.video_nodes.pop_front();
+
+        if self.durations.remove(&self.node_mint_count).is_none() {
+            eprintln!(
+                "❗ Video: segment {} missing from playlists, duration set to {}",
+                self.node_mint_count, duration
+            );
+        }
+
         self.node_mint_count += 1;
+
+        let cid = match cid {
+            Some(cid) => cid,
+            None => {
+                eprintln!("❗ Video: node given up, gap marker archived instead");
+                return Some((gap_link().link, duration));
+            }
+        };
+
         self.previous = Some(cid.into());
 
         println!("Video Node Minted => {}", &cid.to_string());
 
-        Some(cid)
+        Some((cid, duration))
     }
 }
@@ -0,0 +1,239 @@
+use crate::backend::{DagStore, FileReader, Naming, PubSub, PubSubMessage};
+
+use std::convert::TryFrom;
+use std::io::Cursor;
+
+use async_trait::async_trait;
+
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Request};
+
+use ipfs_api::request::Add;
+use ipfs_api::response::Error;
+use ipfs_api::{IpfsClient, KeyType};
+
+use linked_data::codec::Codec;
+
+use serde::Deserialize;
+
+use cid::Cid;
+
+/// HTTP API of the local daemon, as used by the default client.
+const API_URL: &str = "http://127.0.0.1:5001/api/v0";
+
+const BOUNDARY: &str = "dag-node-boundary";
+
+const OPTIONS: Add = Add {
+    trickle: None,
+    only_hash: None,
+    wrap_with_directory: None,
+    chunker: None,
+    pin: Some(false),
+    raw_leaves: None,
+    cid_version: Some(1),
+    hash: None,
+    inline: None,
+    inline_limit: None,
+};
+
+/// Local go-ipfs daemon.
+/// Nodes are put through the HTTP API directly, the client cannot choose their codec.
+#[derive(Default)]
+pub struct Daemon {
+    client: IpfsClient,
+    http: Client<HttpConnector>,
+}
+
+#[derive(Deserialize)]
+struct DagPutResponse {
+    #[serde(rename = "Cid")]
+    cid: Link,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    #[serde(rename = "/")]
+    link: String,
+}
+
+#[async_trait]
+impl DagStore for Daemon {
+    async fn dag_put(&self, data: Vec<u8>, codec: Codec) -> Result<Cid, Error> {
+        let uri = format!(
+            "{}/dag/put?store-codec={}&input-codec={}",
+            API_URL,
+            codec.name(),
+            codec.name()
+        );
+
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"node\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            BOUNDARY
+        )
+        .into_bytes();
+
+        body.extend_from_slice(&data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let request = Request::post(uri)
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .map_err(uncategorized)?;
+
+        let response = self.http.request(request).await.map_err(uncategorized)?;
+
+        let status = response.status();
+
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(uncategorized)?;
+
+        if !status.is_success() {
+            let message = String::from_utf8_lossy(&bytes).into_owned();
+
+            return Err(Error::Uncategorized(message));
+        }
+
+        let response: DagPutResponse = serde_json::from_slice(&bytes).map_err(uncategorized)?;
+
+        to_cid(response.cid.link)
+    }
+
+    /// The daemon output DAG-JSON whatever the codec the node was stored with.
+    async fn dag_get(&self, path: &str) -> Result<(Codec, Vec<u8>), Error> {
+        let data = self
+            .client
+            .dag_get(path)
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await?;
+
+        Ok((Codec::DagJson, data))
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error> {
+        let response = self
+            .client
+            .add_with_options(Cursor::new(data), OPTIONS)
+            .await?;
+
+        to_cid(response.hash)
+    }
+
+    async fn add_reader(&self, reader: FileReader) -> Result<Cid, Error> {
+        let response = self.client.add_with_options(reader, OPTIONS).await?;
+
+        to_cid(response.hash)
+    }
+
+    async fn cat(&self, path: &str) -> Result<Vec<u8>, Error> {
+        self.client
+            .cat(path)
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+    }
+
+    async fn pin_add(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        self.client.pin_add(&cid.to_string(), recursive).await?;
+
+        Ok(())
+    }
+
+    async fn pin_rm(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        self.client.pin_rm(&cid.to_string(), recursive).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Naming for Daemon {
+    async fn key_id(&self, key: &str) -> Result<Option<String>, Error> {
+        let response = self.client.key_list().await?;
+
+        let id = response
+            .keys
+            .into_iter()
+            .find(|keypair| keypair.name == key)
+            .map(|keypair| keypair.id);
+
+        Ok(id)
+    }
+
+    async fn key_gen(&self, key: &str) -> Result<String, Error> {
+        let response = self
+            .client
+            .key_gen(
+                key,
+                KeyType::Ed25519,
+                64, /* Don't think this does anything... */
+            )
+            .await?;
+
+        Ok(response.id)
+    }
+
+    async fn name_publish(&self, cid: &Cid, key: &str) -> Result<(), Error> {
+        self.client
+            .name_publish(
+                &cid.to_string(),
+                true,
+                Some("4320h"), // 6 months
+                None,
+                Some(key),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<Cid, Error> {
+        let response = self.client.name_resolve(Some(name), false, false).await?;
+
+        to_cid(response.path)
+    }
+}
+
+#[async_trait]
+impl PubSub for Daemon {
+    async fn peer_id(&self) -> Result<String, Error> {
+        let response = self.client.id(None).await?;
+
+        Ok(response.id)
+    }
+
+    async fn pubsub_pub(&self, topic: &str, data: &str) -> Result<(), Error> {
+        self.client.pubsub_pub(topic, data).await?;
+
+        Ok(())
+    }
+
+    fn pubsub_sub(&self, topic: &str) -> BoxStream<'static, Result<PubSubMessage, Error>> {
+        self.client
+            .pubsub_sub(topic, true)
+            .map(|result| {
+                result.map(|response| PubSubMessage {
+                    from: response.from,
+                    data: response.data.map(Into::into),
+                })
+            })
+            .boxed()
+    }
+}
+
+/// Also parse IPFS paths. /ipfs/<cid>
+fn to_cid(cid: String) -> Result<Cid, Error> {
+    Cid::try_from(cid).map_err(uncategorized)
+}
+
+fn uncategorized<E: ToString>(error: E) -> Error {
+    Error::Uncategorized(error.to_string())
+}
@@ -0,0 +1,653 @@
+use crate::backend::{DagStore, FileReader, Naming, PubSub, PubSubMessage};
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use tokio::io::AsyncReadExt;
+use tokio::sync::broadcast::{self, error::RecvError, Sender};
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+
+use ipfs_api::response::Error;
+
+use linked_data::codec::Codec;
+
+use multihash::{Code, MultihashDigest};
+
+use cid::Cid;
+
+/// Multicodec code of files added with raw leaves.
+const RAW: u64 = 0x55;
+
+/// Multicodec code of IPNS keys.
+const LIBP2P_KEY: u64 = 0x72;
+
+/// CBOR tag reserved for CIDs.
+const CBOR_CID_TAG: u64 = 42;
+
+const TOPIC_CAPACITY: usize = 256;
+
+/// Blocks, keys and topics kept in memory.
+///
+/// CIDs are computed like the daemon does, files being single raw blocks and nodes re-encoded canonically.
+/// Only files smaller than the daemon chunk size (256KiB) have the same CID.
+pub struct MemoryBackend {
+    peer_id: String,
+
+    blocks: Mutex<HashMap<Cid, Vec<u8>>>,
+    pins: Mutex<HashSet<Cid>>,
+
+    /// Key name => IPNS name
+    keys: Mutex<HashMap<String, String>>,
+
+    /// IPNS name => CID
+    records: Mutex<HashMap<String, Cid>>,
+
+    topics: Mutex<HashMap<String, Sender<PubSubMessage>>>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self {
+            peer_id: key_name("self"),
+
+            blocks: Mutex::new(HashMap::new()),
+            pins: Mutex::new(HashSet::new()),
+
+            keys: Mutex::new(HashMap::new()),
+            records: Mutex::new(HashMap::new()),
+
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Raw block of this CID.
+    pub fn block(&self, cid: &Cid) -> Option<Vec<u8>> {
+        self.blocks.lock().expect("Lock Poisoned").get(cid).cloned()
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.pins.lock().expect("Lock Poisoned").contains(cid)
+    }
+
+    pub fn pins(&self) -> Vec<Cid> {
+        self.pins
+            .lock()
+            .expect("Lock Poisoned")
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Publish as another peer would.
+    pub fn publish_from(&self, peer: &str, topic: &str, data: &[u8]) {
+        let msg = PubSubMessage {
+            from: Some(peer.to_owned()),
+            data: Some(data.to_vec()),
+        };
+
+        // No subscribers is not an error
+        let _ = self.topic(topic).send(msg);
+    }
+
+    fn put_block(&self, codec: u64, data: Vec<u8>) -> Cid {
+        let cid = Cid::new_v1(codec, Code::Sha2_256.digest(&data));
+
+        self.blocks.lock().expect("Lock Poisoned").insert(cid, data);
+
+        cid
+    }
+
+    fn get_block(&self, cid: &Cid) -> Result<Vec<u8>, Error> {
+        self.block(cid)
+            .ok_or_else(|| Error::Uncategorized(format!("block {} not found", cid)))
+    }
+
+    /// Follow the path through links. Return the CID of the last block and the remaining path in it.
+    fn resolve<'a>(&self, path: &'a str) -> Result<(Cid, Vec<&'a str>), Error> {
+        let path = path.strip_prefix("/ipfs/").unwrap_or(path);
+
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+
+        let root = segments.next().unwrap_or_default();
+
+        let mut cid = Cid::try_from(root).map_err(|e| Error::Uncategorized(e.to_string()))?;
+        let mut rest: Vec<&str> = segments.collect();
+
+        loop {
+            if rest.is_empty() {
+                return Ok((cid, rest));
+            }
+
+            let data = self.get_block(&cid)?;
+
+            let link = match cid.codec() {
+                RAW => return Err(Error::Uncategorized(format!("{} is a file", cid))),
+                _ => match Codec::from_cid(&cid) {
+                    Some(Codec::DagJson) => json_link(&data, &rest)?,
+                    Some(Codec::DagCbor) => cbor_link(&data, &rest)?,
+                    None => return Err(Error::Uncategorized(format!("{} unknown codec", cid))),
+                },
+            };
+
+            match link {
+                Some((link, index)) => {
+                    cid = link;
+                    rest.drain(..index);
+                }
+                None => return Ok((cid, rest)),
+            }
+        }
+    }
+
+    fn topic(&self, topic: &str) -> Sender<PubSubMessage> {
+        self.topics
+            .lock()
+            .expect("Lock Poisoned")
+            .entry(topic.to_owned())
+            .or_insert_with(|| broadcast::channel(TOPIC_CAPACITY).0)
+            .clone()
+    }
+}
+
+#[async_trait]
+impl DagStore for MemoryBackend {
+    async fn dag_put(&self, data: Vec<u8>, codec: Codec) -> Result<Cid, Error> {
+        let data = match codec {
+            Codec::DagJson => canonical_json(&data)?,
+            Codec::DagCbor => canonical_cbor(&data)?,
+        };
+
+        Ok(self.put_block(codec.code(), data))
+    }
+
+    async fn dag_get(&self, path: &str) -> Result<(Codec, Vec<u8>), Error> {
+        let (cid, rest) = self.resolve(path)?;
+
+        let codec = match Codec::from_cid(&cid) {
+            Some(codec) => codec,
+            None => return Err(Error::Uncategorized(format!("{} is not a dag node", cid))),
+        };
+
+        let data = self.get_block(&cid)?;
+
+        if rest.is_empty() {
+            return Ok((codec, data));
+        }
+
+        let data = match codec {
+            Codec::DagJson => json_value(&data, &rest)?,
+            Codec::DagCbor => cbor_value(&data, &rest)?,
+        };
+
+        Ok((codec, data))
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error> {
+        Ok(self.put_block(RAW, data))
+    }
+
+    async fn add_reader(&self, mut reader: FileReader) -> Result<Cid, Error> {
+        let mut data = Vec::new();
+
+        if let Err(e) = reader.read_to_end(&mut data).await {
+            return Err(Error::Uncategorized(e.to_string()));
+        }
+
+        self.add(data).await
+    }
+
+    async fn cat(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let (cid, rest) = self.resolve(path)?;
+
+        if cid.codec() != RAW || !rest.is_empty() {
+            return Err(Error::Uncategorized(format!("{} is not a file", path)));
+        }
+
+        self.get_block(&cid)
+    }
+
+    /// Pins are not recursive, the blocks are never removed anyway.
+    async fn pin_add(&self, cid: &Cid, _recursive: bool) -> Result<(), Error> {
+        self.get_block(cid)?;
+
+        self.pins.lock().expect("Lock Poisoned").insert(*cid);
+
+        Ok(())
+    }
+
+    async fn pin_rm(&self, cid: &Cid, _recursive: bool) -> Result<(), Error> {
+        if !self.pins.lock().expect("Lock Poisoned").remove(cid) {
+            return Err(Error::Uncategorized(format!("{} is not pinned", cid)));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Naming for MemoryBackend {
+    async fn key_id(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.keys.lock().expect("Lock Poisoned").get(key).cloned())
+    }
+
+    async fn key_gen(&self, key: &str) -> Result<String, Error> {
+        let mut keys = self.keys.lock().expect("Lock Poisoned");
+
+        if keys.contains_key(key) {
+            return Err(Error::Uncategorized(format!("key {} already exists", key)));
+        }
+
+        let id = key_name(key);
+
+        keys.insert(key.to_owned(), id.clone());
+
+        Ok(id)
+    }
+
+    async fn name_publish(&self, cid: &Cid, key: &str) -> Result<(), Error> {
+        let id = match key {
+            "self" => self.peer_id.clone(),
+            _ => match self.keys.lock().expect("Lock Poisoned").get(key) {
+                Some(id) => id.clone(),
+                None => return Err(Error::Uncategorized(format!("key {} not found", key))),
+            },
+        };
+
+        self.records.lock().expect("Lock Poisoned").insert(id, *cid);
+
+        Ok(())
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<Cid, Error> {
+        let name = name.strip_prefix("/ipns/").unwrap_or(name);
+
+        match self.records.lock().expect("Lock Poisoned").get(name) {
+            Some(cid) => Ok(*cid),
+            None => Err(Error::Uncategorized(format!("{} not found", name))),
+        }
+    }
+}
+
+#[async_trait]
+impl PubSub for MemoryBackend {
+    async fn peer_id(&self) -> Result<String, Error> {
+        Ok(self.peer_id.clone())
+    }
+
+    async fn pubsub_pub(&self, topic: &str, data: &str) -> Result<(), Error> {
+        self.publish_from(&self.peer_id, topic, data.as_bytes());
+
+        Ok(())
+    }
+
+    fn pubsub_sub(&self, topic: &str) -> BoxStream<'static, Result<PubSubMessage, Error>> {
+        let receiver = self.topic(topic).subscribe();
+
+        stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(msg) => return Some((Ok(msg), receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// IPNS name derived from the key name, as a libp2p-key CID.
+fn key_name(key: &str) -> String {
+    Cid::new_v1(LIBP2P_KEY, Code::Sha2_256.digest(key.as_bytes())).to_string()
+}
+
+/// Sorted keys and no whitespace.
+fn canonical_json(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let value: serde_json::Value =
+        serde_json::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    serde_json::to_vec(&value).map_err(|e| Error::Uncategorized(e.to_string()))
+}
+
+/// Keys sorted length first, smallest integer sizes and 64 bits floats.
+fn canonical_cbor(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let value: serde_cbor::Value =
+        serde_cbor::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    let mut data = Vec::with_capacity(data.len());
+
+    write_cbor(&value, &mut data);
+
+    Ok(data)
+}
+
+fn write_cbor(value: &serde_cbor::Value, data: &mut Vec<u8>) {
+    use serde_cbor::Value;
+
+    match value {
+        Value::Null => data.push(0xf6),
+        Value::Bool(false) => data.push(0xf4),
+        Value::Bool(true) => data.push(0xf5),
+        Value::Integer(int) if *int >= 0 => write_cbor_head(0, *int as u64, data),
+        Value::Integer(int) => write_cbor_head(1, (-1 - *int) as u64, data),
+        Value::Float(float) => {
+            data.push(0xfb);
+            data.extend_from_slice(&float.to_bits().to_be_bytes());
+        }
+        Value::Bytes(bytes) => {
+            write_cbor_head(2, bytes.len() as u64, data);
+            data.extend_from_slice(bytes);
+        }
+        Value::Text(text) => {
+            write_cbor_head(3, text.len() as u64, data);
+            data.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(list) => {
+            write_cbor_head(4, list.len() as u64, data);
+
+            for item in list {
+                write_cbor(item, data);
+            }
+        }
+        // Map keys are ordered canonically
+        Value::Map(map) => {
+            write_cbor_head(5, map.len() as u64, data);
+
+            for (key, item) in map {
+                write_cbor(key, data);
+                write_cbor(item, data);
+            }
+        }
+        Value::Tag(tag, item) => {
+            write_cbor_head(6, *tag, data);
+            write_cbor(item, data);
+        }
+        _ => data.push(0xf7),
+    }
+}
+
+/// Major type and argument in the fewest bytes.
+fn write_cbor_head(major: u8, argument: u64, data: &mut Vec<u8>) {
+    let major = major << 5;
+
+    if argument < 24 {
+        data.push(major | argument as u8);
+    } else if argument <= u8::MAX as u64 {
+        data.push(major | 24);
+        data.push(argument as u8);
+    } else if argument <= u16::MAX as u64 {
+        data.push(major | 25);
+        data.extend_from_slice(&(argument as u16).to_be_bytes());
+    } else if argument <= u32::MAX as u64 {
+        data.push(major | 26);
+        data.extend_from_slice(&(argument as u32).to_be_bytes());
+    } else {
+        data.push(major | 27);
+        data.extend_from_slice(&argument.to_be_bytes());
+    }
+}
+
+fn json_link(data: &[u8], path: &[&str]) -> Result<Option<(Cid, usize)>, Error> {
+    let mut value: serde_json::Value =
+        serde_json::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    for (i, segment) in path.iter().enumerate() {
+        value = json_child(value, segment)?;
+
+        if let Some(link) = value.get("/").and_then(|link| link.as_str()) {
+            let cid = Cid::try_from(link).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+            return Ok(Some((cid, i + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn json_value(data: &[u8], path: &[&str]) -> Result<Vec<u8>, Error> {
+    let mut value: serde_json::Value =
+        serde_json::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    for segment in path {
+        value = json_child(value, segment)?;
+    }
+
+    serde_json::to_vec(&value).map_err(|e| Error::Uncategorized(e.to_string()))
+}
+
+fn json_child(value: serde_json::Value, segment: &str) -> Result<serde_json::Value, Error> {
+    let child = match value {
+        serde_json::Value::Object(mut map) => map.remove(segment),
+        serde_json::Value::Array(mut list) => match segment.parse::<usize>() {
+            Ok(index) if index < list.len() => Some(list.swap_remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    child.ok_or_else(|| Error::Uncategorized(format!("no link named {}", segment)))
+}
+
+/// First link along the path and the number of segments walked to reach it.
+fn cbor_link(data: &[u8], path: &[&str]) -> Result<Option<(Cid, usize)>, Error> {
+    let mut value: serde_cbor::Value =
+        serde_cbor::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    for (i, segment) in path.iter().enumerate() {
+        value = cbor_child(value, segment)?;
+
+        if let serde_cbor::Value::Tag(CBOR_CID_TAG, link) = &value {
+            let cid = match link.as_ref() {
+                // Binary CIDs are prefixed with the multibase identity prefix.
+                serde_cbor::Value::Bytes(bytes) if !bytes.is_empty() => {
+                    Cid::try_from(&bytes[1..]).map_err(|e| Error::Uncategorized(e.to_string()))?
+                }
+                _ => return Err(Error::Uncategorized("Invalid CBOR link".into())),
+            };
+
+            return Ok(Some((cid, i + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn cbor_value(data: &[u8], path: &[&str]) -> Result<Vec<u8>, Error> {
+    let mut value: serde_cbor::Value =
+        serde_cbor::from_slice(data).map_err(|e| Error::Uncategorized(e.to_string()))?;
+
+    for segment in path {
+        value = cbor_child(value, segment)?;
+    }
+
+    serde_cbor::to_vec(&value).map_err(|e| Error::Uncategorized(e.to_string()))
+}
+
+fn cbor_child(value: serde_cbor::Value, segment: &str) -> Result<serde_cbor::Value, Error> {
+    let child = match value {
+        serde_cbor::Value::Map(mut map) => map.remove(&serde_cbor::Value::Text(segment.to_owned())),
+        serde_cbor::Value::Array(mut list) => match segment.parse::<usize>() {
+            Ok(index) if index < list.len() => Some(list.swap_remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    child.ok_or_else(|| Error::Uncategorized(format!("no link named {}", segment)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use linked_data::IPLDLink;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Node {
+        name: String,
+        links: Vec<IPLDLink>,
+    }
+
+    #[tokio::test]
+    async fn file_cid_matches_daemon() {
+        let ipfs = MemoryBackend::new();
+
+        let cid = ipfs.add(b"hello world".to_vec()).await.unwrap();
+
+        assert_eq!(
+            cid.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+        assert_eq!(ipfs.cat(&cid.to_string()).await.unwrap(), b"hello world");
+
+        let reader: FileReader = Box::pin(std::io::Cursor::new(b"hello world".to_vec()));
+
+        assert_eq!(ipfs.add_reader(reader).await.unwrap(), cid);
+    }
+
+    #[tokio::test]
+    async fn node_cid_matches_daemon() {
+        // Fields out of canonical order and a float serde_cbor would shrink
+        #[derive(Serialize)]
+        struct Segment {
+            links: Vec<IPLDLink>,
+            duration: f64,
+            name: String,
+        }
+
+        let ipfs = MemoryBackend::new();
+
+        let file = ipfs.add(b"hello world".to_vec()).await.unwrap();
+
+        let node = Segment {
+            links: vec![file.into()],
+            duration: 1.5,
+            name: "leaf".into(),
+        };
+
+        let expected = [
+            (
+                Codec::DagJson,
+                "baguqeerav37gpfje5qibybjatafphuxzhinh5nr7hyowwmsrxo6b3qo6s7cq",
+            ),
+            (
+                Codec::DagCbor,
+                "bafyreiaksj4bn5j7rvoylcew2e7wo2hy77ptdeo5zpzmtcfitabjrb3l7y",
+            ),
+        ];
+
+        for (codec, cid) in expected.iter() {
+            let data = codec.encode(&node).unwrap();
+
+            let put = ipfs.dag_put(data, *codec).await.unwrap();
+
+            assert_eq!(put.to_string(), *cid);
+        }
+    }
+
+    #[tokio::test]
+    async fn dag_get_follows_links() {
+        for codec in [Codec::DagJson, Codec::DagCbor].iter().copied() {
+            let ipfs = MemoryBackend::new();
+
+            let file = ipfs.add(b"segment".to_vec()).await.unwrap();
+
+            let leaf = Node {
+                name: "leaf".into(),
+                links: vec![file.into()],
+            };
+            let leaf_cid = ipfs.dag_put(codec.encode(&leaf).unwrap(), codec).await;
+            let leaf_cid = leaf_cid.unwrap();
+
+            let root = Node {
+                name: "root".into(),
+                links: vec![leaf_cid.into()],
+            };
+            let root_cid = ipfs.dag_put(codec.encode(&root).unwrap(), codec).await;
+            let root_cid = root_cid.unwrap();
+
+            assert_eq!(root_cid.codec(), codec.code());
+
+            let (got_codec, data) = ipfs
+                .dag_get(&format!("/ipfs/{}/links/0", root_cid))
+                .await
+                .unwrap();
+
+            assert_eq!(got_codec, codec);
+            assert_eq!(codec.decode::<Node>(&data).unwrap(), leaf);
+
+            let (_, data) = ipfs
+                .dag_get(&format!("{}/links/0/name", root_cid))
+                .await
+                .unwrap();
+
+            assert_eq!(codec.decode::<String>(&data).unwrap(), "leaf");
+
+            let path = format!("{}/links/0/links/0", root_cid);
+
+            assert_eq!(ipfs.cat(&path).await.unwrap(), b"segment");
+            assert!(ipfs.dag_get(&path).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn names_and_pins() {
+        let ipfs = MemoryBackend::new();
+
+        let cid = ipfs.add(b"feed".to_vec()).await.unwrap();
+
+        assert_eq!(ipfs.key_id("feed").await.unwrap(), None);
+
+        let id = ipfs.key_gen("feed").await.unwrap();
+
+        assert!(ipfs.key_gen("feed").await.is_err());
+        assert_eq!(ipfs.key_id("feed").await.unwrap(), Some(id.clone()));
+
+        ipfs.name_publish(&cid, "feed").await.unwrap();
+
+        let path = format!("/ipns/{}", id);
+
+        assert_eq!(ipfs.name_resolve(&path).await.unwrap(), cid);
+
+        assert!(ipfs.pin_rm(&cid, false).await.is_err());
+
+        ipfs.pin_add(&cid, false).await.unwrap();
+
+        assert!(ipfs.is_pinned(&cid));
+
+        ipfs.pin_rm(&cid, false).await.unwrap();
+
+        assert!(!ipfs.is_pinned(&cid));
+    }
+
+    #[tokio::test]
+    async fn pubsub_delivers_to_subscribers() {
+        let ipfs = MemoryBackend::new();
+
+        let mut stream = ipfs.pubsub_sub("chat");
+
+        ipfs.publish_from("peer", "chat", b"hi");
+        ipfs.pubsub_pub("chat", "Stopping").await.unwrap();
+
+        let msg = stream.next().await.unwrap().unwrap();
+
+        assert_eq!(msg.from.as_deref(), Some("peer"));
+        assert_eq!(msg.data.as_deref(), Some(&b"hi"[..]));
+
+        let msg = stream.next().await.unwrap().unwrap();
+
+        assert_eq!(msg.from, Some(ipfs.peer_id().await.unwrap()));
+    }
+}
@@ -0,0 +1,169 @@
+//! IPFS operations used by the actors and commands.
+//! Implemented by the go-ipfs daemon and, for tests, an in-memory store.
+
+mod daemon;
+#[cfg(test)]
+mod memory;
+
+#[cfg(test)]
+pub use memory::MemoryBackend;
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use tokio::io::AsyncRead;
+
+use futures_util::stream::BoxStream;
+
+use ipfs_api::response::Error;
+
+use linked_data::codec::Codec;
+
+use cid::Cid;
+
+/// Shared handle to an IPFS backend.
+pub type Ipfs = Arc<dyn Backend>;
+
+/// File content read as it is added, e.g. a request body.
+pub type FileReader = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+/// Handle to the local go-ipfs daemon.
+pub fn daemon() -> Ipfs {
+    Arc::new(daemon::Daemon::default())
+}
+
+/// Blocks, files and pins.
+#[async_trait]
+pub trait DagStore: Send + Sync {
+    /// Store a node already serialized with this codec.
+    async fn dag_put(&self, data: Vec<u8>, codec: Codec) -> Result<Cid, Error>;
+
+    /// Serialized node at this path, following links. <cid>/time/hour/0
+    async fn dag_get(&self, path: &str) -> Result<(Codec, Vec<u8>), Error>;
+
+    /// Store a file as CIDv1 without pinning it.
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error>;
+
+    /// Like add but without holding the whole file in memory.
+    async fn add_reader(&self, reader: FileReader) -> Result<Cid, Error>;
+
+    /// File at this path, following links.
+    async fn cat(&self, path: &str) -> Result<Vec<u8>, Error>;
+
+    async fn pin_add(&self, cid: &Cid, recursive: bool) -> Result<(), Error>;
+
+    async fn pin_rm(&self, cid: &Cid, recursive: bool) -> Result<(), Error>;
+}
+
+/// IPNS keys and records.
+#[async_trait]
+pub trait Naming: Send + Sync {
+    /// IPNS name of this key, if it exists.
+    async fn key_id(&self, key: &str) -> Result<Option<String>, Error>;
+
+    /// Generate a new key. Return its IPNS name.
+    async fn key_gen(&self, key: &str) -> Result<String, Error>;
+
+    /// Publish this CID under this key for 6 months.
+    async fn name_publish(&self, cid: &Cid, key: &str) -> Result<(), Error>;
+
+    /// CID published under this name. /ipns/<name> or <name>
+    async fn name_resolve(&self, name: &str) -> Result<Cid, Error>;
+}
+
+/// GossipSub messages.
+#[derive(Debug, Clone)]
+pub struct PubSubMessage {
+    pub from: Option<String>,
+    pub data: Option<Vec<u8>>,
+}
+
+#[async_trait]
+pub trait PubSub: Send + Sync {
+    async fn peer_id(&self) -> Result<String, Error>;
+
+    async fn pubsub_pub(&self, topic: &str, data: &str) -> Result<(), Error>;
+
+    fn pubsub_sub(&self, topic: &str) -> BoxStream<'static, Result<PubSubMessage, Error>>;
+}
+
+/// Everything the actors and commands need.
+pub trait Backend: DagStore + Naming + PubSub {}
+
+impl<T> Backend for T where T: DagStore + Naming + PubSub {}
+
+#[async_trait]
+impl<T> DagStore for Arc<T>
+where
+    T: DagStore + ?Sized,
+{
+    async fn dag_put(&self, data: Vec<u8>, codec: Codec) -> Result<Cid, Error> {
+        (**self).dag_put(data, codec).await
+    }
+
+    async fn dag_get(&self, path: &str) -> Result<(Codec, Vec<u8>), Error> {
+        (**self).dag_get(path).await
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error> {
+        (**self).add(data).await
+    }
+
+    async fn add_reader(&self, reader: FileReader) -> Result<Cid, Error> {
+        (**self).add_reader(reader).await
+    }
+
+    async fn cat(&self, path: &str) -> Result<Vec<u8>, Error> {
+        (**self).cat(path).await
+    }
+
+    async fn pin_add(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        (**self).pin_add(cid, recursive).await
+    }
+
+    async fn pin_rm(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        (**self).pin_rm(cid, recursive).await
+    }
+}
+
+#[async_trait]
+impl<T> Naming for Arc<T>
+where
+    T: Naming + ?Sized,
+{
+    async fn key_id(&self, key: &str) -> Result<Option<String>, Error> {
+        (**self).key_id(key).await
+    }
+
+    async fn key_gen(&self, key: &str) -> Result<String, Error> {
+        (**self).key_gen(key).await
+    }
+
+    async fn name_publish(&self, cid: &Cid, key: &str) -> Result<(), Error> {
+        (**self).name_publish(cid, key).await
+    }
+
+    async fn name_resolve(&self, name: &str) -> Result<Cid, Error> {
+        (**self).name_resolve(name).await
+    }
+}
+
+#[async_trait]
+impl<T> PubSub for Arc<T>
+where
+    T: PubSub + ?Sized,
+{
+    async fn peer_id(&self) -> Result<String, Error> {
+        (**self).peer_id().await
+    }
+
+    async fn pubsub_pub(&self, topic: &str, data: &str) -> Result<(), Error> {
+        (**self).pubsub_pub(topic, data).await
+    }
+
+    fn pubsub_sub(&self, topic: &str) -> BoxStream<'static, Result<PubSubMessage, Error>> {
+        (**self).pubsub_sub(topic)
+    }
+}
@@ -1,14 +1,14 @@
-use crate::cli::content::FEED_KEY;
+use crate::backend::{daemon, DagStore, Ipfs, Naming, PubSub};
 use crate::cli::moderation::{BANS_KEY, MODS_KEY};
+use crate::error::Error;
 use crate::utils::config::Configuration;
-use crate::utils::dag_nodes::{ipfs_dag_put_node_async, search_keypairs, update_ipns};
-use serde::Serialize;
+use crate::utils::dag_nodes::{ipfs_dag_put_node_async, update_ipns};
+use crate::utils::feed::FEED_KEY;
 
-use ipfs_api::response::Error;
-use ipfs_api::IpfsClient;
-use ipfs_api::KeyType;
+use serde::Serialize;
 
 use linked_data::beacon::Topics;
+use linked_data::codec::Codec;
 use linked_data::feed::Feed;
 use linked_data::moderation::{Bans, Moderators};
 
@@ -46,28 +46,27 @@ pub async fn beacon_cli(cli: Beacon) {
     };
 
     if let Err(e) = res {
-        eprintln!("❗ IPFS: {}", e);
+        eprintln!("❗ {}", e);
     }
 }
 
 async fn create_beacon(args: Create) -> Result<(), Error> {
-    let ipfs = IpfsClient::default();
-
-    let mut key_list = ipfs.key_list().await?;
-
-    let bans = create_ipns_link::<Bans>(&ipfs, "Bans", BANS_KEY, &mut key_list).await?;
-    let mods = create_ipns_link::<Moderators>(&ipfs, "Mods", MODS_KEY, &mut key_list).await?;
-    let content_feed =
-        create_ipns_link::<Feed>(&ipfs, "Content Feed", FEED_KEY, &mut key_list).await?;
-    //let comments = create_ipns_link(&ipfs, "Comments", COMMENT_KEY, &mut key_list).await?;
-
-    println!("Creating Beacon...");
+    let ipfs = daemon();
 
     let mut config = match Configuration::from_file().await {
         Ok(conf) => conf,
         Err(_) => Configuration::default(),
     };
 
+    let codec = config.codec;
+
+    let bans = create_ipns_link::<Bans>(&ipfs, "Bans", BANS_KEY, codec).await?;
+    let mods = create_ipns_link::<Moderators>(&ipfs, "Mods", MODS_KEY, codec).await?;
+    let content_feed = create_ipns_link::<Feed>(&ipfs, "Content Feed", FEED_KEY, codec).await?;
+    //let comments = create_ipns_link(&ipfs, "Comments", COMMENT_KEY).await?;
+
+    println!("Creating Beacon...");
+
     config.chat.topic = args.chat;
     config.chat.mods = mods.clone();
     config.chat.bans = bans.clone();
@@ -82,8 +81,7 @@ async fn create_beacon(args: Create) -> Result<(), Error> {
         //comments: args.comments,
     };
 
-    let res = ipfs.id(None).await?;
-    let peer_id = res.id;
+    let peer_id = ipfs.peer_id().await?;
 
     #[cfg(debug_assertions)]
     println!("IPFS: peer id => {}", &peer_id);
@@ -97,9 +95,9 @@ async fn create_beacon(args: Create) -> Result<(), Error> {
         //comments,
     };
 
-    let cid = ipfs_dag_put_node_async(&ipfs, &beacon).await?;
+    let cid = ipfs_dag_put_node_async(&ipfs, &beacon, codec).await?;
 
-    ipfs.pin_add(&cid.to_string(), false).await?;
+    ipfs.pin_add(&cid, false).await?;
 
     println!("✅ Beacon Created => ipfs://{}", &cid.to_string());
 
@@ -107,24 +105,24 @@ async fn create_beacon(args: Create) -> Result<(), Error> {
 }
 
 async fn create_ipns_link<T>(
-    ipfs: &IpfsClient,
+    ipfs: &Ipfs,
     name: &str,
     key: &str,
-    key_list: &mut ipfs_api::response::KeyPairList,
+    codec: Codec,
 ) -> Result<String, Error>
 where
     T: Default + Serialize,
 {
-    let mut link = match search_keypairs(key, key_list) {
-        Some(kp) => kp.id,
+    let mut link = match ipfs.key_id(key).await? {
+        Some(id) => id,
         None => {
             println!("Generating Key...");
 
-            let ipns_link = generate_key(ipfs, key).await?;
+            let ipns_link = ipfs.key_gen(key).await?;
 
             println!("Updating IPNS...");
 
-            update_ipns(ipfs, &key, &T::default()).await?;
+            update_ipns(ipfs, &key, &T::default(), codec).await?;
 
             ipns_link
         }
@@ -136,15 +134,3 @@ where
 
     Ok(link)
 }
-
-async fn generate_key(ipfs: &IpfsClient, key: &str) -> Result<String, Error> {
-    let res = ipfs
-        .key_gen(
-            key,
-            KeyType::Ed25519,
-            64, /* Don't think this does anything... */
-        )
-        .await?;
-
-    Ok(res.id)
-}
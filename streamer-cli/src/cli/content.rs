@@ -1,22 +1,17 @@
-use crate::utils::dag_nodes::{
-    ipfs_dag_get_node_async, ipfs_dag_put_node_async, search_keypairs, update_ipns,
-};
-
-use std::convert::TryFrom;
-
-use ipfs_api::response::Error;
-use ipfs_api::IpfsClient;
+use crate::backend::{daemon, DagStore, Ipfs};
+use crate::error::Error;
+use crate::utils::config::Configuration;
+use crate::utils::dag_nodes::{ipfs_dag_get_node_async, ipfs_dag_put_node_async, update_ipns};
+use crate::utils::feed::{add_content_to_feed, get_feed, FEED_KEY};
 
 use linked_data::blog::FullPost;
-use linked_data::feed::Feed;
-use linked_data::video::{DayNode, HourNode, MinuteNode, VideoMetadata};
+use linked_data::feed::Media;
+use linked_data::video::{ClipNode, DayNode, HourNode, MinuteNode, TimecodeNode, VideoMetadata};
 
 use cid::Cid;
 
 use structopt::StructOpt;
 
-pub const FEED_KEY: &str = "feed";
-
 #[derive(Debug, StructOpt)]
 pub struct ContentFeed {
     #[structopt(subcommand)]
@@ -40,6 +35,7 @@ pub async fn content_feed_cli(cli: ContentFeed) {
         Command::Add(add) => match add {
             AddContent::Blog(blog) => add_blog(blog).await,
             AddContent::Video(video) => add_video(video).await,
+            AddContent::Clip(clip) => add_clip(clip).await,
         },
         Command::Update(update) => match update {
             UpdateContent::Blog(blog) => update_blog(blog).await,
@@ -49,7 +45,7 @@ pub async fn content_feed_cli(cli: ContentFeed) {
     };
 
     if let Err(e) = res {
-        eprintln!("❗ IPFS: {}", e);
+        eprintln!("❗ {}", e);
     }
 }
 
@@ -60,20 +56,9 @@ enum AddContent {
 
     /// Create new video post.
     Video(AddVideo),
-}
-
-async fn add_content_to_feed(ipfs: &IpfsClient, new_cid: Cid) -> Result<usize, Error> {
-    println!("Updating Content Feed...");
-
-    let mut feed = get_feed(ipfs).await?;
 
-    ipfs.pin_add(&new_cid.to_string(), true).await?;
-
-    feed.content.push(new_cid.into());
-
-    update_ipns(&ipfs, &FEED_KEY, &feed).await?;
-
-    Ok(feed.content.len() - 1)
+    /// Create new clip of an existing video.
+    Clip(AddClip),
 }
 
 #[derive(Debug, StructOpt)]
@@ -92,7 +77,8 @@ pub struct AddPost {
 }
 
 async fn add_blog(command: AddPost) -> Result<(), Error> {
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let AddPost {
         title,
@@ -100,13 +86,13 @@ async fn add_blog(command: AddPost) -> Result<(), Error> {
         content,
     } = command;
 
-    let metadata = FullPost::create(title, image, content);
+    let metadata = Media::Blog(FullPost::create(title, image, content));
 
-    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata).await?;
+    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata, codec).await?;
 
     println!("New Post CID => {}", &new_cid.to_string());
 
-    let index = add_content_to_feed(&ipfs, new_cid).await?;
+    let index = add_content_to_feed(&ipfs, new_cid, codec).await?;
 
     println!("✅ Weblog Post Added In Content Feed At Index {}", index);
 
@@ -129,7 +115,8 @@ pub struct AddVideo {
 }
 
 async fn add_video(command: AddVideo) -> Result<(), Error> {
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let AddVideo {
         title,
@@ -139,19 +126,71 @@ async fn add_video(command: AddVideo) -> Result<(), Error> {
 
     let duration = get_video_duration(&ipfs, &video).await?;
 
-    let metadata = VideoMetadata::create(title, duration, image, video);
+    let metadata = Media::Video(VideoMetadata::create(title, duration, image, video));
 
-    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata).await?;
+    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata, codec).await?;
 
     println!("New Post CID => {}", &new_cid.to_string());
 
-    let index = add_content_to_feed(&ipfs, new_cid).await?;
+    let index = add_content_to_feed(&ipfs, new_cid, codec).await?;
 
     println!("✅ Video Post Added In Content Feed At Index {}", index);
 
     Ok(())
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AddClip {
+    /// The new clip title.
+    #[structopt(short, long)]
+    title: Option<String>,
+
+    /// The original video timecode CID.
+    #[structopt(short, long)]
+    video: Cid,
+
+    /// The clip start timecode in seconds.
+    #[structopt(short, long)]
+    start: f64,
+
+    /// The clip end timecode in seconds.
+    #[structopt(short, long)]
+    end: f64,
+}
+
+async fn add_clip(command: AddClip) -> Result<(), Error> {
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
+
+    let AddClip {
+        title,
+        video,
+        start,
+        end,
+    } = command;
+
+    let duration = get_video_duration(&ipfs, &video).await?;
+
+    if start < 0.0 || end <= start || end > duration {
+        return Err(Error::Invalid(format!(
+            "Invalid Clip Range {}s to {}s For Video Of {}s",
+            start, end, duration
+        )));
+    }
+
+    let metadata = Media::Clip(ClipNode::create(title, video, start, end));
+
+    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata, codec).await?;
+
+    println!("New Post CID => {}", &new_cid.to_string());
+
+    let index = add_content_to_feed(&ipfs, new_cid, codec).await?;
+
+    println!("✅ Clip Post Added In Content Feed At Index {}", index);
+
+    Ok(())
+}
+
 #[derive(Debug, StructOpt)]
 enum UpdateContent {
     /// Create new blog post.
@@ -181,7 +220,8 @@ pub struct UpdatePost {
 }
 
 async fn update_blog(command: UpdatePost) -> Result<(), Error> {
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut feed = get_feed(&ipfs).await?;
 
@@ -194,26 +234,26 @@ async fn update_blog(command: UpdatePost) -> Result<(), Error> {
 
     let old_cid = match feed.content.get(index) {
         Some(mt) => mt.link,
-        None => return Err(Error::Uncategorized("Blog Post Index Not Found".into())),
+        None => return Err(Error::Invalid("Blog Post Index Not Found".into())),
     };
 
-    ipfs.pin_rm(&old_cid.to_string(), true).await?;
+    ipfs.pin_rm(&old_cid, true).await?;
 
     let mut metadata: FullPost = ipfs_dag_get_node_async(&ipfs, &old_cid.to_string()).await?;
 
     metadata.update(title, image, content);
 
-    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata).await?;
+    let new_cid = ipfs_dag_put_node_async(&ipfs, &Media::Blog(metadata), codec).await?;
 
     println!("New Post CID => {}", &new_cid.to_string());
 
     println!("Updating Content Feed...");
 
-    ipfs.pin_add(&new_cid.to_string(), true).await?;
+    ipfs.pin_add(&new_cid, true).await?;
 
     feed.content[index] = new_cid.into();
 
-    update_ipns(&ipfs, &FEED_KEY, &feed).await?;
+    update_ipns(&ipfs, &FEED_KEY, &feed, codec).await?;
 
     println!("✅ Weblog Post Updated In Content Feed At Index {}", index);
 
@@ -240,7 +280,8 @@ pub struct UpdateVideo {
 }
 
 async fn update_video(command: UpdateVideo) -> Result<(), Error> {
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut feed = get_feed(&ipfs).await?;
 
@@ -253,7 +294,7 @@ async fn update_video(command: UpdateVideo) -> Result<(), Error> {
 
     let old_cid = match feed.content.get(index) {
         Some(mt) => mt.link,
-        None => return Err(Error::Uncategorized("Video Index Not Found".into())),
+        None => return Err(Error::Invalid("Video Index Not Found".into())),
     };
 
     let mut metadata: VideoMetadata = ipfs_dag_get_node_async(&ipfs, &old_cid.to_string()).await?;
@@ -265,7 +306,7 @@ async fn update_video(command: UpdateVideo) -> Result<(), Error> {
 
     metadata.update(title, image, video, duration);
 
-    let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata).await?;
+    let new_cid = ipfs_dag_put_node_async(&ipfs, &Media::Video(metadata), codec).await?;
 
     println!("New Post CID => {}", &new_cid.to_string());
 
@@ -273,7 +314,7 @@ async fn update_video(command: UpdateVideo) -> Result<(), Error> {
 
     feed.content[index] = new_cid.into();
 
-    update_ipns(&ipfs, &FEED_KEY, &feed).await?;
+    update_ipns(&ipfs, &FEED_KEY, &feed, codec).await?;
 
     println!("✅ Video Post Updated In Content Feed At Index {}", index);
 
@@ -289,47 +330,31 @@ pub struct DeleteContent {
 
 async fn delete_content(command: DeleteContent) -> Result<(), Error> {
     println!("Deleting Content...");
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut feed = get_feed(&ipfs).await?;
 
     let link = feed.content.remove(command.index);
 
-    ipfs.pin_rm(&link.link.to_string(), true).await?;
+    ipfs.pin_rm(&link.link, true).await?;
 
-    update_ipns(&ipfs, &FEED_KEY, &feed).await?;
+    update_ipns(&ipfs, &FEED_KEY, &feed, codec).await?;
 
     println!("✅ Post In Content Feed At Index {} Deleted", command.index);
 
     Ok(())
 }
 
-async fn get_feed(ipfs: &IpfsClient) -> Result<Feed, Error> {
-    let mut res = ipfs.key_list().await?;
-
-    let keypair = match search_keypairs(&FEED_KEY, &mut res) {
-        Some(kp) => kp,
-        None => return Err(Error::Uncategorized("Key Not Found".into())),
-    };
-
-    #[cfg(debug_assertions)]
-    println!("IPNS: key => {} {}", &keypair.name, &keypair.id);
-
-    let res = ipfs.name_resolve(Some(&keypair.id), false, false).await?;
+pub async fn get_video_duration(ipfs: &Ipfs, video: &Cid) -> Result<f64, Error> {
+    let root: TimecodeNode = ipfs_dag_get_node_async(ipfs, &video.to_string()).await?;
 
-    let cid = Cid::try_from(res.path).expect("Invalid Cid");
-
-    ipfs.pin_rm(&cid.to_string(), false).await?;
-
-    let node = ipfs_dag_get_node_async(ipfs, &cid.to_string()).await?;
-
-    Ok(node)
-}
-
-async fn get_video_duration(ipfs: &IpfsClient, video: &Cid) -> Result<f64, Error> {
-    let path = format!("{}/time", video.to_string());
+    if let Some(duration) = root.duration {
+        return Ok(duration);
+    }
 
-    let days: DayNode = ipfs_dag_get_node_async(ipfs, &path).await?;
+    // Videos archived before exact durations are approximated by counting seconds
+    let days: DayNode = ipfs_dag_get_node_async(ipfs, &root.timecode.link.to_string()).await?;
 
     let mut duration = 0.0;
 
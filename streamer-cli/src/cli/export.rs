@@ -0,0 +1,357 @@
+use crate::backend::{daemon, DagStore, Ipfs};
+use crate::error::Error;
+use crate::server::{M4S, MP4};
+use crate::utils::dag_nodes::ipfs_dag_get_node_async;
+use crate::utils::playlists::{master_playlist, media_playlist, MASTER_PLAYLIST, MEDIA_PLAYLIST};
+use crate::utils::segments::{get_video_segments, Segment};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use linked_data::dash::{media_presentation, Period};
+use linked_data::video::{SetupNode, Track};
+
+use cid::Cid;
+
+use structopt::StructOpt;
+
+/// Muxes the quality and audio tracks of MP4 exports.
+const FFMPEG: &str = "ffmpeg";
+
+#[derive(Debug, StructOpt)]
+pub struct Export {
+    /// The video timecode CID or the latest video node CID of a stream.
+    video: Option<Cid>,
+
+    /// The video track to export. Audio is always included.
+    #[structopt(short, long)]
+    quality: Option<String>,
+
+    /// Output directory.
+    #[structopt(short, long, parse(from_os_str), default_value = ".")]
+    out: PathBuf,
+
+    /// Save an HLS directory instead of an MP4 file.
+    #[structopt(long)]
+    hls: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Manifest>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Manifest {
+    /// Print an MPEG-DASH manifest of a video.
+    Dash(Dash),
+}
+
+pub async fn export_cli(cli: Export) {
+    let res = match cli {
+        Export {
+            cmd: Some(Manifest::Dash(dash)),
+            ..
+        } => export_dash(dash).await,
+        Export {
+            video: Some(video),
+            quality: Some(quality),
+            out,
+            hls,
+            cmd: None,
+        } => export_files(video, quality, out, hls).await,
+        Export { video: None, .. } => Err(Error::Invalid("Video CID required".into())),
+        Export { quality: None, .. } => Err(Error::Invalid(
+            "Quality required, --quality <QUALITY>".into(),
+        )),
+    };
+
+    if let Err(e) = res {
+        eprintln!("❗ {}", e);
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Dash {
+    /// The video timecode CID or the latest video node CID of a stream.
+    video: Cid,
+
+    /// IPFS gateway used in segment URLs.
+    #[structopt(short, long, default_value = "http://127.0.0.1:8080")]
+    gateway: String,
+}
+
+async fn export_dash(args: Dash) -> Result<(), Error> {
+    let ipfs = daemon();
+
+    let segments = get_video_segments(&ipfs, args.video).await?.segments;
+
+    let mut periods: Vec<Period> = Vec::new();
+    let mut setup = None;
+
+    for segment in segments {
+        let cid = match segment.setup {
+            Some(cid) => cid,
+            None => {
+                eprintln!("❗ Segment {} has no setup, skipped", segment.video);
+                continue;
+            }
+        };
+
+        // New period each time the setup changes
+        if setup != Some(cid) {
+            let node = ipfs_dag_get_node_async(&ipfs, &cid.to_string()).await?;
+
+            periods.push(Period {
+                setup: node,
+                segments: Vec::new(),
+            });
+
+            setup = Some(cid);
+        }
+
+        if let Some(period) = periods.last_mut() {
+            period.segments.push((segment.video, segment.duration));
+        }
+    }
+
+    if periods.is_empty() {
+        return Err(Error::Invalid("Video has no segments".into()));
+    }
+
+    print!("{}", media_presentation(&periods, &args.gateway));
+
+    Ok(())
+}
+
+async fn export_files(video: Cid, quality: String, out: PathBuf, hls: bool) -> Result<(), Error> {
+    let ipfs = daemon();
+
+    let segments: Vec<Segment> = get_video_segments(&ipfs, video)
+        .await?
+        .segments
+        .into_iter()
+        .filter(|segment| {
+            if segment.setup.is_none() {
+                eprintln!("❗ Segment {} has no setup, skipped", segment.video);
+            }
+
+            segment.setup.is_some()
+        })
+        .collect();
+
+    let mut setups = HashMap::new();
+
+    for segment in segments.iter() {
+        if let Some(cid) = segment.setup {
+            if !setups.contains_key(&cid) {
+                let node: SetupNode = ipfs_dag_get_node_async(&ipfs, &cid.to_string()).await?;
+
+                setups.insert(cid, node);
+            }
+        }
+    }
+
+    let setup = match segments.first().and_then(|segment| segment.setup) {
+        Some(cid) => &setups[&cid],
+        None => return Err(Error::Invalid("Video has no segments".into())),
+    };
+
+    let tracks: Vec<&Track> = setup
+        .tracks
+        .iter()
+        .filter(|track| track.name == quality || track.name == "audio")
+        .collect();
+
+    if !tracks.iter().any(|track| track.name == quality) {
+        return Err(Error::Invalid(format!("Quality {} not found", quality)));
+    }
+
+    fs::create_dir_all(&out).await?;
+
+    if hls {
+        for track in tracks.iter() {
+            println!("Exporting {}...", track.name);
+
+            write_hls_track(&ipfs, &segments, &setups, &track.name, &out).await?;
+        }
+
+        fs::write(out.join(MASTER_PLAYLIST), master_playlist(&tracks)).await?;
+    } else {
+        // An MP4 file has one setup, a new file is started each time it changes
+        let parts = split_setups(&segments);
+
+        for (index, part) in parts.iter().enumerate() {
+            let name = match parts.len() {
+                1 => format!("{}.{}", quality, MP4),
+                _ => format!("{}.{}.{}", quality, index, MP4),
+            };
+
+            println!("Exporting {}...", name);
+
+            write_mp4(&ipfs, part, &setups, &quality, &out.join(name)).await?;
+        }
+    }
+
+    println!("✅ Video Exported To {}", out.display());
+
+    Ok(())
+}
+
+/// Consecutive segments sharing the same setup.
+fn split_setups(segments: &[Segment]) -> Vec<&[Segment]> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for index in 1..segments.len() {
+        if segments[index].setup != segments[index - 1].setup {
+            parts.push(&segments[start..index]);
+            start = index;
+        }
+    }
+
+    if start < segments.len() {
+        parts.push(&segments[start..]);
+    }
+
+    parts
+}
+
+/// Mux the quality and the audio of segments sharing one setup into an MP4 file.
+async fn write_mp4(
+    ipfs: &Ipfs,
+    segments: &[Segment],
+    setups: &HashMap<Cid, SetupNode>,
+    quality: &str,
+    path: &Path,
+) -> Result<(), Error> {
+    let has_audio = segments
+        .first()
+        .and_then(|segment| segment.setup)
+        .and_then(|cid| setups.get(&cid))
+        .map_or(false, |setup| {
+            setup.tracks.iter().any(|track| track.name == "audio")
+        });
+
+    let video = path.with_extension(M4S);
+
+    write_fragmented_track(ipfs, segments, setups, quality, &video).await?;
+
+    if !has_audio {
+        return fs::rename(&video, path).await.map_err(Error::Io);
+    }
+
+    let audio = path.with_extension(format!("audio.{}", M4S));
+
+    if let Err(e) = write_fragmented_track(ipfs, segments, setups, "audio", &audio).await {
+        let _ = fs::remove_file(&video).await;
+
+        return Err(e);
+    }
+
+    let status = Command::new(FFMPEG)
+        .args(&["-y", "-loglevel", "error", "-i"])
+        .arg(&video)
+        .arg("-i")
+        .arg(&audio)
+        .args(&["-map", "0:v", "-map", "1:a", "-c", "copy"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .status()
+        .await;
+
+    let _ = fs::remove_file(&video).await;
+    let _ = fs::remove_file(&audio).await;
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Invalid(format!("{} exited with {}", FFMPEG, status))),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// Concatenate the initialization segment and the media segments of one track.
+/// Segments must share the same setup.
+async fn write_fragmented_track(
+    ipfs: &Ipfs,
+    segments: &[Segment],
+    setups: &HashMap<Cid, SetupNode>,
+    name: &str,
+    path: &Path,
+) -> Result<(), Error> {
+    let mut file = File::create(path).await?;
+
+    let setup = segments.first().and_then(|segment| segment.setup);
+
+    let data = init_segment(ipfs, setups, setup, name).await?;
+
+    file.write_all(&data).await?;
+
+    for segment in segments {
+        let data = cat(ipfs, format!("{}/track/{}", segment.video, name)).await?;
+
+        file.write_all(&data).await?;
+    }
+
+    file.sync_all().await.map_err(Error::Io)
+}
+
+/// Save segments as <name>/<index>.m4s and initialization segments as <name>/<index>.mp4
+async fn write_hls_track(
+    ipfs: &Ipfs,
+    segments: &[Segment],
+    setups: &HashMap<Cid, SetupNode>,
+    name: &str,
+    out: &Path,
+) -> Result<(), Error> {
+    let dir = out.join(name);
+
+    fs::create_dir_all(&dir).await?;
+
+    let mut setup = None;
+
+    for (index, segment) in segments.iter().enumerate() {
+        if segment.setup != setup {
+            setup = segment.setup;
+
+            let data = init_segment(ipfs, setups, setup, name).await?;
+
+            fs::write(dir.join(format!("{}.{}", index, MP4)), data).await?;
+        }
+
+        let data = cat(ipfs, format!("{}/track/{}", segment.video, name)).await?;
+
+        fs::write(dir.join(format!("{}.{}", index, M4S)), data).await?;
+    }
+
+    fs::write(dir.join(MEDIA_PLAYLIST), media_playlist(segments, false))
+        .await
+        .map_err(Error::Io)
+}
+
+async fn init_segment(
+    ipfs: &Ipfs,
+    setups: &HashMap<Cid, SetupNode>,
+    setup: Option<Cid>,
+    name: &str,
+) -> Result<Vec<u8>, Error> {
+    let track = setup
+        .and_then(|cid| setups.get(&cid))
+        .and_then(|setup| setup.tracks.iter().find(|track| track.name == name));
+
+    let cid = match track {
+        Some(track) => track.initialization_segment.link,
+        None => return Err(Error::Invalid(format!("Track {} missing from setup", name))),
+    };
+
+    cat(ipfs, cid.to_string()).await
+}
+
+async fn cat(ipfs: &Ipfs, path: String) -> Result<Vec<u8>, Error> {
+    let data = ipfs.cat(&path).await?;
+
+    Ok(data)
+}
@@ -1,20 +1,43 @@
-use crate::actors::{Archivist, SetupAggregator, VideoAggregator};
-use crate::server::start_server;
+use crate::actors::{Archivist, Encoder, EncoderInput, SetupAggregator, VideoAggregator};
+use crate::backend::{daemon, PubSub};
+use crate::server::{start_server, StreamChannels, Streams, MAIN_STREAM};
 use crate::utils::config::Configuration;
 
-use tokio::sync::mpsc::unbounded_channel;
+use std::net::TcpListener;
+use std::path::PathBuf;
 
-use ipfs_api::IpfsClient;
+use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
+
+use cid::Cid;
 
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
-pub struct File {}
+pub struct File {
+    /// Video file to encode. Without it, run your own FFMPEG script.
+    #[structopt(parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Title of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    title: Option<String>,
+
+    /// Thumbnail image CID of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    image: Option<Cid>,
+}
+
+pub async fn file_cli(file: File) {
+    let File {
+        input,
+        title,
+        image,
+    } = file;
 
-pub async fn file_cli(_file: File) {
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
 
-    if let Err(e) = ipfs.id(None).await {
+    if let Err(e) = ipfs.peer_id().await {
         eprintln!("❗ IPFS must be started beforehand. {}", e);
         return;
     }
@@ -31,18 +54,39 @@ pub async fn file_cli(_file: File) {
 
     let Configuration {
         input_socket_addr,
+        stream_key,
+        codec,
+        queue_depth,
+        streams: stream_ids,
         mut archive,
         mut video,
         chat,
+        encoder,
     } = config;
 
+    let listener = match TcpListener::bind(input_socket_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("❗ Server: cannot listen on {}. {}", input_socket_addr, e);
+            return;
+        }
+    };
+
     let mut handles = Vec::with_capacity(4);
 
-    let (archive_tx, archive_rx) = unbounded_channel();
+    let (archive_tx, archive_rx) = channel(queue_depth);
 
     archive.archive_live_chat = false;
 
-    let mut archivist = Archivist::new(ipfs.clone(), archive_rx);
+    if title.is_some() {
+        archive.title = title;
+    }
+
+    if let Some(image) = image {
+        archive.image = Some(image);
+    }
+
+    let mut archivist = Archivist::new(ipfs.clone(), archive_rx, archive.clone(), codec, None);
 
     let archive_handle = tokio::spawn(async move {
         archivist.start().await;
@@ -50,11 +94,28 @@ pub async fn file_cli(_file: File) {
 
     handles.push(archive_handle);
 
-    let (video_tx, video_rx) = unbounded_channel();
+    let (video_tx, video_rx) = channel(queue_depth);
+    let (end_tx, end_rx) = oneshot::channel();
 
     video.pubsub_enable = false;
 
-    let mut video = VideoAggregator::new(ipfs.clone(), video_rx, Some(archive_tx.clone()), video);
+    let streams = Streams::new(
+        ipfs.clone(),
+        codec,
+        Some(archive),
+        video.clone(),
+        queue_depth,
+        stream_ids,
+    );
+
+    let mut video = VideoAggregator::new(
+        ipfs.clone(),
+        video_rx,
+        Some(archive_tx.clone()),
+        video,
+        codec,
+        Some(end_tx),
+    );
 
     let video_handle = tokio::spawn(async move {
         video.start().await;
@@ -62,9 +123,9 @@ pub async fn file_cli(_file: File) {
 
     handles.push(video_handle);
 
-    let (setup_tx, setup_rx) = unbounded_channel();
+    let (setup_tx, setup_rx) = channel(queue_depth);
 
-    let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone());
+    let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone(), codec);
 
     let setup_handle = tokio::spawn(async move {
         setup.start().await;
@@ -72,23 +133,54 @@ pub async fn file_cli(_file: File) {
 
     handles.push(setup_handle);
 
+    let channels = StreamChannels {
+        video_tx,
+        setup_tx,
+        archive_tx: Some(archive_tx),
+    };
+
+    streams.insert(MAIN_STREAM, channels);
+
+    let streams_clone = streams.clone();
+    let server_key = stream_key.clone();
+
     let server_handle = tokio::spawn(async move {
         start_server(
-            input_socket_addr,
-            video_tx,
-            setup_tx,
-            Some(archive_tx),
+            listener,
+            streams_clone,
             ipfs,
             chat.topic,
+            Some(end_rx),
+            server_key,
+            None,
         )
         .await;
     });
 
     handles.push(server_handle);
 
+    if let Some(input) = input {
+        match encoder {
+            Some(encoder) => {
+                let input = EncoderInput::File(input);
+
+                let mut encoder = Encoder::new(encoder, input, input_socket_addr, stream_key);
+
+                let encoder_handle = tokio::spawn(async move {
+                    encoder.start().await;
+                });
+
+                handles.push(encoder_handle);
+            }
+            None => eprintln!("❗ Encoder not configured, run your own FFMPEG script."),
+        }
+    }
+
     for handle in handles {
         if let Err(e) = handle.await {
             eprintln!("❗ Main: {}", e);
         }
     }
+
+    streams.join().await;
 }
@@ -0,0 +1,387 @@
+use crate::actors::{Archive, Archivist, SetupAggregator, SetupData, VideoAggregator, VideoData};
+use crate::backend::{daemon, DagStore, Ipfs, PubSub};
+use crate::error::Error;
+use crate::utils::config::Configuration;
+use crate::utils::playlists::MEDIA_PLAYLIST;
+use crate::utils::segment_names::playlist_variant;
+
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task::JoinHandle;
+
+use cid::Cid;
+
+use m3u8_rs::playlist::{
+    AlternativeMedia, AlternativeMediaType, MasterPlaylist, MediaPlaylist, Playlist, VariantStream,
+};
+
+use structopt::StructOpt;
+
+const MASTER_PLAYLIST: &str = "master.m3u8";
+const INIT_SEGMENT: &str = "init.mp4";
+
+/// Track name of the audio rendition.
+const AUDIO_TRACK: &str = "audio";
+
+/// Used when the rendition size is unknown, bits per second.
+const AUDIO_BANDWIDTH: usize = 160_000;
+
+/// Used when the variants do not list the audio codec.
+const AUDIO_CODEC: &str = "mp4a.40.2";
+
+const AUDIO_CODECS: [&str; 5] = ["mp4a", "opus", "ac-3", "ec-3", "fLaC"];
+
+#[derive(Debug, StructOpt)]
+pub struct Import {
+    /// Directory containing master.m3u8 and one directory per variant.
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Title of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    title: Option<String>,
+
+    /// Thumbnail image CID of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    image: Option<Cid>,
+}
+
+pub async fn import_cli(cli: Import) {
+    let Import { dir, title, image } = cli;
+
+    let ipfs = daemon();
+
+    if let Err(e) = ipfs.peer_id().await {
+        eprintln!("❗ IPFS must be started beforehand. {}", e);
+        return;
+    }
+
+    println!("Initialization...");
+
+    let Configuration {
+        codec,
+        queue_depth,
+        mut archive,
+        mut video,
+        ..
+    } = Configuration::from_file().await.unwrap_or_default();
+
+    let (archive_tx, archive_rx) = channel(queue_depth);
+
+    archive.archive_live_chat = false;
+
+    if title.is_some() {
+        archive.title = title;
+    }
+
+    if let Some(image) = image {
+        archive.image = Some(image);
+    }
+
+    let mut archivist = Archivist::new(ipfs.clone(), archive_rx, archive, codec, None);
+
+    let archive_handle = tokio::spawn(async move {
+        archivist.start().await;
+    });
+
+    let (video_tx, video_rx) = channel(queue_depth);
+
+    video.pubsub_enable = false;
+
+    let mut video = VideoAggregator::new(
+        ipfs.clone(),
+        video_rx,
+        Some(archive_tx.clone()),
+        video,
+        codec,
+        None,
+    );
+
+    let video_handle = tokio::spawn(async move {
+        video.start().await;
+    });
+
+    let (setup_tx, setup_rx) = channel(queue_depth);
+
+    let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone(), codec);
+
+    let setup_handle = tokio::spawn(async move {
+        setup.start().await;
+    });
+
+    if let Err(e) = import_directory(&ipfs, &dir, setup_tx, setup_handle, video_tx).await {
+        eprintln!("❗ Import: {}", e);
+    }
+
+    // Every node is minted once all senders are dropped
+    if let Err(e) = video_handle.await {
+        eprintln!("❗ Main: {}", e);
+    }
+
+    if let Err(e) = archive_tx.send(Archive::Finalize).await {
+        eprintln!("❗ Archive receiver hung up! Error: {}", e);
+    }
+
+    drop(archive_tx);
+
+    if let Err(e) = archive_handle.await {
+        eprintln!("❗ Main: {}", e);
+    }
+}
+
+/// Media playlist of a track read from disk.
+struct Rendition {
+    /// Track name, the folder of its files as seen by the actors.
+    name: String,
+
+    /// Media playlist on disk, URIs are relative to it.
+    path: PathBuf,
+
+    playlist: MediaPlaylist,
+}
+
+impl Rendition {
+    async fn read(dir: &Path, name: String, uri: &str) -> Result<Self, Error> {
+        let path = dir.join(uri);
+
+        match read_playlist(&path).await? {
+            Playlist::MediaPlaylist(playlist) => Ok(Self {
+                name,
+                path,
+                playlist,
+            }),
+            Playlist::MasterPlaylist(_) => Err(Error::Invalid(format!(
+                "{} is not a media playlist",
+                path.display()
+            ))),
+        }
+    }
+
+    fn disk_path(&self, uri: &str) -> PathBuf {
+        self.path.with_file_name(uri)
+    }
+
+    /// Files of flat layouts are moved in a folder named after the track. 720p30_024.m4s => 720p30/720p30_024.m4s
+    fn actor_path(&self, uri: &str) -> PathBuf {
+        let file = Path::new(uri).file_name().unwrap_or_else(|| uri.as_ref());
+
+        Path::new(&self.name).join(file)
+    }
+
+    /// Average bits per second, from the size of the segments.
+    async fn bandwidth(&self) -> Option<usize> {
+        let mut bytes = 0;
+        let mut seconds = 0.0;
+
+        for segment in self.playlist.segments.iter() {
+            bytes += fs::metadata(self.disk_path(&segment.uri)).await.ok()?.len();
+            seconds += segment.duration as f64;
+        }
+
+        if seconds <= 0.0 {
+            return None;
+        }
+
+        Some((bytes as f64 * 8.0 / seconds) as usize)
+    }
+}
+
+/// Add the setup then the segments of all variants in order.
+async fn import_directory(
+    ipfs: &Ipfs,
+    dir: &Path,
+    setup_tx: Sender<SetupData>,
+    setup_handle: JoinHandle<()>,
+    video_tx: Sender<VideoData>,
+) -> Result<(), Error> {
+    let master = match read_playlist(&dir.join(MASTER_PLAYLIST)).await? {
+        Playlist::MasterPlaylist(playlist) => playlist,
+        Playlist::MediaPlaylist(_) => {
+            return Err(Error::Invalid(format!(
+                "{} is not a master playlist",
+                MASTER_PLAYLIST
+            )))
+        }
+    };
+
+    let (renditions, master) = read_renditions(dir, master).await?;
+
+    if setup_tx.send(SetupData::Playlist(master)).await.is_err() {
+        return Err(Error::ChannelClosed("Setup"));
+    }
+
+    for rendition in renditions.iter() {
+        let init = rendition
+            .playlist
+            .segments
+            .iter()
+            .find_map(|segment| segment.map.as_ref())
+            .map(|map| map.uri.as_str())
+            .unwrap_or(INIT_SEGMENT);
+
+        let cid = add_file(ipfs, &rendition.disk_path(init)).await?;
+
+        let msg = SetupData::Segment((rendition.actor_path(init), cid));
+
+        if setup_tx.send(msg).await.is_err() {
+            return Err(Error::ChannelClosed("Setup"));
+        }
+    }
+
+    drop(setup_tx);
+
+    // The setup node must reach the video aggregator before any segment
+    if let Err(e) = setup_handle.await {
+        return Err(Error::Invalid(e.to_string()));
+    }
+
+    // Segment durations first, nodes are minted as soon as all tracks are added
+    for rendition in renditions.iter() {
+        let path = rendition.actor_path(MEDIA_PLAYLIST);
+        let msg = VideoData::Playlist((path, rendition.playlist.clone()));
+
+        if video_tx.send(msg).await.is_err() {
+            return Err(Error::ChannelClosed("Video"));
+        }
+    }
+
+    let count = renditions
+        .iter()
+        .map(|rendition| rendition.playlist.segments.len())
+        .max()
+        .unwrap_or_default();
+
+    println!("Importing {} Segments...", count);
+
+    for index in 0..count {
+        for rendition in renditions.iter() {
+            let segment = match rendition.playlist.segments.get(index) {
+                Some(segment) => segment,
+                None => continue,
+            };
+
+            let cid = add_file(ipfs, &rendition.disk_path(&segment.uri)).await?;
+
+            let msg = VideoData::Segment((rendition.actor_path(&segment.uri), cid));
+
+            if video_tx.send(msg).await.is_err() {
+                return Err(Error::ChannelClosed("Video"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the media playlists of all variants and of the default audio rendition.
+/// The master playlist is rewritten with one variant per track, audio included.
+async fn read_renditions(
+    dir: &Path,
+    master: MasterPlaylist,
+) -> Result<(Vec<Rendition>, MasterPlaylist), Error> {
+    let mut audio: Vec<&AlternativeMedia> = master
+        .alternatives
+        .iter()
+        .filter(|media| matches!(media.media_type, AlternativeMediaType::Audio))
+        .filter(|media| media.uri.is_some())
+        .collect();
+
+    audio.sort_by_key(|media| !media.default);
+
+    if audio.len() > 1 {
+        eprintln!(
+            "❗ Import: {} audio renditions, only {} is imported",
+            audio.len(),
+            audio[0].name
+        );
+    }
+
+    let audio_uri = audio.first().and_then(|media| media.uri.clone());
+
+    let mut renditions = Vec::with_capacity(master.variants.len() + 1);
+    let mut variants = Vec::with_capacity(master.variants.len() + 1);
+    let mut audio_codec = None;
+
+    for variant in master.variants.iter() {
+        let name = match playlist_variant(&variant.uri) {
+            Some(name) => name.to_owned(),
+            None => return Err(Error::Invalid(format!("Unknown variant {}", variant.uri))),
+        };
+
+        // Codecs of the audio rendition are listed with the video ones
+        let codecs = match (&audio_uri, &variant.codecs) {
+            (Some(_), Some(codecs)) => {
+                let (video, audio) = split_codecs(codecs);
+
+                audio_codec = audio_codec.or(audio);
+
+                Some(video)
+            }
+            _ => variant.codecs.clone(),
+        };
+
+        variants.push(VariantStream {
+            uri: format!("{}/{}", name, MEDIA_PLAYLIST),
+            codecs,
+            ..variant.clone()
+        });
+
+        renditions.push(Rendition::read(dir, name, &variant.uri).await?);
+    }
+
+    if let Some(uri) = audio_uri {
+        let rendition = Rendition::read(dir, AUDIO_TRACK.to_owned(), &uri).await?;
+
+        let bandwidth = rendition.bandwidth().await.unwrap_or(AUDIO_BANDWIDTH);
+
+        variants.push(VariantStream {
+            uri: format!("{}/{}", AUDIO_TRACK, MEDIA_PLAYLIST),
+            bandwidth: bandwidth.to_string(),
+            codecs: Some(audio_codec.unwrap_or_else(|| AUDIO_CODEC.to_owned())),
+            ..Default::default()
+        });
+
+        renditions.push(rendition);
+    }
+
+    let master = MasterPlaylist { variants, ..master };
+
+    Ok((renditions, master))
+}
+
+/// Split the codecs of a variant into video codecs and audio codec.
+/// avc1.64001f,mp4a.40.2 => (avc1.64001f, Some(mp4a.40.2))
+fn split_codecs(codecs: &str) -> (String, Option<String>) {
+    let (audio, video): (Vec<&str>, Vec<&str>) = codecs
+        .split(',')
+        .map(str::trim)
+        .partition(|codec| AUDIO_CODECS.iter().any(|prefix| codec.starts_with(prefix)));
+
+    (
+        video.join(","),
+        audio.first().map(|codec| codec.to_string()),
+    )
+}
+
+async fn read_playlist(path: &Path) -> Result<Playlist, Error> {
+    let data = fs::read(path)
+        .await
+        .map_err(|e| Error::Invalid(format!("{} {}", path.display(), e)))?;
+
+    match m3u8_rs::parse_playlist(&data) {
+        Ok((_, playlist)) => Ok(playlist),
+        Err(e) => Err(Error::Invalid(format!("{} {}", path.display(), e))),
+    }
+}
+
+async fn add_file(ipfs: &Ipfs, path: &Path) -> Result<Cid, Error> {
+    let data = fs::read(path)
+        .await
+        .map_err(|e| Error::Invalid(format!("{} {}", path.display(), e)))?;
+
+    let cid = ipfs.add(data).await?;
+
+    Ok(cid)
+}
@@ -1,5 +1,9 @@
 pub mod beacon;
 pub mod content;
+pub mod export;
 pub mod file;
+pub mod import;
 pub mod moderation;
+pub mod serve;
 pub mod stream;
+pub mod vod;
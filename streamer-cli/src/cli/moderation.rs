@@ -1,10 +1,10 @@
+use crate::backend::{daemon, DagStore, Naming};
+use crate::error::Error;
+use crate::utils::config::Configuration;
 use crate::utils::dag_nodes::{get_from_ipns, update_ipns};
 
 use hex::FromHex;
 
-use ipfs_api::response::Error;
-use ipfs_api::IpfsClient;
-
 use cid::Cid;
 
 use structopt::StructOpt;
@@ -34,7 +34,7 @@ pub async fn moderation_cli(cli: Moderation) {
     };
 
     if let Err(e) = res {
-        eprintln!("❗ IPFS: {}", e);
+        eprintln!("❗ {}", e);
     }
 }
 
@@ -72,17 +72,18 @@ pub struct Ban {
 }
 
 async fn ban_user(args: Ban) -> Result<(), Error> {
-    let address = parse_address(&args.address);
+    let address = parse_address(&args.address)?;
 
     println!("Banning User...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut ban_list: linked_data::moderation::Bans = get_from_ipns(&ipfs, BANS_KEY).await?;
 
     ban_list.banned.insert(address);
 
-    update_ipns(&ipfs, BANS_KEY, &ban_list).await?;
+    update_ipns(&ipfs, BANS_KEY, &ban_list, codec).await?;
 
     println!("✅ User {} Banned", args.address);
 
@@ -97,16 +98,17 @@ pub struct UnBan {
 }
 
 async fn unban_user(args: UnBan) -> Result<(), Error> {
-    let address = parse_address(&args.address);
+    let address = parse_address(&args.address)?;
 
     println!("Unbanning User...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut ban_list: linked_data::moderation::Bans = get_from_ipns(&ipfs, BANS_KEY).await?;
 
     if ban_list.banned.remove(&address) {
-        update_ipns(&ipfs, BANS_KEY, &ban_list).await?;
+        update_ipns(&ipfs, BANS_KEY, &ban_list, codec).await?;
 
         println!("✅ User {} Unbanned", args.address);
 
@@ -128,20 +130,13 @@ pub struct ReplaceBanList {
 async fn replace_ban_list(args: ReplaceBanList) -> Result<(), Error> {
     println!("Replacing Ban List...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
 
     let _ = get_from_ipns(&ipfs, BANS_KEY).await?;
 
-    ipfs.pin_add(&args.cid.to_string(), false).await?;
+    ipfs.pin_add(&args.cid, false).await?;
 
-    ipfs.name_publish(
-        &args.cid.to_string(),
-        true,
-        Some("4320h"), // 6 months
-        None,
-        Some(BANS_KEY),
-    )
-    .await?;
+    ipfs.name_publish(&args.cid, BANS_KEY).await?;
 
     println!(
         "✅ Previous Ban List Replaced with {}",
@@ -185,17 +180,18 @@ pub struct Mod {
 }
 
 async fn mod_user(args: Mod) -> Result<(), Error> {
-    let address = parse_address(&args.address);
+    let address = parse_address(&args.address)?;
 
     println!("Promoting User...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut mods_list: linked_data::moderation::Moderators = get_from_ipns(&ipfs, MODS_KEY).await?;
 
     mods_list.mods.insert(address);
 
-    update_ipns(&ipfs, MODS_KEY, &mods_list).await?;
+    update_ipns(&ipfs, MODS_KEY, &mods_list, codec).await?;
 
     println!("✅ User {} Promoted To Moderator Position", args.address);
 
@@ -210,15 +206,16 @@ pub struct UnMod {
 }
 
 async fn unmod_user(args: UnMod) -> Result<(), Error> {
-    let address = parse_address(&args.address);
+    let address = parse_address(&args.address)?;
     println!("Demoting Moderator...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
 
     let mut mod_list: linked_data::moderation::Moderators = get_from_ipns(&ipfs, MODS_KEY).await?;
 
     if mod_list.mods.remove(&address) {
-        update_ipns(&ipfs, MODS_KEY, &mod_list).await?;
+        update_ipns(&ipfs, MODS_KEY, &mod_list, codec).await?;
 
         println!("✅ Moderator {} Demoted", args.address);
 
@@ -240,20 +237,13 @@ pub struct ReplaceModList {
 async fn replace_mod_list(args: ReplaceModList) -> Result<(), Error> {
     println!("Replacing Moderator List...");
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
 
     let _ = get_from_ipns(&ipfs, MODS_KEY).await?;
 
-    ipfs.pin_add(&args.cid.to_string(), false).await?;
+    ipfs.pin_add(&args.cid, false).await?;
 
-    ipfs.name_publish(
-        &args.cid.to_string(),
-        true,
-        Some("4320h"), // 6 months
-        None,
-        Some(MODS_KEY),
-    )
-    .await?;
+    ipfs.name_publish(&args.cid, MODS_KEY).await?;
 
     println!(
         "✅ Previous Moderator List Replaced with {}",
@@ -263,10 +253,11 @@ async fn replace_mod_list(args: ReplaceModList) -> Result<(), Error> {
     Ok(())
 }
 
-fn parse_address(addrs: &str) -> [u8; 20] {
-    if let Some(end) = addrs.strip_prefix("0x") {
-        return <[u8; 20]>::from_hex(end).expect("Invalid Ethereum Address");
-    }
+fn parse_address(addrs: &str) -> Result<[u8; 20], Error> {
+    let hex = addrs.strip_prefix("0x").unwrap_or(addrs);
 
-    <[u8; 20]>::from_hex(&addrs).expect("Invalid Ethereum Address")
+    match <[u8; 20]>::from_hex(hex) {
+        Ok(address) => Ok(address),
+        Err(e) => Err(Error::Invalid(format!("Invalid Ethereum Address {}", e))),
+    }
 }
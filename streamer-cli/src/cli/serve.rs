@@ -0,0 +1,24 @@
+use crate::backend::{daemon, PubSub};
+use crate::server::start_gateway;
+
+use std::net::SocketAddr;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Serve {
+    /// Socket address used to serve playlists and segments.
+    #[structopt(short, long, default_value = "127.0.0.1:2527")]
+    addr: SocketAddr,
+}
+
+pub async fn serve_cli(cli: Serve) {
+    let ipfs = daemon();
+
+    if let Err(e) = ipfs.peer_id().await {
+        eprintln!("❗ IPFS must be started beforehand. {}", e);
+        return;
+    }
+
+    start_gateway(cli.addr, ipfs).await;
+}
@@ -1,10 +1,21 @@
-use crate::actors::{Archivist, ChatAggregator, SetupAggregator, VideoAggregator};
-use crate::server::start_server;
+use crate::actors::{
+    Archivist, ChatAggregator, Encoder, EncoderInput, SetupAggregator, VideoAggregator,
+};
+use crate::backend::{daemon, PubSub};
+use crate::server::{start_rtmp_server, start_server, StreamChannels, Streams, Whip, MAIN_STREAM};
 use crate::utils::config::Configuration;
+use crate::utils::journal::{Journal, JournalEntry};
 
-use tokio::sync::mpsc::unbounded_channel;
+use std::io::stdin;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
 
-use ipfs_api::IpfsClient;
+use tokio::sync::mpsc::channel;
+use tokio::task::spawn_blocking;
+
+use linked_data::video::SecondNode;
+
+use cid::Cid;
 
 use structopt::StructOpt;
 
@@ -17,17 +28,42 @@ pub struct Stream {
     /// Disable all archiving.
     #[structopt(long)]
     no_archive: bool,
+
+    /// Do not start the encoder, use your own FFMPEG script instead.
+    #[structopt(long)]
+    no_encoder: bool,
+
+    /// Receive H.264 & AAC from your broadcast software and archive it as is, without FFMPEG.
+    #[structopt(long)]
+    passthrough: bool,
+
+    /// Address to listen on for your broadcast software in passthrough mode.
+    #[structopt(long, default_value = "127.0.0.1:2525")]
+    rtmp_addr: SocketAddr,
+
+    /// Title of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    title: Option<String>,
+
+    /// Thumbnail image CID of the video added to your content feed once archived.
+    #[structopt(short, long)]
+    image: Option<Cid>,
 }
 
 pub async fn stream_cli(stream: Stream) {
     let Stream {
         no_chat,
         no_archive,
+        no_encoder,
+        passthrough,
+        rtmp_addr,
+        title,
+        image,
     } = stream;
 
-    let ipfs = IpfsClient::default();
+    let ipfs = daemon();
 
-    if ipfs.id(None).await.is_err() {
+    if ipfs.peer_id().await.is_err() {
         eprintln!("❗ IPFS must be started beforehand. Aborting...");
         return;
     }
@@ -44,32 +80,67 @@ pub async fn stream_cli(stream: Stream) {
 
     let Configuration {
         input_socket_addr,
+        stream_key,
+        codec,
+        queue_depth,
+        streams: stream_ids,
         mut archive,
         mut video,
         chat,
+        encoder,
     } = config;
 
+    let listener = match TcpListener::bind(input_socket_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("❗ Server: cannot listen on {}. {}", input_socket_addr, e);
+            return;
+        }
+    };
+
     let mut handles = Vec::with_capacity(4);
 
     let topic = chat.topic.clone();
 
     let archive_tx = {
         if !no_archive {
-            let (archive_tx, archive_rx) = unbounded_channel();
+            let (archive_tx, archive_rx) = channel(queue_depth);
+
+            let mut chat_enable = false;
 
             if !no_chat {
-                let mut chat = ChatAggregator::new(ipfs.clone(), archive_tx.clone(), chat).await;
+                match ChatAggregator::new(ipfs.clone(), archive_tx.clone(), chat, codec).await {
+                    Ok(mut chat) => {
+                        let chat_handle = tokio::spawn(async move {
+                            chat.start().await;
+                        });
+
+                        handles.push(chat_handle);
+
+                        chat_enable = true;
+                    }
+                    Err(e) => eprintln!("❗ Chat: {}. Archiving without chat", e),
+                }
+            }
 
-                let chat_handle = tokio::spawn(async move {
-                    chat.start().await;
-                });
+            archive.archive_live_chat = chat_enable;
 
-                handles.push(chat_handle);
+            if title.is_some() {
+                archive.title = title;
             }
 
-            archive.archive_live_chat = !no_chat;
+            if let Some(image) = image {
+                archive.image = Some(image);
+            }
 
-            let mut archivist = Archivist::new(ipfs.clone(), archive_rx);
+            let (entries, pending, journal) = start_journal().await;
+
+            let mut archivist =
+                Archivist::new(ipfs.clone(), archive_rx, archive.clone(), codec, journal);
+
+            if !entries.is_empty() || !pending.is_empty() {
+                archivist.resume(entries, pending).await;
+            }
 
             let archive_handle = tokio::spawn(async move {
                 archivist.start().await;
@@ -83,11 +154,29 @@ pub async fn stream_cli(stream: Stream) {
         }
     };
 
-    let (video_tx, video_rx) = unbounded_channel();
+    let (video_tx, video_rx) = channel(queue_depth);
 
     video.pubsub_enable = true;
 
-    let mut video = VideoAggregator::new(ipfs.clone(), video_rx, archive_tx.clone(), video);
+    let archive = if no_archive { None } else { Some(archive) };
+
+    let streams = Streams::new(
+        ipfs.clone(),
+        codec,
+        archive,
+        video.clone(),
+        queue_depth,
+        stream_ids,
+    );
+
+    let mut video = VideoAggregator::new(
+        ipfs.clone(),
+        video_rx,
+        archive_tx.clone(),
+        video,
+        codec,
+        None,
+    );
 
     let video_handle = tokio::spawn(async move {
         video.start().await;
@@ -95,9 +184,9 @@ pub async fn stream_cli(stream: Stream) {
 
     handles.push(video_handle);
 
-    let (setup_tx, setup_rx) = unbounded_channel();
+    let (setup_tx, setup_rx) = channel(queue_depth);
 
-    let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone());
+    let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone(), codec);
 
     let setup_handle = tokio::spawn(async move {
         setup.start().await;
@@ -105,23 +194,135 @@ pub async fn stream_cli(stream: Stream) {
 
     handles.push(setup_handle);
 
+    let channels = StreamChannels {
+        video_tx,
+        setup_tx,
+        archive_tx,
+    };
+
+    streams.insert(MAIN_STREAM, channels);
+
+    let segment_length = encoder.as_ref().map_or(1, |encoder| encoder.segment_length);
+
+    if passthrough {
+        let rtmp = start_rtmp_server(
+            rtmp_addr,
+            streams.clone(),
+            ipfs.clone(),
+            stream_key.clone(),
+            segment_length,
+        );
+
+        handles.push(tokio::spawn(rtmp));
+    }
+
+    let whip = match Whip::new(streams.clone(), ipfs.clone(), segment_length) {
+        Ok(whip) => Some(whip),
+        Err(e) => {
+            eprintln!("❗ WHIP: {}", e);
+            None
+        }
+    };
+
+    let streams_clone = streams.clone();
+    let server_key = stream_key.clone();
+
     let server_handle = tokio::spawn(async move {
-        start_server(
-            input_socket_addr,
-            video_tx,
-            setup_tx,
-            archive_tx,
-            ipfs,
-            topic,
-        )
-        .await;
+        start_server(listener, streams_clone, ipfs, topic, None, server_key, whip).await;
     });
 
     handles.push(server_handle);
 
+    if let (Some(encoder), false) = (encoder, no_encoder || passthrough) {
+        let mut encoder = Encoder::new(encoder, EncoderInput::Live, input_socket_addr, stream_key);
+
+        let encoder_handle = tokio::spawn(async move {
+            encoder.start().await;
+        });
+
+        handles.push(encoder_handle);
+    }
+
     for handle in handles {
         if let Err(e) = handle.await {
             eprintln!("❗ Main: {}", e);
         }
     }
+
+    streams.join().await;
+}
+
+/// Resume the unfinished archive or set its journal aside, then start a new journal.
+/// No journal rather than overwriting one that could not be set aside.
+async fn start_journal() -> (Vec<JournalEntry>, Vec<SecondNode>, Option<Journal>) {
+    let dir = Path::new(".");
+
+    let mut entries = match Journal::read(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("❗ Journal: read failed {}. Archiving without journal", e);
+            return (Vec::new(), Vec::new(), None);
+        }
+    };
+
+    let mut pending = match Journal::read_pending(dir, &entries).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            eprintln!("❗ Journal: outbox read failed {}. Pending seconds lost", e);
+            Vec::new()
+        }
+    };
+
+    let count = entries.len() + pending.len();
+
+    if count > 0 && !ask_resume(count).await {
+        match Journal::set_aside(dir).await {
+            Ok(location) => println!("Unfinished Archive Journal Kept As {}", location.display()),
+            Err(e) => {
+                eprintln!("❗ Journal: {}. Archiving without journal", e);
+                return (Vec::new(), Vec::new(), None);
+            }
+        }
+
+        entries.clear();
+        pending.clear();
+    }
+
+    let journal = match Journal::create(dir, &entries, &pending).await {
+        Ok(journal) => Some(journal),
+        Err(e) => {
+            eprintln!("❗ Journal: create failed {}", e);
+            None
+        }
+    };
+
+    (entries, pending, journal)
+}
+
+/// Ask whether the unfinished archive should be continued.
+async fn ask_resume(count: usize) -> bool {
+    println!(
+        "Unfinished archive found with {} nodes. Resume it? [y/N]",
+        count
+    );
+
+    // Stdin blocks, keep it off the runtime threads
+    let answer = spawn_blocking(|| {
+        let mut answer = String::new();
+
+        stdin().read_line(&mut answer).map(|_| answer)
+    })
+    .await;
+
+    match answer {
+        Ok(Ok(answer)) => matches!(answer.trim(), "y" | "Y" | "yes"),
+        Ok(Err(e)) => {
+            eprintln!("❗ Stdin: {}", e);
+            false
+        }
+        Err(e) => {
+            eprintln!("❗ Stdin: {}", e);
+            false
+        }
+    }
 }
@@ -0,0 +1,312 @@
+use crate::backend::{daemon, DagStore, Ipfs};
+use crate::cli::content::get_video_duration;
+use crate::error::Error;
+use crate::utils::config::Configuration;
+use crate::utils::dag_nodes::{ipfs_dag_get_node_async, ipfs_dag_put_node_async};
+use crate::utils::feed::add_content_to_feed;
+
+use linked_data::codec::Codec;
+use linked_data::feed::Media;
+use linked_data::video::{
+    DayNode, HourNode, MinuteNode, SecondNode, SetupNode, TimecodeNode, VideoMetadata, VideoNode,
+};
+use linked_data::IPLDLink;
+
+use cid::Cid;
+
+use structopt::StructOpt;
+
+const SETUP_PATH: &str = "time/hour/0/minute/0/second/0/video/setup";
+
+#[derive(Debug, StructOpt)]
+pub struct Vod {
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Cut the start and end of a video.
+    Trim(Trim),
+
+    /// Join videos end to end.
+    Concat(Concat),
+
+    /// Rebuild a video from the last video node of a stream.
+    Recover(Recover),
+}
+
+pub async fn vod_cli(cli: Vod) {
+    let res = match cli.cmd {
+        Command::Trim(trim) => trim_video(trim).await,
+        Command::Concat(concat) => concat_videos(concat).await,
+        Command::Recover(recover) => recover_video(recover).await,
+    };
+
+    if let Err(e) = res {
+        eprintln!("❗ {}", e);
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Trim {
+    /// The video timecode CID.
+    #[structopt(short, long)]
+    video: Cid,
+
+    /// The new start timecode in seconds.
+    #[structopt(short, long)]
+    from: f64,
+
+    /// The new end timecode in seconds.
+    #[structopt(short, long)]
+    to: f64,
+}
+
+async fn trim_video(command: Trim) -> Result<(), Error> {
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
+
+    let Trim { video, from, to } = command;
+
+    let (seconds, _) = get_second_links(&ipfs, &video).await?;
+
+    let start = from.floor() as usize;
+    let end = (to.ceil() as usize).min(seconds.len());
+
+    if from < 0.0 || start >= end {
+        return Err(Error::Invalid(format!(
+            "Invalid Trim Range {}s to {}s For Video Of {}s",
+            from,
+            to,
+            seconds.len()
+        )));
+    }
+
+    let cid = create_timecode_dag(&ipfs, &seconds[start..end], None, codec).await?;
+
+    println!("Final Timecode-addressable Node => {}", &cid.to_string());
+
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Concat {
+    /// The video timecode CIDs in order.
+    #[structopt(min_values = 2, required = true)]
+    videos: Vec<Cid>,
+}
+
+async fn concat_videos(command: Concat) -> Result<(), Error> {
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
+
+    let mut first_setup: Option<SetupNode> = None;
+    let mut seconds = Vec::new();
+    let mut duration = Some(0.0);
+
+    for video in command.videos.iter() {
+        let path = format!("{}/{}", video.to_string(), SETUP_PATH);
+
+        let setup: SetupNode = ipfs_dag_get_node_async(&ipfs, &path).await?;
+
+        // Players only load the setup of the first video.
+        if let Some(first) = first_setup.as_ref() {
+            if !is_compatible(first, &setup) {
+                return Err(Error::Invalid(format!(
+                    "Video {} Tracks Or Encoder Settings Differ From The First Video",
+                    video.to_string()
+                )));
+            }
+        } else {
+            first_setup = Some(setup);
+        }
+
+        let (links, video_duration) = get_second_links(&ipfs, video).await?;
+
+        seconds.extend(links);
+
+        duration = match (duration, video_duration) {
+            (Some(total), Some(video_duration)) => Some(total + video_duration),
+            _ => None,
+        };
+    }
+
+    let cid = create_timecode_dag(&ipfs, &seconds, duration, codec).await?;
+
+    println!("Final Timecode-addressable Node => {}", &cid.to_string());
+
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Recover {
+    /// The last video node CID of the stream.
+    video_node: Cid,
+
+    /// Also add the video to your feed with this title.
+    #[structopt(short, long, requires = "image")]
+    title: Option<String>,
+
+    /// The video thumbnail image CID.
+    #[structopt(short, long, requires = "title")]
+    image: Option<Cid>,
+}
+
+async fn recover_video(command: Recover) -> Result<(), Error> {
+    let ipfs = daemon();
+    let codec = Configuration::codec_from_file().await;
+
+    let Recover {
+        video_node,
+        title,
+        image,
+    } = command;
+
+    println!("Following Video Nodes...");
+
+    let mut video_nodes = Vec::new();
+    let mut cid = video_node;
+
+    loop {
+        let node: VideoNode = ipfs_dag_get_node_async(&ipfs, &cid.to_string()).await?;
+
+        // Nodes without duration predate variable segment length.
+        video_nodes.push((cid, node.duration.unwrap_or(1.0)));
+
+        cid = match node.previous {
+            Some(ipld) => ipld.link,
+            None => break,
+        };
+    }
+
+    video_nodes.reverse();
+
+    println!("{} Video Nodes Found", video_nodes.len());
+
+    // Chat messages were never linked, only video can be recovered.
+    let mut seconds = Vec::with_capacity(video_nodes.len());
+    let mut time: f64 = 0.0;
+
+    for (cid, duration) in video_nodes {
+        let start = time.floor();
+        time += duration;
+        let count = (time.floor() - start) as usize;
+
+        if count == 0 {
+            continue;
+        }
+
+        let node = SecondNode {
+            link_to_video: cid.into(),
+            links_to_chat: Vec::new(),
+        };
+
+        let cid = ipfs_dag_put_node_async(&ipfs, &node, codec).await?;
+
+        for _ in 0..count {
+            seconds.push(cid.into());
+        }
+    }
+
+    let video = create_timecode_dag(&ipfs, &seconds, Some(time), codec).await?;
+
+    println!("Final Timecode-addressable Node => {}", &video.to_string());
+
+    if let (Some(title), Some(image)) = (title, image) {
+        let duration = get_video_duration(&ipfs, &video).await?;
+
+        let metadata = Media::Video(VideoMetadata::create(title, duration, image, video));
+
+        let new_cid = ipfs_dag_put_node_async(&ipfs, &metadata, codec).await?;
+
+        println!("New Post CID => {}", &new_cid.to_string());
+
+        let index = add_content_to_feed(&ipfs, new_cid, codec).await?;
+
+        println!("✅ Video Post Added In Content Feed At Index {}", index);
+    }
+
+    Ok(())
+}
+
+/// Same tracks, in the same order, with the same codecs and initialization segments.
+/// Encoder parameters (SPS/PPS, resolution) are in the initialization segments.
+fn is_compatible(first: &SetupNode, other: &SetupNode) -> bool {
+    first.tracks.len() == other.tracks.len()
+        && first.tracks.iter().zip(other.tracks.iter()).all(|(a, b)| {
+            a.name == b.name
+                && a.codec == b.codec
+                && a.initialization_segment == b.initialization_segment
+        })
+}
+
+/// Return links to all SecondNodes of a video in chronological order and its exact duration, if known.
+async fn get_second_links(ipfs: &Ipfs, video: &Cid) -> Result<(Vec<IPLDLink>, Option<f64>), Error> {
+    let root: TimecodeNode = ipfs_dag_get_node_async(ipfs, &video.to_string()).await?;
+
+    let days: DayNode = ipfs_dag_get_node_async(ipfs, &root.timecode.link.to_string()).await?;
+
+    let mut seconds = Vec::with_capacity(days.links_to_hours.len() * 3600);
+
+    for ipld in days.links_to_hours.iter() {
+        let hours: HourNode = ipfs_dag_get_node_async(ipfs, &ipld.link.to_string()).await?;
+
+        for ipld in hours.links_to_minutes.iter() {
+            let minutes: MinuteNode = ipfs_dag_get_node_async(ipfs, &ipld.link.to_string()).await?;
+
+            seconds.extend(minutes.links_to_seconds);
+        }
+    }
+
+    Ok((seconds, root.duration))
+}
+
+/// Create a new timecode structure around existing SecondNodes then pin it.
+async fn create_timecode_dag(
+    ipfs: &Ipfs,
+    seconds: &[IPLDLink],
+    duration: Option<f64>,
+    codec: Codec,
+) -> Result<Cid, Error> {
+    println!("Collecting Nodes...");
+
+    let mut day_node = DayNode {
+        links_to_hours: Vec::with_capacity(24),
+    };
+
+    for hour in seconds.chunks(3600) {
+        let mut hour_node = HourNode {
+            links_to_minutes: Vec::with_capacity(60),
+        };
+
+        for minute in hour.chunks(60) {
+            let minute_node = MinuteNode {
+                links_to_seconds: minute.to_vec(),
+            };
+
+            let cid = ipfs_dag_put_node_async(ipfs, &minute_node, codec).await?;
+
+            hour_node.links_to_minutes.push(cid.into());
+        }
+
+        let cid = ipfs_dag_put_node_async(ipfs, &hour_node, codec).await?;
+
+        day_node.links_to_hours.push(cid.into());
+    }
+
+    let cid = ipfs_dag_put_node_async(ipfs, &day_node, codec).await?;
+
+    let stream = TimecodeNode {
+        timecode: cid.into(),
+        duration,
+    };
+
+    let cid = ipfs_dag_put_node_async(ipfs, &stream, codec).await?;
+
+    println!("Pinning Nodes...");
+
+    ipfs.pin_add(&cid, true).await?;
+
+    Ok(cid)
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// How the actors react to an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Transient failure, the same work is attempted again later.
+    Retry,
+
+    /// Bad input, it is dropped and the pipeline keeps going.
+    Skip,
+
+    /// Nothing more can be archived, the actor stops.
+    /// The video aggregator finalizes the archive first, others stop after it.
+    Shutdown,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// IPFS daemon request failed.
+    Ipfs(ipfs_api::response::Error),
+
+    /// Node (de)serialization or signature failed.
+    LinkedData(linked_data::Error),
+
+    /// Invalid CID.
+    Cid(cid::Error),
+
+    /// File system access failed.
+    Io(std::io::Error),
+
+    /// Segment or playlist path not following ffmpeg naming.
+    Path(PathBuf),
+
+    /// Segment at this index too far ahead of the next node to mint.
+    Lagging(usize),
+
+    /// Key missing from the IPFS keystore.
+    KeyNotFound(String),
+
+    /// The receiving actor stopped.
+    ChannelClosed(&'static str),
+
+    /// Input rejected for this reason.
+    Invalid(String),
+}
+
+impl Error {
+    /// Retry IPFS failures, skip bad input and shut down when an actor is gone.
+    pub fn policy(&self) -> Policy {
+        match self {
+            Self::Ipfs(_) | Self::Io(_) => Policy::Retry,
+            Self::LinkedData(_)
+            | Self::Cid(_)
+            | Self::Path(_)
+            | Self::Lagging(_)
+            | Self::Invalid(_) => Policy::Skip,
+            Self::KeyNotFound(_) | Self::ChannelClosed(_) => Policy::Shutdown,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipfs(e) => write!(f, "IPFS: {}", e),
+            Self::LinkedData(e) => write!(f, "{}", e),
+            Self::Cid(e) => write!(f, "CID: {}", e),
+            Self::Io(e) => write!(f, "IO: {}", e),
+            Self::Path(path) => write!(f, "Path: unknown segment {}", path.display()),
+            Self::Lagging(index) => write!(f, "segment {} too far ahead, archive lagging", index),
+            Self::KeyNotFound(key) => write!(f, "IPNS: key {} not found", key),
+            Self::ChannelClosed(actor) => write!(f, "{} receiver hung up", actor),
+            Self::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ipfs(e) => Some(e),
+            Self::LinkedData(e) => Some(e),
+            Self::Cid(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ipfs_api::response::Error> for Error {
+    fn from(error: ipfs_api::response::Error) -> Self {
+        Self::Ipfs(error)
+    }
+}
+
+impl From<linked_data::Error> for Error {
+    fn from(error: linked_data::Error) -> Self {
+        Self::LinkedData(error)
+    }
+}
+
+impl From<cid::Error> for Error {
+    fn from(error: cid::Error) -> Self {
+        Self::Cid(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
@@ -1,13 +1,19 @@
 mod actors;
+mod backend;
 mod cli;
+mod error;
 mod server;
 mod utils;
 
 use crate::cli::beacon::{beacon_cli, Beacon};
 use crate::cli::content::{content_feed_cli, ContentFeed};
+use crate::cli::export::{export_cli, Export};
 use crate::cli::file::{file_cli, File};
+use crate::cli::import::{import_cli, Import};
 use crate::cli::moderation::{moderation_cli, Moderation};
+use crate::cli::serve::{serve_cli, Serve};
 use crate::cli::stream::{stream_cli, Stream};
+use crate::cli::vod::{vod_cli, Vod};
 
 use structopt::StructOpt;
 
@@ -30,6 +36,18 @@ enum CommandLineInterface {
 
     /// Manage your content feed.
     ContentFeed(ContentFeed),
+
+    /// Edit recorded videos.
+    Vod(Vod),
+
+    /// Serve videos to HLS players.
+    Serve(Serve),
+
+    /// Convert videos to other formats.
+    Export(Export),
+
+    /// Archive a HLS directory.
+    Import(Import),
 }
 
 #[tokio::main]
@@ -40,5 +58,9 @@ async fn main() {
         CommandLineInterface::Beacon(beacon) => beacon_cli(beacon).await,
         CommandLineInterface::Moderation(mods) => moderation_cli(mods).await,
         CommandLineInterface::ContentFeed(feed) => content_feed_cli(feed).await,
+        CommandLineInterface::Vod(vod) => vod_cli(vod).await,
+        CommandLineInterface::Serve(serve) => serve_cli(serve).await,
+        CommandLineInterface::Export(export) => export_cli(export).await,
+        CommandLineInterface::Import(import) => import_cli(import).await,
     }
 }
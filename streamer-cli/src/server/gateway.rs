@@ -0,0 +1,278 @@
+use crate::backend::{DagStore, Ipfs};
+use crate::server::services::{internal_error_response, not_found_response, M4S, MP4};
+use crate::utils::dag_nodes::ipfs_dag_get_node_async;
+use crate::utils::playlists::{master_playlist, media_playlist, MASTER_PLAYLIST, MEDIA_PLAYLIST};
+use crate::utils::segments::{get_video_segments, Segment, VideoSegments};
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use tokio::signal::ctrl_c;
+
+use hyper::header::{HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Error, Method, Request, Response, Server};
+
+use linked_data::video::{SetupNode, Track};
+
+use cid::Cid;
+
+const M3U8_MIME: &str = "application/vnd.apple.mpegurl";
+const MP4_MIME: &str = "video/mp4";
+const M4S_MIME: &str = "video/iso.segment";
+
+/// Archives kept in cache, the least recently served is evicted first.
+const CACHE_CAPACITY: usize = 64;
+
+type Segments = Arc<VideoSegments>;
+
+type Cache = Arc<Mutex<ArchiveCache>>;
+
+/// Segments of the archives served recently. DAGs are immutable so entries are never stale.
+/// Live chains keep growing and are never cached.
+#[derive(Default)]
+struct ArchiveCache {
+    entries: HashMap<Cid, Segments>,
+
+    /// Least recently served first.
+    order: VecDeque<Cid>,
+}
+
+impl ArchiveCache {
+    fn get(&mut self, cid: &Cid) -> Option<Segments> {
+        let segments = self.entries.get(cid)?.clone();
+
+        self.touch(cid);
+
+        Some(segments)
+    }
+
+    fn insert(&mut self, cid: Cid, segments: Segments) {
+        if self.entries.insert(cid, segments).is_some() {
+            self.touch(&cid);
+            return;
+        }
+
+        self.order.push_back(cid);
+
+        if self.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn touch(&mut self, cid: &Cid) {
+        if let Some(index) = self.order.iter().position(|item| item == cid) {
+            self.order.remove(index);
+            self.order.push_back(*cid);
+        }
+    }
+}
+
+pub async fn start_gateway(server_addr: SocketAddr, ipfs: Ipfs) {
+    let cache: Cache = Arc::new(Mutex::new(ArchiveCache::default()));
+
+    let service = make_service_fn(move |_| {
+        let ipfs = ipfs.clone();
+        let cache = cache.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                get_requests(req, ipfs.clone(), cache.clone())
+            }))
+        }
+    });
+
+    let server = Server::bind(&server_addr).serve(service);
+
+    println!("✅ Gateway Server Online");
+    println!(
+        "Playlists at http://{}/<CID>/{}",
+        server_addr, MASTER_PLAYLIST
+    );
+
+    let graceful = server.with_graceful_shutdown(async {
+        ctrl_c()
+            .await
+            .expect("Failed to install CTRL+C signal handler");
+    });
+
+    if let Err(e) = graceful.await {
+        eprintln!("Server: {}", e);
+    }
+
+    println!("❌ Gateway Server Offline");
+}
+
+async fn get_requests(
+    req: Request<Body>,
+    ipfs: Ipfs,
+    cache: Cache,
+) -> Result<Response<Body>, Error> {
+    #[cfg(debug_assertions)]
+    println!("Gateway: {:#?}", req);
+
+    let res = Response::new(Body::empty());
+
+    if req.method() != Method::GET {
+        return not_found_response(res);
+    }
+
+    let path: Vec<&str> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    let cid = match path.first().map(|s| Cid::from_str(s)) {
+        Some(Ok(cid)) => cid,
+        _ => return not_found_response(res),
+    };
+
+    let video = match get_segments(&ipfs, &cache, cid).await {
+        Ok(video) => video,
+        Err(crate::error::Error::Invalid(_)) => return not_found_response(res),
+        Err(e) => return internal_error_response(res, &e),
+    };
+
+    let segments = &video.segments;
+
+    match path[1..] {
+        [MASTER_PLAYLIST] => master_response(res, &ipfs, segments).await,
+        [_, MEDIA_PLAYLIST] => playlist_response(res, media_playlist(segments, video.live)),
+        [variant, file] => match file.rsplit_once('.') {
+            Some((index, MP4)) => match index.parse::<usize>() {
+                Ok(index) => init_segment(res, &ipfs, variant, segments, index).await,
+                Err(_) => not_found_response(res),
+            },
+            Some((index, M4S)) => match index.parse::<usize>() {
+                Ok(index) => media_segment(res, &ipfs, variant, segments, index).await,
+                Err(_) => not_found_response(res),
+            },
+            _ => not_found_response(res),
+        },
+        _ => not_found_response(res),
+    }
+}
+
+/// Get segments from cache or from IPFS.
+async fn get_segments(
+    ipfs: &Ipfs,
+    cache: &Cache,
+    cid: Cid,
+) -> Result<Segments, crate::error::Error> {
+    if let Some(segments) = cache.lock().expect("Lock Poisoned").get(&cid) {
+        return Ok(segments);
+    }
+
+    let segments = Arc::new(get_video_segments(ipfs, cid).await?);
+
+    if !segments.live {
+        cache
+            .lock()
+            .expect("Lock Poisoned")
+            .insert(cid, segments.clone());
+    }
+
+    Ok(segments)
+}
+
+/// Generate master playlist from the tracks of the first SetupNode.
+async fn master_response(
+    res: Response<Body>,
+    ipfs: &Ipfs,
+    segments: &[Segment],
+) -> Result<Response<Body>, Error> {
+    let setup = match segments.first().and_then(|segment| segment.setup) {
+        Some(setup) => setup,
+        None => return not_found_response(res),
+    };
+
+    let setup: SetupNode = match ipfs_dag_get_node_async(ipfs, &setup.to_string()).await {
+        Ok(node) => node,
+        Err(e) => return internal_error_response(res, &e),
+    };
+
+    let tracks: Vec<&Track> = setup.tracks.iter().collect();
+
+    playlist_response(res, master_playlist(&tracks))
+}
+
+/// Proxy the initialization segment of this variant used by the segment at index.
+async fn init_segment(
+    res: Response<Body>,
+    ipfs: &Ipfs,
+    variant: &str,
+    segments: &[Segment],
+    index: usize,
+) -> Result<Response<Body>, Error> {
+    let setup = match segments.get(index).and_then(|segment| segment.setup) {
+        Some(setup) => setup,
+        None => return not_found_response(res),
+    };
+
+    let setup: SetupNode = match ipfs_dag_get_node_async(ipfs, &setup.to_string()).await {
+        Ok(node) => node,
+        Err(e) => return internal_error_response(res, &e),
+    };
+
+    let cid = match setup.tracks.iter().find(|track| track.name == variant) {
+        Some(track) => track.initialization_segment.link,
+        None => return not_found_response(res),
+    };
+
+    cat_response(res, ipfs, cid.to_string(), MP4_MIME).await
+}
+
+/// Proxy the media segment of this variant at index.
+async fn media_segment(
+    res: Response<Body>,
+    ipfs: &Ipfs,
+    variant: &str,
+    segments: &[Segment],
+    index: usize,
+) -> Result<Response<Body>, Error> {
+    let video = match segments.get(index) {
+        Some(segment) => segment.video,
+        None => return not_found_response(res),
+    };
+
+    let path = format!("{}/track/{}", video, variant);
+
+    cat_response(res, ipfs, path, M4S_MIME).await
+}
+
+fn playlist_response(mut res: Response<Body>, playlist: String) -> Result<Response<Body>, Error> {
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(M3U8_MIME));
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+
+    *res.body_mut() = Body::from(playlist);
+
+    Ok(res)
+}
+
+async fn cat_response(
+    mut res: Response<Body>,
+    ipfs: &Ipfs,
+    path: String,
+    mime: &'static str,
+) -> Result<Response<Body>, Error> {
+    let data = match ipfs.cat(&path).await {
+        Ok(data) => data,
+        Err(e) => return internal_error_response(res, &e),
+    };
+
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+
+    *res.body_mut() = Body::from(data);
+
+    Ok(res)
+}
@@ -1,69 +1,89 @@
-use crate::actors::{Archive, SetupData, VideoData};
+use crate::backend::{Ipfs, PubSub};
 use crate::server::services::put_requests;
+use crate::server::streams::Streams;
+use crate::server::whip::Whip;
 
 use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::net::TcpListener;
 
 use tokio::signal::ctrl_c;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot::Receiver;
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 
-use ipfs_api::IpfsClient;
-
 async fn shutdown_signal(
-    ipfs: IpfsClient,
+    ipfs: Ipfs,
     topic: String,
-    archive_tx: Option<UnboundedSender<Archive>>,
+    streams: Streams,
+    end_rx: Option<Receiver<()>>,
 ) {
-    ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler");
-
-    if let Some(archive_tx) = archive_tx {
-        let msg = Archive::Finalize;
-
-        if let Err(error) = archive_tx.send(msg) {
-            eprintln!("Archive receiver hung up {}", error);
-        }
+    match end_rx {
+        Some(end_rx) => tokio::select! {
+            res = ctrl_c() => res.expect("Failed to install CTRL+C signal handler"),
+            _ = end_rx => println!("End Of Stream Reached"),
+        },
+        None => ctrl_c()
+            .await
+            .expect("Failed to install CTRL+C signal handler"),
+    }
 
+    if streams.finalize().await {
         //Hacky way to shutdown chat actor. Send some msg to trigger a check
-        ipfs.pubsub_pub(&topic, "Stopping")
-            .await
-            .expect("PubSub Pub Failed!");
+        if let Err(e) = ipfs.pubsub_pub(&topic, "Stopping").await {
+            eprintln!("❗ IPFS: pubsub pub failed {}", e);
+        }
     }
 }
 
+/// Serve ingest on this already bound listener.
 pub async fn start_server(
-    server_addr: SocketAddr,
-    video_tx: UnboundedSender<VideoData>,
-    setup_tx: UnboundedSender<SetupData>,
-    archive_tx: Option<UnboundedSender<Archive>>,
-    ipfs: IpfsClient,
+    listener: TcpListener,
+    streams: Streams,
+    ipfs: Ipfs,
     topic: String,
+    end_rx: Option<Receiver<()>>,
+    stream_key: Option<String>,
+    whip: Option<Whip>,
 ) {
     let ipfs_clone = ipfs.clone();
+    let streams_clone = streams.clone();
 
     let service = make_service_fn(move |_| {
         let ipfs = ipfs.clone();
-        let video_tx = video_tx.clone();
-        let setup_tx = setup_tx.clone();
+        let streams = streams.clone();
+        let stream_key = stream_key.clone();
+        let whip = whip.clone();
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
-                put_requests(req, video_tx.clone(), setup_tx.clone(), ipfs.clone())
+                put_requests(
+                    req,
+                    streams.clone(),
+                    ipfs.clone(),
+                    stream_key.clone(),
+                    whip.clone(),
+                )
             }))
         }
     });
 
-    let server = Server::bind(&server_addr)
+    let builder = match Server::from_tcp(listener) {
+        Ok(builder) => builder,
+        Err(e) => {
+            eprintln!("❗ Server: {}", e);
+            return;
+        }
+    };
+
+    let server = builder
         .http1_half_close(true) //FFMPEG requirement
         .serve(service);
 
     println!("✅ Ingess Server Online");
 
-    let graceful = server.with_graceful_shutdown(shutdown_signal(ipfs_clone, topic, archive_tx));
+    let graceful =
+        server.with_graceful_shutdown(shutdown_signal(ipfs_clone, topic, streams_clone, end_rx));
 
     if let Err(e) = graceful.await {
         eprintln!("Server: {}", e);
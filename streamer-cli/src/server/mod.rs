@@ -1,5 +1,17 @@
+mod gateway;
 mod hyper_server;
+mod remuxer;
+mod rtmp;
 mod services;
+mod streams;
+mod whip;
 
+#[cfg(test)]
+mod tests;
+
+pub use gateway::start_gateway;
 pub use hyper_server::start_server;
+pub use rtmp::start_rtmp_server;
 pub use services::{M4S, MP4};
+pub use streams::{StreamChannels, Streams, MAIN_STREAM};
+pub use whip::Whip;
@@ -0,0 +1,530 @@
+use crate::actors::{SetupData, VideoData};
+use crate::backend::{DagStore, Ipfs};
+use crate::server::streams::StreamChannels;
+use crate::utils::fmp4::{
+    audio_init, media_segment, opus_init, video_init, Sample, OPUS_SAMPLE_RATE,
+};
+use crate::utils::h264::dimensions;
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use cid::Cid;
+
+use m3u8_rs::playlist::{MasterPlaylist, MediaPlaylist, MediaSegment, VariantStream};
+
+/// Name of the video track in passthrough mode.
+const VIDEO_TRACK: &str = "source";
+const AUDIO_TRACK: &str = "audio";
+
+/// RTMP timestamps are in milliseconds.
+const VIDEO_TIMESCALE: u32 = 1000;
+
+/// AAC frames always contain 1024 samples.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+const FLV_AVC: u8 = 7;
+const FLV_AAC: u8 = 10;
+
+struct VideoSample {
+    timestamp: u32,
+    composition_offset: i32,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+enum AudioCodec {
+    /// AudioSpecificConfig
+    Aac(Vec<u8>),
+    Opus,
+}
+
+struct AudioConfig {
+    codec: AudioCodec,
+    channels: u16,
+    sample_rate: u32,
+}
+
+struct AudioSample {
+    timestamp: u32,
+
+    /// Duration in samples.
+    duration: u32,
+
+    data: Vec<u8>,
+}
+
+/// Remux H.264 with AAC or Opus into fmp4 segments cut at key frames.
+pub struct Remuxer {
+    ipfs: Ipfs,
+
+    stream: StreamChannels,
+
+    /// Minimum segment duration in milliseconds.
+    segment_length: u32,
+
+    width: Option<u16>,
+    height: Option<u16>,
+    video_bandwidth: Option<usize>,
+    audio_bandwidth: Option<usize>,
+
+    avc_config: Option<Vec<u8>>,
+    audio_config: Option<AudioConfig>,
+
+    /// Tracks sent with the setup.
+    tracks: Vec<&'static str>,
+
+    /// Timestamp of the first key frame.
+    origin: Option<u32>,
+
+    index: usize,
+    segment_start: u32,
+
+    video_samples: Vec<VideoSample>,
+    audio_samples: Vec<AudioSample>,
+
+    /// Decode time of the next audio segment in samples.
+    audio_time: Option<u64>,
+}
+
+impl Remuxer {
+    pub fn new(ipfs: Ipfs, stream: StreamChannels, segment_length: u32) -> Self {
+        Self {
+            ipfs,
+
+            stream,
+
+            segment_length: segment_length * 1000,
+
+            width: None,
+            height: None,
+            video_bandwidth: None,
+            audio_bandwidth: None,
+
+            avc_config: None,
+            audio_config: None,
+
+            tracks: Vec::with_capacity(2),
+
+            origin: None,
+
+            index: 0,
+            segment_start: 0,
+
+            video_samples: Vec::with_capacity(120),
+            audio_samples: Vec::with_capacity(100),
+
+            audio_time: None,
+        }
+    }
+
+    /// Stream metadata sent by the broadcast software.
+    pub fn metadata(
+        &mut self,
+        width: Option<u32>,
+        height: Option<u32>,
+        video_kbps: Option<u32>,
+        audio_kbps: Option<u32>,
+    ) {
+        self.width = width.and_then(|width| u16::try_from(width).ok());
+        self.height = height.and_then(|height| u16::try_from(height).ok());
+        self.video_bandwidth = video_kbps.map(|kbps| kbps as usize * 1000);
+        self.audio_bandwidth = audio_kbps.map(|kbps| kbps as usize * 1000);
+    }
+
+    /// FLV video tag body.
+    pub async fn video(&mut self, timestamp: u32, data: &[u8]) {
+        if data.len() < 5 {
+            return;
+        }
+
+        if data[0] & 0x0F != FLV_AVC {
+            eprintln!("❗ Remuxer: only H.264 video is supported");
+            return;
+        }
+
+        let keyframe = data[0] >> 4 == 1;
+
+        match data[1] {
+            0 => self.avc_config(data[5..].to_vec()),
+            1 => {
+                let composition_offset = composition_offset(data);
+
+                self.video_frame(timestamp, composition_offset, keyframe, data[5..].to_vec())
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    /// AVCDecoderConfigurationRecord of the video track.
+    pub fn avc_config(&mut self, config: Vec<u8>) {
+        self.avc_config = Some(config);
+    }
+
+    /// Length prefixed NAL units of one frame. Timestamp in milliseconds.
+    pub async fn video_frame(
+        &mut self,
+        timestamp: u32,
+        composition_offset: i32,
+        keyframe: bool,
+        data: Vec<u8>,
+    ) {
+        if self.tracks.is_empty() {
+            if !keyframe {
+                return;
+            }
+
+            self.send_setup().await;
+
+            if self.tracks.is_empty() {
+                return;
+            }
+        }
+
+        match self.origin {
+            None => {
+                self.origin = Some(timestamp);
+                self.segment_start = timestamp;
+            }
+            Some(_) => {
+                if keyframe && timestamp.saturating_sub(self.segment_start) >= self.segment_length {
+                    self.send_segment(timestamp).await;
+                }
+            }
+        }
+
+        self.video_samples.push(VideoSample {
+            timestamp,
+            composition_offset,
+            keyframe,
+            data,
+        });
+    }
+
+    /// FLV audio tag body.
+    pub async fn audio(&mut self, timestamp: u32, data: &[u8]) {
+        if data.len() < 2 {
+            return;
+        }
+
+        if data[0] >> 4 != FLV_AAC {
+            eprintln!("❗ Remuxer: only AAC audio is supported");
+            return;
+        }
+
+        match data[1] {
+            0 => self.audio_config = parse_audio_config(&data[2..]),
+            1 => self.audio_frame(timestamp, AAC_FRAME_SAMPLES, data[2..].to_vec()),
+            _ => {}
+        }
+    }
+
+    /// Audio of the stream is Opus, must be set before the first video frame.
+    pub fn opus_config(&mut self, channels: u16) {
+        self.audio_config = Some(AudioConfig {
+            codec: AudioCodec::Opus,
+            channels,
+            sample_rate: OPUS_SAMPLE_RATE,
+        });
+    }
+
+    /// One audio frame. Timestamp in milliseconds, duration in samples.
+    pub fn audio_frame(&mut self, timestamp: u32, duration: u32, data: Vec<u8>) {
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        if !self.tracks.contains(&AUDIO_TRACK) || timestamp < origin {
+            return;
+        }
+
+        self.audio_samples.push(AudioSample {
+            timestamp,
+            duration,
+            data,
+        });
+    }
+
+    /// Send the last samples as a segment.
+    pub async fn finish(&mut self) {
+        let last = match self.video_samples.last() {
+            Some(sample) => sample.timestamp,
+            None => return,
+        };
+
+        // Assume the last frame is as long as the previous one
+        let previous = match self.video_samples.len() {
+            len if len > 1 => self.video_samples[len - 2].timestamp,
+            _ => last,
+        };
+
+        self.send_segment(last + (last - previous)).await;
+
+        println!("Remuxer: End Of Stream");
+    }
+
+    /// Mint the setup with a video track and an audio track if any.
+    async fn send_setup(&mut self) {
+        let avc_config = match self.avc_config.as_ref() {
+            Some(config) if config.len() > 4 => config,
+            _ => return,
+        };
+
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => match dimensions(avc_config) {
+                Some(dimensions) => dimensions,
+                None => {
+                    eprintln!("❗ Remuxer: unknown video dimensions");
+                    return;
+                }
+            },
+        };
+
+        let mut variants = Vec::with_capacity(2);
+        let mut segments = Vec::with_capacity(2);
+
+        variants.push(VariantStream {
+            uri: format!("{}/index.m3u8", VIDEO_TRACK),
+            bandwidth: self.video_bandwidth.unwrap_or(6_000_000).to_string(),
+            codecs: Some(format!(
+                "avc1.{:02x}{:02x}{:02x}",
+                avc_config[1], avc_config[2], avc_config[3]
+            )),
+            ..Default::default()
+        });
+
+        segments.push((
+            VIDEO_TRACK,
+            video_init(avc_config, width, height, VIDEO_TIMESCALE),
+        ));
+
+        if let Some(config) = self.audio_config.as_ref() {
+            let (codecs, init) = match &config.codec {
+                AudioCodec::Aac(data) => (
+                    format!("mp4a.40.{}", data[0] >> 3),
+                    audio_init(data, config.channels, config.sample_rate),
+                ),
+                AudioCodec::Opus => ("opus".to_owned(), opus_init(config.channels)),
+            };
+
+            variants.push(VariantStream {
+                uri: format!("{}/index.m3u8", AUDIO_TRACK),
+                bandwidth: self.audio_bandwidth.unwrap_or(160_000).to_string(),
+                codecs: Some(codecs),
+                ..Default::default()
+            });
+
+            segments.push((AUDIO_TRACK, init));
+        }
+
+        let playlist = MasterPlaylist {
+            variants,
+            ..Default::default()
+        };
+
+        if let Err(error) = self
+            .stream
+            .setup_tx
+            .send(SetupData::Playlist(playlist))
+            .await
+        {
+            eprintln!("❗ Setup receiver hung up! Error: {}", error);
+            return;
+        }
+
+        for (track, data) in segments {
+            let cid = match self.add(data).await {
+                Some(cid) => cid,
+                None => return,
+            };
+
+            let path = PathBuf::from(format!("/{}/init.mp4", track));
+
+            if let Err(error) = self
+                .stream
+                .setup_tx
+                .send(SetupData::Segment((path, cid)))
+                .await
+            {
+                eprintln!("❗ Setup receiver hung up! Error: {}", error);
+                return;
+            }
+
+            self.tracks.push(track);
+        }
+    }
+
+    /// Send buffered samples before this timestamp as the next segment of each track.
+    async fn send_segment(&mut self, end: u32) {
+        let origin = self.origin.unwrap_or_default();
+
+        let video_samples = std::mem::take(&mut self.video_samples);
+
+        if video_samples.is_empty() {
+            return;
+        }
+
+        let decode_time = (video_samples[0].timestamp - origin) as u64;
+
+        let mut samples = Vec::with_capacity(video_samples.len());
+        let mut iter = video_samples.into_iter().peekable();
+
+        while let Some(sample) = iter.next() {
+            let next = iter.peek().map_or(end, |next| next.timestamp);
+
+            samples.push(Sample {
+                duration: next.saturating_sub(sample.timestamp),
+                composition_offset: sample.composition_offset,
+                keyframe: sample.keyframe,
+                data: sample.data,
+            });
+        }
+
+        let sequence = self.index as u32 + 1;
+
+        let mut segments = vec![(VIDEO_TRACK, media_segment(sequence, decode_time, &samples))];
+
+        if let (Some(config), true) = (
+            self.audio_config.as_ref(),
+            self.tracks.contains(&AUDIO_TRACK),
+        ) {
+            let split = self
+                .audio_samples
+                .iter()
+                .position(|sample| sample.timestamp >= end)
+                .unwrap_or(self.audio_samples.len());
+
+            let audio_samples: Vec<AudioSample> = self.audio_samples.drain(..split).collect();
+
+            let decode_time = match (self.audio_time, audio_samples.first()) {
+                (Some(time), _) => time,
+                (None, Some(sample)) => {
+                    (sample.timestamp - origin) as u64 * config.sample_rate as u64 / 1000
+                }
+                (None, None) => 0,
+            };
+
+            let duration: u64 = audio_samples
+                .iter()
+                .map(|sample| sample.duration as u64)
+                .sum();
+
+            self.audio_time = Some(decode_time + duration);
+
+            let samples: Vec<Sample> = audio_samples
+                .into_iter()
+                .map(|sample| Sample {
+                    duration: sample.duration,
+                    composition_offset: 0,
+                    keyframe: true,
+                    data: sample.data,
+                })
+                .collect();
+
+            segments.push((AUDIO_TRACK, media_segment(sequence, decode_time, &samples)));
+        }
+
+        for (track, data) in segments {
+            let cid = match self.add(data).await {
+                Some(cid) => cid,
+                None => continue,
+            };
+
+            let path = PathBuf::from(format!("/{}/{}.m4s", track, self.index));
+
+            if let Err(error) = self
+                .stream
+                .video_tx
+                .send(VideoData::Segment((path, cid)))
+                .await
+            {
+                eprintln!("❗ Video receiver hung up! Error: {}", error);
+            }
+        }
+
+        // Durations are the same for all tracks
+        let playlist = MediaPlaylist {
+            segments: vec![MediaSegment {
+                uri: format!("{}.m4s", self.index),
+                duration: (end - self.segment_start) as f32 / VIDEO_TIMESCALE as f32,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let path = PathBuf::from(format!("/{}/index.m3u8", VIDEO_TRACK));
+
+        if let Err(error) = self
+            .stream
+            .video_tx
+            .send(VideoData::Playlist((path, playlist)))
+            .await
+        {
+            eprintln!("❗ Video receiver hung up! Error: {}", error);
+        }
+
+        self.index += 1;
+        self.segment_start = end;
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Option<Cid> {
+        match self.ipfs.add(data).await {
+            Ok(cid) => Some(cid),
+            Err(e) => {
+                eprintln!("❗ IPFS: add failed {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Composition time of an FLV video tag in milliseconds, signed 24 bits.
+fn composition_offset(data: &[u8]) -> i32 {
+    i32::from_be_bytes([data[2], data[3], data[4], 0]) >> 8
+}
+
+/// Parse AudioSpecificConfig.
+fn parse_audio_config(data: &[u8]) -> Option<AudioConfig> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let rate_index = ((data[0] & 0x07) << 1) | (data[1] >> 7);
+    let channels = (data[1] >> 3) & 0x0F;
+
+    let sample_rate = *AAC_SAMPLE_RATES.get(rate_index as usize)?;
+
+    Some(AudioConfig {
+        codec: AudioCodec::Aac(data.to_vec()),
+        channels: channels as u16,
+        sample_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flv_composition_offset() {
+        assert_eq!(composition_offset(&[0x17, 1, 0, 0, 0x50]), 80);
+        assert_eq!(composition_offset(&[0x27, 1, 0xFF, 0xFF, 0xD8]), -40);
+        assert_eq!(composition_offset(&[0x27, 1, 0x80, 0, 0]), -(1 << 23));
+    }
+
+    #[test]
+    fn aac_config_parsed() {
+        let config = parse_audio_config(&[0x11, 0x90]).expect("Invalid config");
+
+        assert_eq!((config.channels, config.sample_rate), (2, 48000));
+
+        assert!(parse_audio_config(&[0x17, 0x90]).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::backend::Ipfs;
+use crate::server::remuxer::Remuxer;
+use crate::server::services::same_key;
+use crate::server::streams::{Streams, MAIN_STREAM};
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::ctrl_c;
+
+use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::sessions::{
+    ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
+};
+
+const BUFFER_SIZE: usize = 4096;
+
+/// Accept RTMP publications and remux them into the main stream without transcoding.
+pub async fn start_rtmp_server(
+    addr: SocketAddr,
+    streams: Streams,
+    ipfs: Ipfs,
+    stream_key: Option<String>,
+    segment_length: u32,
+) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("❗ RTMP Server: {}", e);
+            return;
+        }
+    };
+
+    println!("✅ RTMP Server Online");
+
+    loop {
+        let (socket, peer) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("❗ RTMP Server: {}", e);
+                    continue;
+                }
+            },
+            res = ctrl_c() => {
+                res.expect("Failed to install CTRL+C signal handler");
+                break;
+            }
+        };
+
+        println!("RTMP Server: {} connected", peer);
+
+        let mut connection = Connection {
+            socket,
+            streams: streams.clone(),
+            ipfs: ipfs.clone(),
+            stream_key: stream_key.clone(),
+            segment_length,
+            remuxer: None,
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = connection.start().await {
+                eprintln!("❗ RTMP Server: {}", e);
+            }
+
+            if let Some(mut remuxer) = connection.remuxer.take() {
+                remuxer.finish().await;
+            }
+
+            println!("RTMP Server: {} disconnected", peer);
+        });
+    }
+
+    println!("❌ RTMP Server Offline");
+}
+
+struct Connection {
+    socket: TcpStream,
+
+    streams: Streams,
+    ipfs: Ipfs,
+
+    stream_key: Option<String>,
+    segment_length: u32,
+
+    /// Started once publishing is accepted.
+    remuxer: Option<Remuxer>,
+}
+
+impl Connection {
+    async fn start(&mut self) -> Result<(), String> {
+        let remaining = self.handshake().await?;
+
+        let (mut session, results) =
+            ServerSession::new(ServerSessionConfig::new()).map_err(|e| format!("{:?}", e))?;
+
+        self.process(&mut session, results).await?;
+
+        let results = session
+            .handle_input(&remaining)
+            .map_err(|e| format!("{:?}", e))?;
+
+        self.process(&mut session, results).await?;
+
+        let mut buffer = [0; BUFFER_SIZE];
+
+        loop {
+            let count = self
+                .socket
+                .read(&mut buffer)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if count == 0 {
+                return Ok(());
+            }
+
+            let results = session
+                .handle_input(&buffer[..count])
+                .map_err(|e| format!("{:?}", e))?;
+
+            if !self.process(&mut session, results).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Return the bytes received after the handshake.
+    async fn handshake(&mut self) -> Result<Vec<u8>, String> {
+        let mut handshake = Handshake::new(PeerType::Server);
+        let mut buffer = [0; BUFFER_SIZE];
+
+        loop {
+            let count = self
+                .socket
+                .read(&mut buffer)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if count == 0 {
+                return Err("Connection closed during handshake".into());
+            }
+
+            match handshake
+                .process_bytes(&buffer[..count])
+                .map_err(|e| format!("{:?}", e))?
+            {
+                HandshakeProcessResult::InProgress { response_bytes } => {
+                    self.write(&response_bytes).await?;
+                }
+                HandshakeProcessResult::Completed {
+                    response_bytes,
+                    remaining_bytes,
+                } => {
+                    self.write(&response_bytes).await?;
+
+                    return Ok(remaining_bytes);
+                }
+            }
+        }
+    }
+
+    /// Send responses and handle events. Return false if the connection should be closed.
+    async fn process(
+        &mut self,
+        session: &mut ServerSession,
+        results: Vec<ServerSessionResult>,
+    ) -> Result<bool, String> {
+        let mut queue: VecDeque<ServerSessionResult> = results.into();
+
+        while let Some(result) = queue.pop_front() {
+            let event = match result {
+                ServerSessionResult::OutboundResponse(packet) => {
+                    self.write(&packet.bytes).await?;
+                    continue;
+                }
+                ServerSessionResult::RaisedEvent(event) => event,
+                ServerSessionResult::UnhandleableMessageReceived(_) => continue,
+            };
+
+            match event {
+                ServerSessionEvent::ConnectionRequested { request_id, .. } => {
+                    let results = session
+                        .accept_request(request_id)
+                        .map_err(|e| format!("{:?}", e))?;
+
+                    queue.extend(results);
+                }
+                ServerSessionEvent::PublishStreamRequested {
+                    request_id,
+                    stream_key,
+                    ..
+                } => {
+                    if let Some(key) = self.stream_key.as_ref() {
+                        if !same_key(&stream_key, key) {
+                            eprintln!("❗ RTMP Server: invalid stream key");
+                            return Ok(false);
+                        }
+                    }
+
+                    if self.remuxer.is_some() {
+                        eprintln!("❗ RTMP Server: already publishing");
+                        return Ok(false);
+                    }
+
+                    let stream = match self.streams.get_or_start(MAIN_STREAM) {
+                        Some(stream) => stream,
+                        None => {
+                            eprintln!("❗ RTMP Server: main stream not started");
+                            return Ok(false);
+                        }
+                    };
+
+                    let results = session
+                        .accept_request(request_id)
+                        .map_err(|e| format!("{:?}", e))?;
+
+                    queue.extend(results);
+
+                    self.remuxer =
+                        Some(Remuxer::new(self.ipfs.clone(), stream, self.segment_length));
+                }
+                ServerSessionEvent::StreamMetadataChanged { metadata, .. } => {
+                    if let Some(remuxer) = self.remuxer.as_mut() {
+                        remuxer.metadata(
+                            metadata.video_width,
+                            metadata.video_height,
+                            metadata.video_bitrate_kbps,
+                            metadata.audio_bitrate_kbps,
+                        );
+                    }
+                }
+                ServerSessionEvent::VideoDataReceived {
+                    data, timestamp, ..
+                } => {
+                    if let Some(remuxer) = self.remuxer.as_mut() {
+                        remuxer.video(timestamp.value, &data).await;
+                    }
+                }
+                ServerSessionEvent::AudioDataReceived {
+                    data, timestamp, ..
+                } => {
+                    if let Some(remuxer) = self.remuxer.as_mut() {
+                        remuxer.audio(timestamp.value, &data).await;
+                    }
+                }
+                ServerSessionEvent::PublishStreamFinished { .. } => {
+                    if let Some(mut remuxer) = self.remuxer.take() {
+                        remuxer.finish().await;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.socket
+            .write_all(bytes)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
@@ -1,19 +1,28 @@
 use crate::actors::{SetupData, VideoData};
+use crate::backend::{DagStore, Ipfs};
+use crate::server::streams::{StreamChannels, Streams, MAIN_STREAM};
+use crate::server::whip::{Whip, WHIP};
+use crate::utils::mpegts;
+use crate::utils::segment_names::{parse_stem, SegmentKind};
+
+use std::fmt::{Debug, Write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio::sync::mpsc::{Permit, Sender};
+use tokio::time::timeout;
+use tokio_util::io::StreamReader;
 
-use std::convert::TryFrom;
-use std::fmt::Debug;
-use std::path::Path;
-
+use futures_util::future::ready;
 use futures_util::stream::TryStreamExt;
 
-use tokio::sync::mpsc::UnboundedSender;
-use tokio_util::io::StreamReader;
-
-use hyper::header::{HeaderValue, LOCATION};
+use hyper::header::{HeaderValue, CONTENT_TYPE, LOCATION, RETRY_AFTER};
 use hyper::{Body, Error, Method, Request, Response, StatusCode};
 
-use ipfs_api::IpfsClient;
-
 use cid::Cid;
 
 use m3u8_rs::playlist::Playlist;
@@ -21,25 +30,31 @@ use m3u8_rs::playlist::Playlist;
 const M3U8: &str = "m3u8";
 pub const MP4: &str = "mp4";
 pub const M4S: &str = "m4s";
+const TS: &str = "ts";
+
+/// CMAF video and audio, initialization or media segments.
+const CMFV: &str = "cmfv";
+const CMFA: &str = "cmfa";
 
-const OPTIONS: ipfs_api::request::Add = ipfs_api::request::Add {
-    trickle: None,
-    only_hash: None,
-    wrap_with_directory: None,
-    chunker: None,
-    pin: Some(false),
-    raw_leaves: None,
-    cid_version: Some(1),
-    hash: None,
-    inline: None,
-    inline_limit: None,
-};
+/// Prometheus scrape endpoint.
+const METRICS: &str = "/metrics";
+
+/// Ingest waits this long on a full actor queue before asking the encoder to retry.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Uploads larger than this are rejected, before being read if their length is known.
+const MAX_SEGMENT_SIZE: usize = 64 * 1024 * 1024;
+const MAX_PLAYLIST_SIZE: usize = 1024 * 1024;
+
+/// Seconds the encoder should wait before retrying a rejected upload.
+const RETRY_AFTER_SECS: &str = "1";
 
 pub async fn put_requests(
     req: Request<Body>,
-    video_tx: UnboundedSender<VideoData>,
-    setup_tx: UnboundedSender<SetupData>,
-    ipfs: IpfsClient,
+    streams: Streams,
+    ipfs: Ipfs,
+    stream_key: Option<String>,
+    whip: Option<Whip>,
 ) -> Result<Response<Body>, Error> {
     #[cfg(debug_assertions)]
     println!("Service: {:#?}", req);
@@ -48,55 +63,350 @@ pub async fn put_requests(
 
     let (parts, body) = req.into_parts();
 
-    let path = Path::new(parts.uri.path());
+    let path = match authorize(parts.uri.path(), stream_key.as_deref()) {
+        Some(path) => Path::new(path),
+        None => return unauthorized_response(res),
+    };
 
-    if parts.method != Method::PUT
-        || path.extension() == None
-        || (path.extension().unwrap() != M3U8
-            && path.extension().unwrap() != M4S
-            && path.extension().unwrap() != MP4)
-    {
+    if let Some(whip) = whip {
+        if path.iter().nth(1).map_or(false, |part| part == WHIP) {
+            return whip_response(res, body, &parts.method, parts.uri.path(), path, whip).await;
+        }
+    }
+
+    if parts.method == Method::GET && path == Path::new(METRICS) {
+        return metrics_response(res, &streams);
+    }
+
+    if parts.method != Method::PUT {
         return not_found_response(res);
     }
 
-    if path.extension().unwrap() == M3U8 {
-        return manifest_response(res, body, &path, setup_tx).await;
+    if path.extension() == Some(M3U8.as_ref()) {
+        return manifest_response(res, body, path, streams).await;
     }
 
-    //Change error type
-    let stream =
-        body.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()));
+    let upload = match parse_upload(path) {
+        Some(upload) => upload,
+        None => return bad_request_response(res, &format!("Unknown segment {:?}", path)),
+    };
 
-    //Stream to AsyncRead
-    let reader = StreamReader::new(stream);
+    let stream = match streams.get_or_start(upload.stream) {
+        Some(stream) => stream,
+        None => return not_found_response(res),
+    };
+
+    let (variant, kind) = (upload.variant, upload.kind);
 
-    let cid = match ipfs.add_with_options(reader, OPTIONS).await {
-        Ok(res) => Cid::try_from(res.hash).expect("Invalid Cid"),
-        Err(error) => return internal_error_response(res, &error),
+    // TS segments are remuxed, their initialization segment derived from them
+    let ts_index = match kind {
+        SegmentKind::Media(index) if upload.extension == TS => Some(index),
+        SegmentKind::Init if upload.extension == TS => {
+            return bad_request_response(res, &"TS initialization segment")
+        }
+        _ => None,
     };
 
-    #[cfg(debug_assertions)]
-    println!("IPFS: add => {}", &cid.to_string());
+    if announced_size(&body) > MAX_SEGMENT_SIZE as u64 {
+        return too_large_response(res);
+    }
 
-    if path.extension().unwrap() == M4S {
-        let msg = VideoData::Segment((path.to_path_buf(), cid));
+    // Room in the actor queues is held before the upload is read and added to IPFS
+    let slot = match Slot::reserve(&stream, kind).await {
+        Ok(slot) => slot,
+        Err(error) => return queue_error_response(res, error),
+    };
 
-        if let Err(error) = video_tx.send(msg) {
-            return internal_error_response(res, &error);
+    let init_slot = if ts_index.is_some() && !streams.has_init_segment(upload.stream, variant) {
+        match reserve(&stream.setup_tx).await {
+            Ok(permit) => Some(permit),
+            Err(error) => return queue_error_response(res, error),
         }
-    } else if path.extension().unwrap() == MP4 {
-        let msg = SetupData::Segment((path.to_path_buf(), cid));
+    } else {
+        None
+    };
+
+    let (path, cid) = if let Some(index) = ts_index {
+        let bytes = match read_body(body, MAX_SEGMENT_SIZE).await? {
+            Some(bytes) => bytes,
+            None => return too_large_response(res),
+        };
+
+        let fmp4 = match mpegts::to_fmp4(&bytes, index as u32 + 1) {
+            Ok(fmp4) => fmp4,
+            Err(error) => return bad_request_response(res, &error),
+        };
+
+        if let Some(permit) = init_slot {
+            let cid = match add_bytes(&ipfs, fmp4.init).await {
+                Ok(cid) => cid,
+                Err(error) => return internal_error_response(res, &error),
+            };
+
+            let path = PathBuf::from(format!("/{}/init.{}", variant, MP4));
 
-        if let Err(error) = setup_tx.send(msg) {
-            return internal_error_response(res, &error);
+            permit.send(SetupData::Segment((path, cid)));
+
+            streams.init_segment_queued(upload.stream, variant);
         }
+
+        let cid = match add_bytes(&ipfs, fmp4.segment).await {
+            Ok(cid) => cid,
+            Err(error) => return internal_error_response(res, &error),
+        };
+
+        (
+            PathBuf::from(format!("/{}/{}.{}", variant, index, M4S)),
+            cid,
+        )
+    } else {
+        let cid = match add_body(&ipfs, body, MAX_SEGMENT_SIZE).await {
+            Ok(Some(cid)) => cid,
+            Ok(None) => return too_large_response(res),
+            Err(error) => return internal_error_response(res, &error),
+        };
+
+        let name = match kind {
+            SegmentKind::Init => "init".to_owned(),
+            SegmentKind::Media(index) => index.to_string(),
+        };
+
+        let path = PathBuf::from(format!("/{}/{}.{}", variant, name, upload.extension));
+
+        (path, cid)
+    };
+
+    #[cfg(debug_assertions)]
+    println!("IPFS: add {:?} => {}", path, &cid.to_string());
+
+    match slot {
+        Slot::Setup(permit) => permit.send(SetupData::Segment((path, cid))),
+        Slot::Video(permit) => permit.send(VideoData::Segment((path, cid))),
     }
 
     *res.status_mut() = StatusCode::CREATED;
 
-    let header_value = HeaderValue::from_str(parts.uri.path()).expect("Invalid Header Value");
+    if let Ok(header_value) = HeaderValue::from_str(parts.uri.path()) {
+        res.headers_mut().insert(LOCATION, header_value);
+    }
+
+    #[cfg(debug_assertions)]
+    println!("Service: {:#?}", res);
+
+    Ok(res)
+}
+
+/// Uploaded segment. The variant is either its folder or a part of its name.
+struct Upload<'a> {
+    stream: &'a str,
+    variant: &'a str,
+    kind: SegmentKind,
+    extension: &'a str,
+}
+
+/// /<stream>/<variant>/<n>.<ext> or /<stream>/<variant>_<n>.<ext>, the stream being optional.
+fn parse_upload(path: &Path) -> Option<Upload> {
+    let extension = path.extension()?.to_str()?;
+    let stem = path.file_stem()?.to_str()?;
 
-    res.headers_mut().insert(LOCATION, header_value);
+    let (name_variant, kind) = parse_stem(stem)?;
+
+    let valid = match kind {
+        SegmentKind::Init => [MP4, CMFV, CMFA].contains(&extension),
+        SegmentKind::Media(_) => [M4S, CMFV, CMFA, TS].contains(&extension),
+    };
+
+    if !valid {
+        return None;
+    }
+
+    let (stream, variant) = match name_variant {
+        Some(variant) => (split_stream(path, 1)?.0, variant),
+        None => {
+            let (stream, path) = split_stream(path, 2)?;
+
+            let variant = path.parent()?.file_name()?.to_str()?;
+
+            (stream, variant)
+        }
+    };
+
+    Some(Upload {
+        stream,
+        variant,
+        kind,
+        extension,
+    })
+}
+
+/// Room in the queue of the actor receiving the segment.
+enum Slot<'a> {
+    Setup(Permit<'a, SetupData>),
+    Video(Permit<'a, VideoData>),
+}
+
+impl<'a> Slot<'a> {
+    /// Initialization segments go to the setup aggregator, media segments to the video one.
+    async fn reserve(
+        stream: &'a StreamChannels,
+        kind: SegmentKind,
+    ) -> Result<Slot<'a>, SendTimeoutError<()>> {
+        let slot = match kind {
+            SegmentKind::Init => Self::Setup(reserve(&stream.setup_tx).await?),
+            SegmentKind::Media(_) => Self::Video(reserve(&stream.video_tx).await?),
+        };
+
+        Ok(slot)
+    }
+}
+
+/// Wait for room in the actor queue, up to a limit.
+async fn enqueue<T>(tx: &Sender<T>, msg: T) -> Result<(), SendTimeoutError<T>> {
+    tx.send_timeout(msg, QUEUE_TIMEOUT).await
+}
+
+/// Wait for room in the actor queue, up to a limit, and keep it until a message is sent.
+async fn reserve<T>(tx: &Sender<T>) -> Result<Permit<'_, T>, SendTimeoutError<()>> {
+    match timeout(QUEUE_TIMEOUT, tx.reserve()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        Ok(Err(_)) => Err(SendTimeoutError::Closed(())),
+        Err(_) => Err(SendTimeoutError::Timeout(())),
+    }
+}
+
+/// Length of the body if known in advance, zero otherwise.
+fn announced_size(body: &Body) -> u64 {
+    hyper::body::HttpBody::size_hint(body).lower()
+}
+
+/// Read the whole request body. None once larger than this size.
+async fn read_body(mut body: Body, max_size: usize) -> Result<Option<Vec<u8>>, Error> {
+    let mut data = Vec::new();
+
+    while let Some(chunk) = body.try_next().await? {
+        if data.len() + chunk.len() > max_size {
+            return Ok(None);
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(data))
+}
+
+async fn add_bytes(ipfs: &Ipfs, data: Vec<u8>) -> Result<Cid, ipfs_api::response::Error> {
+    ipfs.add(data).await
+}
+
+/// Add the request body as it is received. None once larger than this size.
+async fn add_body(
+    ipfs: &Ipfs,
+    body: Body,
+    max_size: usize,
+) -> Result<Option<Cid>, ipfs_api::response::Error> {
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let flag = exceeded.clone();
+
+    let mut size = 0;
+
+    // The add fails with the body, the flag tells why
+    let stream = body.map_err(io::Error::other).and_then(move |chunk| {
+        size += chunk.len();
+
+        if size > max_size {
+            flag.store(true, Ordering::Relaxed);
+
+            return ready(Err(io::Error::other("Body too large")));
+        }
+
+        ready(Ok(chunk))
+    });
+
+    let result = ipfs.add_reader(Box::pin(StreamReader::new(stream))).await;
+
+    if exceeded.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    result.map(Some)
+}
+
+/// POST /whip to publish, DELETE /whip/<SESSION> when done.
+async fn whip_response(
+    res: Response<Body>,
+    body: Body,
+    method: &Method,
+    uri_path: &str,
+    path: &Path,
+    whip: Whip,
+) -> Result<Response<Body>, Error> {
+    let count = path.iter().count();
+
+    match (method, count) {
+        (&Method::OPTIONS, _) => whip.options(res),
+        (&Method::POST, 2) => whip.offer(res, body, uri_path).await,
+        (&Method::DELETE, 3) => match path.file_name().and_then(|id| id.to_str()) {
+            Some(id) => whip.delete(res, id).await,
+            None => not_found_response(res),
+        },
+        _ => not_found_response(res),
+    }
+}
+
+/// Strip the stream key from the path. /<STREAM_KEY>/720p30/1.m4s => /720p30/1.m4s
+fn authorize<'a>(path: &'a str, stream_key: Option<&str>) -> Option<&'a str> {
+    let stream_key = match stream_key {
+        Some(key) => key,
+        None => return Some(path),
+    };
+
+    let path = path.strip_prefix('/')?;
+
+    let (key, path) = path.split_at(path.find('/')?);
+
+    if !same_key(key, stream_key) {
+        return None;
+    }
+
+    Some(path)
+}
+
+/// Constant time comparison of stream keys.
+pub fn same_key(key: &str, stream_key: &str) -> bool {
+    if key.len() != stream_key.len() {
+        return false;
+    }
+
+    let diff = key
+        .bytes()
+        .zip(stream_key.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+
+    diff == 0
+}
+
+/// Split the stream ID from the path. /<stream>/720p30/1.m4s => (<stream>, /720p30/1.m4s)
+/// Paths of the main stream have this depth, namespaced paths one more.
+fn split_stream(path: &Path, depth: usize) -> Option<(&str, &Path)> {
+    let count = path.iter().filter(|part| *part != "/").count();
+
+    if count == depth {
+        return Some((MAIN_STREAM, path));
+    }
+
+    if count != depth + 1 {
+        return None;
+    }
+
+    let id = path.iter().find(|part| *part != "/")?.to_str()?;
+
+    let path = Path::new(path.to_str()?.strip_prefix('/')?.strip_prefix(id)?);
+
+    Some((id, path))
+}
+
+fn unauthorized_response(mut res: Response<Body>) -> Result<Response<Body>, Error> {
+    *res.status_mut() = StatusCode::UNAUTHORIZED;
 
     #[cfg(debug_assertions)]
     println!("Service: {:#?}", res);
@@ -104,7 +414,21 @@ pub async fn put_requests(
     Ok(res)
 }
 
-fn not_found_response(mut res: Response<Body>) -> Result<Response<Body>, Error> {
+fn bad_request_response(
+    mut res: Response<Body>,
+    error: &dyn Debug,
+) -> Result<Response<Body>, Error> {
+    eprintln!("Service: {:?}", error);
+
+    *res.status_mut() = StatusCode::BAD_REQUEST;
+
+    #[cfg(debug_assertions)]
+    println!("Service: {:#?}", res);
+
+    Ok(res)
+}
+
+pub fn not_found_response(mut res: Response<Body>) -> Result<Response<Body>, Error> {
     *res.status_mut() = StatusCode::NOT_FOUND;
 
     #[cfg(debug_assertions)]
@@ -117,28 +441,142 @@ async fn manifest_response(
     mut res: Response<Body>,
     body: Body,
     path: &Path,
-    setup_tx: UnboundedSender<SetupData>,
+    streams: Streams,
 ) -> Result<Response<Body>, Error> {
-    let bytes = hyper::body::to_bytes(body).await?;
+    if announced_size(&body) > MAX_PLAYLIST_SIZE as u64 {
+        return too_large_response(res);
+    }
+
+    let bytes = match read_body(body, MAX_PLAYLIST_SIZE).await? {
+        Some(bytes) => bytes,
+        None => return too_large_response(res),
+    };
 
     let playlist = match m3u8_rs::parse_playlist(&bytes) {
         Ok((_, playlist)) => playlist,
-        Err(e) => return internal_error_response(res, &e),
+        Err(e) => return bad_request_response(res, &e),
+    };
+
+    // Media playlists of segments named after their variant are not in variant folders.
+    // 720p30.m3u8 => 720p30_001.m4s
+    let flat_variant = match &playlist {
+        Playlist::MediaPlaylist(playlist) => playlist
+            .segments
+            .first()
+            .and_then(|segment| Path::new(&segment.uri).file_stem()?.to_str())
+            .and_then(parse_stem)
+            .and_then(|(variant, _)| variant.map(str::to_owned)),
+        Playlist::MasterPlaylist(_) => None,
+    };
+
+    // Master playlists are at the root of the stream, media playlists in variant folders.
+    let depth = match (&playlist, &flat_variant) {
+        (Playlist::MediaPlaylist(_), None) => 2,
+        _ => 1,
+    };
+
+    let (id, path) = match split_stream(path, depth) {
+        Some(split) => split,
+        None => return bad_request_response(res, &format!("Unknown playlist {:?}", path)),
+    };
+
+    let path = match flat_variant {
+        Some(variant) => PathBuf::from(format!("/{}/index.{}", variant, M3U8)),
+        None => path.to_path_buf(),
+    };
+
+    let stream = match streams.get_or_start(id) {
+        Some(stream) => stream,
+        None => return not_found_response(res),
     };
 
-    if let Playlist::MasterPlaylist(playlist) = playlist {
-        let msg = SetupData::Playlist(playlist);
+    match playlist {
+        Playlist::MasterPlaylist(playlist) => {
+            let msg = SetupData::Playlist(playlist);
+
+            if let Err(error) = enqueue(&stream.setup_tx, msg).await {
+                return queue_error_response(res, error);
+            }
+        }
+        Playlist::MediaPlaylist(playlist) => {
+            let msg = VideoData::Playlist((path.clone(), playlist));
 
-        if let Err(error) = setup_tx.send(msg) {
-            return internal_error_response(res, &error);
+            if let Err(error) = enqueue(&stream.video_tx, msg).await {
+                return queue_error_response(res, error);
+            }
         }
     }
 
     *res.status_mut() = StatusCode::NO_CONTENT;
 
-    let header_value = HeaderValue::from_str(path.to_str().unwrap()).unwrap();
+    if let Some(header_value) = path
+        .to_str()
+        .and_then(|path| HeaderValue::from_str(path).ok())
+    {
+        res.headers_mut().insert(LOCATION, header_value);
+    }
+
+    #[cfg(debug_assertions)]
+    println!("Service: {:#?}", res);
+
+    Ok(res)
+}
+
+/// Queue depth of every actor, in Prometheus text format.
+fn metrics_response(mut res: Response<Body>, streams: &Streams) -> Result<Response<Body>, Error> {
+    let mut body = String::from(
+        "# HELP streamer_queue_depth Messages waiting in the queue of each actor.\n\
+         # TYPE streamer_queue_depth gauge\n",
+    );
+
+    for (stream, actor, depth) in streams.queue_depths() {
+        let _ = writeln!(
+            body,
+            "streamer_queue_depth{{stream=\"{}\",actor=\"{}\"}} {}",
+            stream, actor, depth
+        );
+    }
+
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+
+    *res.body_mut() = Body::from(body);
+
+    Ok(res)
+}
+
+/// Full queue means the pipeline fell behind, the encoder should retry later.
+/// Ffmpeg drops the segment instead, the video aggregator archives a gap in its place.
+fn queue_error_response<T>(
+    res: Response<Body>,
+    error: SendTimeoutError<T>,
+) -> Result<Response<Body>, Error> {
+    match error {
+        SendTimeoutError::Timeout(_) => unavailable_response(res),
+        SendTimeoutError::Closed(_) => internal_error_response(res, &"Actor receiver hung up"),
+    }
+}
+
+fn too_large_response(mut res: Response<Body>) -> Result<Response<Body>, Error> {
+    eprintln!("Service: Upload too large, rejected");
+
+    *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+
+    #[cfg(debug_assertions)]
+    println!("Service: {:#?}", res);
+
+    Ok(res)
+}
+
+fn unavailable_response(mut res: Response<Body>) -> Result<Response<Body>, Error> {
+    eprintln!("Service: Actors fell behind, ingest rejected");
+
+    *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
 
-    res.headers_mut().insert(LOCATION, header_value);
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
 
     #[cfg(debug_assertions)]
     println!("Service: {:#?}", res);
@@ -146,7 +584,7 @@ async fn manifest_response(
     Ok(res)
 }
 
-fn internal_error_response(
+pub fn internal_error_response(
     mut res: Response<Body>,
     error: &dyn Debug,
 ) -> Result<Response<Body>, Error> {
@@ -0,0 +1,250 @@
+use crate::actors::{Archive, Archivist, SetupAggregator, SetupData, VideoAggregator, VideoData};
+use crate::backend::Ipfs;
+use crate::utils::config::{ArchiveConfig, VideoConfig};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task::JoinHandle;
+
+use linked_data::codec::Codec;
+
+/// ID of the stream ingested without namespace. /<variant>/<n>.m4s
+pub const MAIN_STREAM: &str = "";
+
+/// Actors of one stream.
+#[derive(Clone)]
+pub struct StreamChannels {
+    pub video_tx: Sender<VideoData>,
+    pub setup_tx: Sender<SetupData>,
+    pub archive_tx: Option<Sender<Archive>>,
+}
+
+impl StreamChannels {
+    /// Messages waiting in the queue of each actor, all of this capacity.
+    pub fn queue_depths(&self, capacity: usize) -> Vec<(&'static str, usize)> {
+        let mut depths = vec![
+            ("setup", capacity.saturating_sub(self.setup_tx.capacity())),
+            ("video", capacity.saturating_sub(self.video_tx.capacity())),
+        ];
+
+        if let Some(archive_tx) = self.archive_tx.as_ref() {
+            depths.push(("archive", capacity.saturating_sub(archive_tx.capacity())));
+        }
+
+        depths
+    }
+}
+
+/// Actors of every stream by ID, configured streams are started on their first request.
+#[derive(Clone)]
+pub struct Streams {
+    ipfs: Ipfs,
+    codec: Codec,
+
+    /// Configuration of new streams. No archive if none.
+    archive: Option<ArchiveConfig>,
+    video: VideoConfig,
+
+    /// Capacity of the actor queues, the same for streams started elsewhere.
+    queue_depth: usize,
+
+    /// IDs of the streams that can be started on request.
+    ids: Arc<HashSet<String>>,
+
+    channels: Arc<Mutex<HashMap<String, StreamChannels>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+
+    /// Variants of each stream whose initialization segment was queued. Used for remuxed segments without one.
+    variants: Arc<Mutex<HashSet<(String, String)>>>,
+}
+
+impl Streams {
+    pub fn new(
+        ipfs: Ipfs,
+        codec: Codec,
+        archive: Option<ArchiveConfig>,
+        video: VideoConfig,
+        queue_depth: usize,
+        ids: Vec<String>,
+    ) -> Self {
+        Self {
+            ipfs,
+            codec,
+
+            archive,
+            video,
+
+            queue_depth,
+
+            ids: Arc::new(ids.into_iter().collect()),
+
+            channels: Arc::new(Mutex::new(HashMap::with_capacity(4))),
+            handles: Arc::new(Mutex::new(Vec::new())),
+
+            variants: Arc::new(Mutex::new(HashSet::with_capacity(4))),
+        }
+    }
+
+    /// Add a stream whose actors were started elsewhere.
+    pub fn insert(&self, id: &str, channels: StreamChannels) {
+        self.channels
+            .lock()
+            .expect("Lock Poisoned")
+            .insert(id.to_owned(), channels);
+    }
+
+    /// Get the actors of this stream, starting them if configured. None for unknown streams.
+    pub fn get_or_start(&self, id: &str) -> Option<StreamChannels> {
+        let mut channels = self.channels.lock().expect("Lock Poisoned");
+
+        if let Some(stream) = channels.get(id) {
+            return Some(stream.clone());
+        }
+
+        if !self.ids.contains(id) {
+            return None;
+        }
+
+        let stream = self.start_stream(id);
+
+        channels.insert(id.to_owned(), stream.clone());
+
+        Some(stream)
+    }
+
+    /// True once the initialization segment of this variant was queued.
+    pub fn has_init_segment(&self, id: &str, variant: &str) -> bool {
+        self.variants
+            .lock()
+            .expect("Lock Poisoned")
+            .contains(&(id.to_owned(), variant.to_owned()))
+    }
+
+    /// Called after queueing the initialization segment so that a failed upload is retried.
+    pub fn init_segment_queued(&self, id: &str, variant: &str) {
+        self.variants
+            .lock()
+            .expect("Lock Poisoned")
+            .insert((id.to_owned(), variant.to_owned()));
+    }
+
+    /// Streams started on request have no journal and do not archive chat.
+    /// Only the main stream, started by the CLI, can be resumed.
+    fn start_stream(&self, id: &str) -> StreamChannels {
+        println!("New Stream => {}", id);
+
+        let mut handles = self.handles.lock().expect("Lock Poisoned");
+
+        let archive_tx = match self.archive.as_ref() {
+            Some(config) => {
+                let (archive_tx, archive_rx) = channel(self.queue_depth);
+
+                let mut config = config.clone();
+                config.archive_live_chat = false;
+                config.title = config.title.map(|title| format!("{} ({})", title, id));
+
+                let mut archivist =
+                    Archivist::new(self.ipfs.clone(), archive_rx, config, self.codec, None);
+
+                handles.push(tokio::spawn(async move {
+                    archivist.start().await;
+                }));
+
+                Some(archive_tx)
+            }
+            None => None,
+        };
+
+        let (video_tx, video_rx) = channel(self.queue_depth);
+
+        let mut config = self.video.clone();
+        config.pubsub_topic = format!("{}_{}", config.pubsub_topic, id);
+
+        let mut video = VideoAggregator::new(
+            self.ipfs.clone(),
+            video_rx,
+            archive_tx.clone(),
+            config,
+            self.codec,
+            None,
+        );
+
+        handles.push(tokio::spawn(async move {
+            video.start().await;
+        }));
+
+        let (setup_tx, setup_rx) = channel(self.queue_depth);
+
+        let mut setup =
+            SetupAggregator::new(self.ipfs.clone(), setup_rx, video_tx.clone(), self.codec);
+
+        handles.push(tokio::spawn(async move {
+            setup.start().await;
+        }));
+
+        StreamChannels {
+            video_tx,
+            setup_tx,
+            archive_tx,
+        }
+    }
+
+    /// Queue depths of the actors of every stream.
+    pub fn queue_depths(&self) -> Vec<(String, &'static str, usize)> {
+        let channels = self.channels.lock().expect("Lock Poisoned");
+
+        let mut depths = Vec::with_capacity(channels.len() * 3);
+
+        for (id, stream) in channels.iter() {
+            for (actor, depth) in stream.queue_depths(self.queue_depth) {
+                depths.push((id.clone(), actor, depth));
+            }
+        }
+
+        depths
+    }
+
+    /// Finalize every archive then forget all streams so that their actors stop.
+    /// Return true if any stream was archived.
+    pub async fn finalize(&self) -> bool {
+        let streams: Vec<(String, StreamChannels)> = self
+            .channels
+            .lock()
+            .expect("Lock Poisoned")
+            .drain()
+            .collect();
+
+        self.variants.lock().expect("Lock Poisoned").clear();
+
+        let mut archived = false;
+
+        for (id, stream) in streams {
+            let archive_tx = match stream.archive_tx {
+                Some(archive_tx) => archive_tx,
+                None => continue,
+            };
+
+            archived = true;
+
+            // Waits for the segments still queued
+            if let Err(error) = archive_tx.send(Archive::Finalize).await {
+                eprintln!("Archive receiver of stream {} hung up {}", id, error);
+            }
+        }
+
+        archived
+    }
+
+    /// Wait for the actors of streams started on request.
+    pub async fn join(&self) {
+        let handles = std::mem::take(&mut *self.handles.lock().expect("Lock Poisoned"));
+
+        for handle in handles {
+            if let Err(e) = handle.await {
+                eprintln!("❗ Main: {}", e);
+            }
+        }
+    }
+}
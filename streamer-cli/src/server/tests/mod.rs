@@ -0,0 +1,676 @@
+//! Ingest to archive through the HTTP server and every actor, against an in-memory IPFS.
+
+use crate::actors::{Archivist, SetupAggregator, VideoAggregator};
+use crate::backend::{DagStore, Ipfs, MemoryBackend, PubSub, PubSubMessage};
+use crate::server::{start_server, StreamChannels, Streams, MAIN_STREAM};
+use crate::utils::config::{ArchiveConfig, VideoConfig};
+use crate::utils::dag_nodes::ipfs_dag_get_node_async;
+use crate::utils::fmp4::{audio_init, media_segment, video_init, Sample};
+use crate::utils::journal::Journal;
+
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+use tokio_stream::StreamExt;
+
+use futures_util::stream::BoxStream;
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+
+use ipfs_api::response::Error;
+
+use linked_data::codec::Codec;
+use linked_data::video::{
+    is_gap, DayNode, HourNode, MinuteNode, SecondNode, SetupNode, TimecodeNode, VideoNode,
+};
+use linked_data::IPLDLink;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use cid::Cid;
+
+const MASTER_PLAYLIST: &str = include_str!("master.m3u8");
+
+/// Tracks of the master playlist by bandwidth.
+const TRACKS: [&str; 3] = ["audio", "360p30", "720p30"];
+
+const AUDIO_TIMESCALE: u32 = 48000;
+const VIDEO_TIMESCALE: u32 = 90000;
+
+/// Segments kept in media playlists, like ffmpeg hls_list_size.
+const PLAYLIST_SIZE: usize = 6;
+
+const TOPIC: &str = "video";
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Small actor queues, ingest has to wait on the actors.
+const QUEUE_DEPTH: usize = 4;
+
+struct Harness {
+    memory: Arc<MemoryBackend>,
+
+    streams: Streams,
+
+    client: Client<HttpConnector>,
+    addr: SocketAddr,
+
+    server: JoinHandle<()>,
+
+    /// Actors of the main stream.
+    actors: Vec<JoinHandle<()>>,
+
+    /// Directory of the archive journal, one per test.
+    journal_dir: PathBuf,
+
+    /// Video nodes, as published by the video aggregator.
+    minted: BoxStream<'static, Result<PubSubMessage, Error>>,
+}
+
+/// Every node of an archive.
+struct Archived {
+    root: TimecodeNode,
+    hours: Vec<HourNode>,
+    minutes: Vec<MinuteNode>,
+    seconds: Vec<SecondNode>,
+
+    /// Video nodes in the order they were published.
+    videos: Vec<(Cid, VideoNode)>,
+}
+
+impl Harness {
+    /// Main stream actors started and inserted like the stream command does.
+    async fn start() -> Self {
+        let memory = Arc::new(MemoryBackend::new());
+        let ipfs: Ipfs = memory.clone();
+        let codec = Codec::default();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("No free port");
+        let addr = listener.local_addr().expect("No local address");
+
+        let archive = ArchiveConfig {
+            archive_live_chat: false,
+            title: None,
+            image: None,
+        };
+
+        let video = VideoConfig {
+            pubsub_enable: true,
+            pubsub_topic: TOPIC.to_owned(),
+        };
+
+        let journal_dir = std::env::temp_dir().join(format!("streamer-test-{}", addr.port()));
+
+        std::fs::create_dir_all(&journal_dir).expect("Journal directory failed");
+
+        let journal = Journal::create(&journal_dir, &[], &[])
+            .await
+            .expect("Journal failed");
+
+        let mut actors = Vec::with_capacity(3);
+
+        let (archive_tx, archive_rx) = channel(QUEUE_DEPTH);
+
+        let mut archivist = Archivist::new(
+            ipfs.clone(),
+            archive_rx,
+            archive.clone(),
+            codec,
+            Some(journal),
+        );
+
+        actors.push(tokio::spawn(async move {
+            archivist.start().await;
+        }));
+
+        let streams = Streams::new(
+            ipfs.clone(),
+            codec,
+            Some(archive),
+            video.clone(),
+            QUEUE_DEPTH,
+            Vec::new(),
+        );
+
+        let (video_tx, video_rx) = channel(QUEUE_DEPTH);
+        let (end_tx, end_rx) = oneshot::channel();
+
+        let mut video = VideoAggregator::new(
+            ipfs.clone(),
+            video_rx,
+            Some(archive_tx.clone()),
+            video,
+            codec,
+            Some(end_tx),
+        );
+
+        actors.push(tokio::spawn(async move {
+            video.start().await;
+        }));
+
+        let (setup_tx, setup_rx) = channel(QUEUE_DEPTH);
+
+        let mut setup = SetupAggregator::new(ipfs.clone(), setup_rx, video_tx.clone(), codec);
+
+        actors.push(tokio::spawn(async move {
+            setup.start().await;
+        }));
+
+        let channels = StreamChannels {
+            video_tx,
+            setup_tx,
+            archive_tx: Some(archive_tx),
+        };
+
+        streams.insert(MAIN_STREAM, channels);
+
+        let minted = ipfs.pubsub_sub(TOPIC);
+
+        let server = tokio::spawn(start_server(
+            listener,
+            streams.clone(),
+            ipfs,
+            TOPIC.to_owned(),
+            Some(end_rx),
+            None,
+            None,
+        ));
+
+        while TcpStream::connect(addr).await.is_err() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        Self {
+            memory,
+
+            streams,
+
+            client: Client::new(),
+            addr,
+
+            server,
+
+            actors,
+            journal_dir,
+
+            minted,
+        }
+    }
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> StatusCode {
+        let req = Request::builder()
+            .method(Method::PUT)
+            .uri(format!("http://{}{}", self.addr, path))
+            .body(Body::from(data))
+            .expect("Invalid Request");
+
+        let res = self.client.request(req).await.expect("PUT failed");
+
+        res.status()
+    }
+
+    async fn get(&self, path: &str) -> String {
+        let uri = format!("http://{}{}", self.addr, path)
+            .parse()
+            .expect("Invalid Uri");
+
+        let res = self.client.get(uri).await.expect("GET failed");
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let bytes = hyper::body::to_bytes(res.into_body())
+            .await
+            .expect("Body failed");
+
+        String::from_utf8(bytes.to_vec()).expect("Invalid Unicode")
+    }
+
+    async fn put_master_playlist(&self) {
+        let status = self.put("/master.m3u8", MASTER_PLAYLIST.into()).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    async fn put_init_segment(&self, track: &str) {
+        let path = format!("/{}/init.mp4", track);
+
+        let status = self.put(&path, init_segment(track)).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    async fn put_media_segment(&self, track: &str, index: usize) {
+        let path = format!("/{}/{}.m4s", track, index);
+
+        let status = self.put(&path, media_segment_of(track, index)).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    /// Media playlist of every track, ending with this segment.
+    async fn put_media_playlists(&self, last: usize) {
+        for track in TRACKS.iter() {
+            let path = format!("/{}/index.m3u8", track);
+
+            let status = self.put(&path, media_playlist(last).into()).await;
+
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+    }
+
+    /// Media playlist of every track, ending the stream.
+    async fn put_end_playlists(&self, last: usize) {
+        for track in TRACKS.iter() {
+            let path = format!("/{}/index.m3u8", track);
+
+            let mut playlist = media_playlist(last);
+            playlist.push_str("#EXT-X-ENDLIST\n");
+
+            let status = self.put(&path, playlist.into()).await;
+
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+    }
+
+    /// Wait for the video nodes of this many segments, gaps are not published.
+    /// End the stream then collect the archive.
+    async fn archive(mut self, count: usize, gaps: usize) -> Archived {
+        let mut published = Vec::with_capacity(count);
+
+        while published.len() < count - gaps {
+            let msg = timeout(TIMEOUT, self.minted.next())
+                .await
+                .expect("Video nodes not minted")
+                .expect("Topic closed")
+                .expect("PubSub error");
+
+            let data = msg.data.expect("Empty message");
+            let cid = String::from_utf8(data).expect("Invalid Unicode");
+
+            published.push(Cid::try_from(cid).expect("Invalid Cid"));
+        }
+
+        // The video aggregator signals the end of stream to the server
+        self.put_end_playlists(count - 1).await;
+
+        drop(self.client);
+
+        timeout(TIMEOUT, self.server)
+            .await
+            .expect("Server not stopped")
+            .expect("Server panicked");
+
+        for actor in self.actors {
+            timeout(TIMEOUT, actor)
+                .await
+                .expect("Actors not stopped")
+                .expect("Actor panicked");
+        }
+
+        timeout(TIMEOUT, self.streams.join())
+            .await
+            .expect("Actors not stopped");
+
+        let entries = Journal::read(&self.journal_dir)
+            .await
+            .expect("Journal failed");
+
+        assert!(
+            entries.is_empty(),
+            "Journal should be removed once finalized"
+        );
+
+        let pending = Journal::read_pending(&self.journal_dir, &entries)
+            .await
+            .expect("Outbox failed");
+
+        assert!(
+            pending.is_empty(),
+            "Outbox should be removed once finalized"
+        );
+
+        std::fs::remove_dir_all(&self.journal_dir).expect("Journal directory failed");
+
+        let pins = self.memory.pins();
+
+        assert_eq!(pins.len(), 1, "Only the archive should be pinned");
+
+        let ipfs = self.memory;
+
+        let root: TimecodeNode = get(&ipfs, &pins[0].into()).await;
+        let day: DayNode = get(&ipfs, &root.timecode).await;
+
+        let mut hours = Vec::with_capacity(day.links_to_hours.len());
+
+        for link in day.links_to_hours.iter() {
+            hours.push(get::<HourNode>(&ipfs, link).await);
+        }
+
+        let mut minutes = Vec::new();
+
+        for hour in hours.iter() {
+            for link in hour.links_to_minutes.iter() {
+                minutes.push(get::<MinuteNode>(&ipfs, link).await);
+            }
+        }
+
+        let mut seconds = Vec::new();
+
+        for minute in minutes.iter() {
+            for link in minute.links_to_seconds.iter() {
+                seconds.push(get::<SecondNode>(&ipfs, link).await);
+            }
+        }
+
+        let mut videos = Vec::with_capacity(published.len());
+
+        for cid in published {
+            videos.push((cid, get::<VideoNode>(&ipfs, &cid.into()).await));
+        }
+
+        Archived {
+            root,
+            hours,
+            minutes,
+            seconds,
+            videos,
+        }
+    }
+}
+
+impl Archived {
+    /// One second per segment, video nodes chained and linking every track.
+    async fn assert_segments(&self, ipfs: &MemoryBackend, count: usize) {
+        assert_eq!(self.root.duration, Some(count as f64));
+        assert_eq!(self.seconds.len(), count);
+        assert_eq!(self.videos.len(), count);
+
+        for (index, (cid, video)) in self.videos.iter().enumerate() {
+            assert_eq!(self.seconds[index].link_to_video.link, *cid);
+
+            let previous = index.checked_sub(1).map(|i| self.videos[i].0.into());
+
+            assert_eq!(video.previous, previous, "Video node {}", index);
+            assert_eq!(video.duration, Some(1.0));
+            assert_eq!(video.tracks.len(), TRACKS.len());
+
+            for track in TRACKS.iter() {
+                let link = video.tracks[*track];
+
+                let data = ipfs.cat(&link.link.to_string()).await.unwrap();
+
+                assert_eq!(data, media_segment_of(track, index), "{} {}", track, index);
+            }
+        }
+    }
+
+    /// Tracks ordered by bandwidth with their initialization segments.
+    async fn assert_setup(&self, ipfs: &MemoryBackend) {
+        let setup_link = self.videos[0].1.setup.expect("No setup");
+
+        for (_, video) in self.videos.iter() {
+            assert_eq!(video.setup, Some(setup_link));
+        }
+
+        let setup: SetupNode = get(ipfs, &setup_link).await;
+
+        let names: Vec<&str> = setup.tracks.iter().map(|t| t.name.as_str()).collect();
+
+        assert_eq!(names, TRACKS);
+
+        for track in setup.tracks.iter() {
+            let path = track.initialization_segment.link.to_string();
+
+            assert_eq!(ipfs.cat(&path).await.unwrap(), init_segment(&track.name));
+        }
+    }
+}
+
+#[tokio::test]
+async fn archive_minutes_of_segments() {
+    let count = 150;
+
+    let harness = Harness::start().await;
+    let ipfs = harness.memory.clone();
+
+    harness.put_master_playlist().await;
+
+    for track in TRACKS.iter() {
+        harness.put_init_segment(track).await;
+    }
+
+    for index in 0..count {
+        for track in TRACKS.iter() {
+            harness.put_media_segment(track, index).await;
+        }
+
+        harness.put_media_playlists(index).await;
+    }
+
+    let archived = harness.archive(count, 0).await;
+
+    assert_eq!(archived.hours.len(), 1);
+    assert_eq!(archived.hours[0].links_to_minutes.len(), 3);
+
+    let minutes: Vec<usize> = archived
+        .minutes
+        .iter()
+        .map(|minute| minute.links_to_seconds.len())
+        .collect();
+
+    assert_eq!(minutes, [60, 60, 30]);
+
+    archived.assert_segments(&ipfs, count).await;
+    archived.assert_setup(&ipfs).await;
+}
+
+#[tokio::test]
+async fn archive_out_of_order_segments() {
+    let count = 20;
+
+    let harness = Harness::start().await;
+    let ipfs = harness.memory.clone();
+
+    // Pairs of segments swapped, tracks in reverse and before the setup
+    for pair in (0..count).step_by(2) {
+        for index in [pair + 1, pair].iter() {
+            for track in TRACKS.iter().rev() {
+                harness.put_media_segment(track, *index).await;
+            }
+        }
+
+        harness.put_media_playlists(pair + 1).await;
+    }
+
+    for track in TRACKS.iter().rev() {
+        harness.put_init_segment(track).await;
+    }
+
+    harness.put_master_playlist().await;
+
+    let archived = harness.archive(count, 0).await;
+
+    assert_eq!(archived.minutes.len(), 1);
+
+    archived.assert_segments(&ipfs, count).await;
+    archived.assert_setup(&ipfs).await;
+}
+
+#[tokio::test]
+async fn archive_gap_at_missing_track() {
+    let count = 20;
+    let missing = 5;
+
+    let harness = Harness::start().await;
+    let ipfs = harness.memory.clone();
+
+    harness.put_master_playlist().await;
+
+    for track in TRACKS.iter() {
+        harness.put_init_segment(track).await;
+    }
+
+    for index in 0..count {
+        for track in TRACKS.iter() {
+            if index == missing && *track == "360p30" {
+                continue;
+            }
+
+            harness.put_media_segment(track, index).await;
+        }
+
+        harness.put_media_playlists(index).await;
+    }
+
+    let archived = harness.archive(count, 1).await;
+
+    assert_eq!(archived.root.duration, Some(count as f64));
+    assert_eq!(archived.seconds.len(), count);
+    assert_eq!(archived.videos.len(), count - 1);
+
+    // The incomplete node is a gap left out of the chain, later nodes are still archived
+    assert!(is_gap(&archived.seconds[missing].link_to_video));
+
+    let mut previous = None;
+
+    for (index, second) in archived.seconds.iter().enumerate() {
+        if index == missing {
+            continue;
+        }
+
+        let position = if index < missing { index } else { index - 1 };
+        let (cid, video) = &archived.videos[position];
+
+        assert_eq!(second.link_to_video.link, *cid);
+        assert_eq!(video.previous, previous, "Video node {}", index);
+        assert_eq!(video.tracks.len(), TRACKS.len());
+
+        for track in TRACKS.iter() {
+            let link = video.tracks[*track];
+
+            let data = ipfs.cat(&link.link.to_string()).await.unwrap();
+
+            assert_eq!(data, media_segment_of(track, index), "{} {}", track, index);
+        }
+
+        previous = Some((*cid).into());
+    }
+
+    archived.assert_setup(&ipfs).await;
+}
+
+#[tokio::test]
+async fn metrics_report_queue_depths() {
+    let harness = Harness::start().await;
+    let ipfs = harness.memory.clone();
+
+    harness.put_master_playlist().await;
+
+    for track in TRACKS.iter() {
+        harness.put_init_segment(track).await;
+        harness.put_media_segment(track, 0).await;
+    }
+
+    harness.put_media_playlists(0).await;
+
+    let metrics = harness.get("/metrics").await;
+
+    for actor in ["setup", "video", "archive"].iter() {
+        let metric = format!("streamer_queue_depth{{stream=\"\",actor=\"{}\"}}", actor);
+
+        assert!(metrics.contains(&metric), "Missing {}", metric);
+    }
+
+    let archived = harness.archive(1, 0).await;
+
+    archived.assert_segments(&ipfs, 1).await;
+}
+
+#[tokio::test]
+async fn reject_oversized_playlist() {
+    let harness = Harness::start().await;
+    let ipfs = harness.memory.clone();
+
+    harness.put_master_playlist().await;
+
+    for track in TRACKS.iter() {
+        harness.put_init_segment(track).await;
+        harness.put_media_segment(track, 0).await;
+    }
+
+    let mut playlist = media_playlist(0);
+    playlist.push_str(&"#".repeat(1024 * 1024));
+
+    let status = harness.put("/audio/index.m3u8", playlist.into()).await;
+
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    harness.put_media_playlists(0).await;
+
+    let archived = harness.archive(1, 0).await;
+
+    archived.assert_segments(&ipfs, 1).await;
+}
+
+async fn get<T>(ipfs: &MemoryBackend, link: &IPLDLink) -> T
+where
+    T: DeserializeOwned + Serialize,
+{
+    ipfs_dag_get_node_async(ipfs, &link.link.to_string())
+        .await
+        .expect("Missing node")
+}
+
+fn init_segment(track: &str) -> Vec<u8> {
+    match track {
+        "audio" => audio_init(&[0x11, 0x90], 2, AUDIO_TIMESCALE),
+        _ => video_init(
+            &[1, 0x64, 0, 0x1F, 0xFF, 0xE0, 0],
+            640,
+            360,
+            VIDEO_TIMESCALE,
+        ),
+    }
+}
+
+/// One second of a single sample, unique to this track and index.
+fn media_segment_of(track: &str, index: usize) -> Vec<u8> {
+    let timescale = match track {
+        "audio" => AUDIO_TIMESCALE,
+        _ => VIDEO_TIMESCALE,
+    };
+
+    let sample = Sample {
+        duration: timescale,
+        composition_offset: 0,
+        keyframe: true,
+        data: format!("{} {}", track, index).into_bytes(),
+    };
+
+    media_segment(index as u32 + 1, index as u64 * timescale as u64, &[sample])
+}
+
+fn media_playlist(last: usize) -> String {
+    let first = (last + 1).saturating_sub(PLAYLIST_SIZE);
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+        first
+    );
+
+    for index in first..=last {
+        playlist.push_str(&format!("#EXTINF:1.000000,\n{}.m4s\n", index));
+    }
+
+    playlist
+}
@@ -0,0 +1,609 @@
+use crate::backend::Ipfs;
+use crate::server::remuxer::Remuxer;
+use crate::server::services::{internal_error_response, not_found_response};
+use crate::server::streams::{Streams, MAIN_STREAM};
+use crate::utils::h264::{decoder_config, push_nal_unit, NAL_AUD, NAL_IDR, NAL_PPS, NAL_SPS};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, CONTENT_TYPE, LOCATION,
+};
+use hyper::{Body, Error, Response, StatusCode};
+
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
+use webrtc::api::{APIBuilder, API};
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::{
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+};
+use webrtc::track::track_remote::TrackRemote;
+
+pub const WHIP: &str = "whip";
+
+const SDP: &str = "application/sdp";
+
+const VIDEO_CLOCK_RATE: u32 = 90000;
+const AUDIO_CLOCK_RATE: u32 = 48000;
+
+/// Common H.264 profiles, WebRTC only support this video codec for now.
+const H264_PROFILES: [&str; 4] = ["42001f", "42e01f", "4d001f", "640032"];
+
+const NAL_STAP_A: u8 = 24;
+const NAL_FU_A: u8 = 28;
+
+enum Media {
+    AvcConfig(Vec<u8>),
+    Video {
+        timestamp: u32,
+        keyframe: bool,
+        data: Vec<u8>,
+    },
+    Audio {
+        timestamp: u32,
+        duration: u32,
+        data: Vec<u8>,
+    },
+    End,
+}
+
+struct Session {
+    peer: Arc<RTCPeerConnection>,
+    media_tx: UnboundedSender<Media>,
+}
+
+/// WebRTC-HTTP ingestion. Each session is remuxed into the main stream.
+#[derive(Clone)]
+pub struct Whip {
+    streams: Streams,
+    ipfs: Ipfs,
+
+    /// Key frames are requested at this interval in seconds.
+    segment_length: u32,
+
+    api: Arc<API>,
+
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    count: Arc<AtomicUsize>,
+}
+
+impl Whip {
+    pub fn new(streams: Streams, ipfs: Ipfs, segment_length: u32) -> Result<Self, webrtc::Error> {
+        let mut media_engine = MediaEngine::default();
+
+        for (i, profile) in H264_PROFILES.iter().enumerate() {
+            let codec = RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: VIDEO_CLOCK_RATE,
+                    channels: 0,
+                    sdp_fmtp_line: format!(
+                        "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id={}",
+                        profile
+                    ),
+                    rtcp_feedback: vec![],
+                },
+                payload_type: 102 + i as u8,
+                ..Default::default()
+            };
+
+            media_engine.register_codec(codec, RTPCodecType::Video)?;
+        }
+
+        let opus = RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_OPUS.to_owned(),
+                clock_rate: AUDIO_CLOCK_RATE,
+                channels: 2,
+                sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                rtcp_feedback: vec![],
+            },
+            payload_type: 111,
+            ..Default::default()
+        };
+
+        media_engine.register_codec(opus, RTPCodecType::Audio)?;
+
+        let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+
+        let api = APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build();
+
+        Ok(Self {
+            streams,
+            ipfs,
+
+            segment_length,
+
+            api: Arc::new(api),
+
+            sessions: Arc::new(Mutex::new(HashMap::with_capacity(1))),
+            count: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Answer the SDP offer and start a new session.
+    /// The session URL is the request path followed by the session ID.
+    pub async fn offer(
+        &self,
+        mut res: Response<Body>,
+        body: Body,
+        path: &str,
+    ) -> Result<Response<Body>, Error> {
+        cors_headers(&mut res);
+
+        if !self.sessions.lock().expect("Lock Poisoned").is_empty() {
+            *res.status_mut() = StatusCode::CONFLICT;
+
+            return Ok(res);
+        }
+
+        let stream = match self.streams.get_or_start(MAIN_STREAM) {
+            Some(stream) => stream,
+            None => return internal_error_response(res, &"Main stream not started"),
+        };
+
+        let bytes = hyper::body::to_bytes(body).await?;
+
+        let sdp = match String::from_utf8(bytes.to_vec()) {
+            Ok(sdp) => sdp,
+            Err(e) => return internal_error_response(res, &e),
+        };
+
+        let audio = sdp.contains("m=audio");
+
+        let (peer, media_rx, media_tx) = match self.connect(sdp).await {
+            Ok(result) => result,
+            Err(e) => return internal_error_response(res, &e),
+        };
+
+        let answer = match peer.local_description().await {
+            Some(answer) => answer,
+            None => return internal_error_response(res, &"No local description"),
+        };
+
+        let id = self.count.fetch_add(1, Ordering::Relaxed).to_string();
+
+        let session = Session {
+            peer: peer.clone(),
+            media_tx,
+        };
+
+        self.sessions
+            .lock()
+            .expect("Lock Poisoned")
+            .insert(id.clone(), session);
+
+        let mut remuxer = Remuxer::new(self.ipfs.clone(), stream, self.segment_length);
+
+        if audio {
+            remuxer.opus_config(2);
+        }
+
+        let sessions = self.sessions.clone();
+        let session_id = id.clone();
+
+        tokio::spawn(async move {
+            remux(remuxer, media_rx).await;
+
+            sessions.lock().expect("Lock Poisoned").remove(&session_id);
+
+            if let Err(e) = peer.close().await {
+                eprintln!("❗ WHIP: {}", e);
+            }
+        });
+
+        *res.status_mut() = StatusCode::CREATED;
+
+        let location = format!("{}/{}", path.trim_end_matches('/'), id);
+
+        let header_value = HeaderValue::from_str(&location).expect("Invalid Header Value");
+        res.headers_mut().insert(LOCATION, header_value);
+
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(SDP));
+
+        *res.body_mut() = Body::from(answer.sdp);
+
+        #[cfg(debug_assertions)]
+        println!("WHIP: {:#?}", res);
+
+        Ok(res)
+    }
+
+    /// End a session.
+    pub async fn delete(&self, mut res: Response<Body>, id: &str) -> Result<Response<Body>, Error> {
+        cors_headers(&mut res);
+
+        let session = match self.sessions.lock().expect("Lock Poisoned").remove(id) {
+            Some(session) => session,
+            None => return not_found_response(res),
+        };
+
+        let _ = session.media_tx.send(Media::End);
+
+        if let Err(e) = session.peer.close().await {
+            eprintln!("❗ WHIP: {}", e);
+        }
+
+        *res.status_mut() = StatusCode::OK;
+
+        Ok(res)
+    }
+
+    /// CORS preflight of browsers.
+    pub fn options(&self, mut res: Response<Body>) -> Result<Response<Body>, Error> {
+        cors_headers(&mut res);
+
+        *res.status_mut() = StatusCode::NO_CONTENT;
+
+        Ok(res)
+    }
+
+    async fn connect(
+        &self,
+        sdp: String,
+    ) -> Result<
+        (
+            Arc<RTCPeerConnection>,
+            UnboundedReceiver<Media>,
+            UnboundedSender<Media>,
+        ),
+        webrtc::Error,
+    > {
+        let peer = Arc::new(
+            self.api
+                .new_peer_connection(RTCConfiguration::default())
+                .await?,
+        );
+
+        let (media_tx, media_rx) = unbounded_channel();
+
+        let start = Instant::now();
+        let segment_length = Duration::from_secs(self.segment_length.max(1) as u64);
+
+        let track_tx = media_tx.clone();
+        let pli_peer = Arc::downgrade(&peer);
+
+        peer.on_track(Box::new(move |track, _receiver| {
+            let media_tx = track_tx.clone();
+            let peer = pli_peer.clone();
+
+            Box::pin(async move {
+                let track = match track {
+                    Some(track) => track,
+                    None => return,
+                };
+
+                let mime_type = track.codec().await.capability.mime_type.to_lowercase();
+
+                if mime_type == MIME_TYPE_H264.to_lowercase() {
+                    // Ask for key frames at segment boundaries.
+                    let media_ssrc = track.ssrc();
+
+                    tokio::spawn(async move {
+                        let mut interval = tokio::time::interval(segment_length);
+
+                        loop {
+                            interval.tick().await;
+
+                            let peer = match peer.upgrade() {
+                                Some(peer) => peer,
+                                None => return,
+                            };
+
+                            let pli = PictureLossIndication {
+                                sender_ssrc: 0,
+                                media_ssrc,
+                            };
+
+                            if peer.write_rtcp(&[Box::new(pli)]).await.is_err() {
+                                return;
+                            }
+                        }
+                    });
+
+                    tokio::spawn(read_video(track, start, media_tx));
+                } else if mime_type == MIME_TYPE_OPUS.to_lowercase() {
+                    tokio::spawn(read_audio(track, start, media_tx));
+                } else {
+                    eprintln!("❗ WHIP: unsupported codec {}", mime_type);
+                }
+            })
+        }))
+        .await;
+
+        let state_tx = media_tx.clone();
+
+        peer.on_peer_connection_state_change(Box::new(move |state| {
+            println!("WHIP: Peer Connection {}", state);
+
+            if state == RTCPeerConnectionState::Failed || state == RTCPeerConnectionState::Closed {
+                let _ = state_tx.send(Media::End);
+            }
+
+            Box::pin(async {})
+        }))
+        .await;
+
+        let offer = RTCSessionDescription::offer(sdp)?;
+
+        peer.set_remote_description(offer).await?;
+
+        let answer = peer.create_answer(None).await?;
+
+        // No trickle ICE, answer with every candidate.
+        let mut gather_complete = peer.gathering_complete_promise().await;
+
+        peer.set_local_description(answer).await?;
+
+        let _ = gather_complete.recv().await;
+
+        Ok((peer, media_rx, media_tx))
+    }
+}
+
+async fn remux(mut remuxer: Remuxer, mut media_rx: UnboundedReceiver<Media>) {
+    println!("WHIP: Session Started");
+
+    while let Some(media) = media_rx.recv().await {
+        match media {
+            Media::AvcConfig(config) => remuxer.avc_config(config),
+            Media::Video {
+                timestamp,
+                keyframe,
+                data,
+            } => remuxer.video_frame(timestamp, 0, keyframe, data).await,
+            Media::Audio {
+                timestamp,
+                duration,
+                data,
+            } => remuxer.audio_frame(timestamp, duration, data),
+            Media::End => break,
+        }
+    }
+
+    remuxer.finish().await;
+
+    println!("WHIP: Session Ended");
+}
+
+/// Timestamps in milliseconds since the session started.
+/// RTP timestamps of each track start at random, the arrival of the first packet is used instead.
+struct Clock {
+    start: Instant,
+    clock_rate: u32,
+    first: Option<(u32, u32)>,
+}
+
+impl Clock {
+    fn new(start: Instant, clock_rate: u32) -> Self {
+        Self {
+            start,
+            clock_rate,
+            first: None,
+        }
+    }
+
+    fn millis(&mut self, rtp_timestamp: u32) -> u32 {
+        let start = self.start;
+
+        let (first_rtp, first_ms) = *self
+            .first
+            .get_or_insert_with(|| (rtp_timestamp, start.elapsed().as_millis() as u32));
+
+        let elapsed = rtp_timestamp.wrapping_sub(first_rtp) as u64 * 1000 / self.clock_rate as u64;
+
+        first_ms + elapsed as u32
+    }
+}
+
+async fn read_video(track: Arc<TrackRemote>, start: Instant, media_tx: UnboundedSender<Media>) {
+    let mut clock = Clock::new(start, VIDEO_CLOCK_RATE);
+    let mut depacketizer = H264Depacketizer::default();
+
+    while let Ok((packet, _)) = track.read_rtp().await {
+        depacketizer.push(&packet.payload);
+
+        if !packet.header.marker {
+            continue;
+        }
+
+        if let Some(config) = depacketizer.take_config() {
+            if media_tx.send(Media::AvcConfig(config)).is_err() {
+                return;
+            }
+        }
+
+        let (keyframe, data) = match depacketizer.take_frame() {
+            Some(frame) => frame,
+            None => continue,
+        };
+
+        let msg = Media::Video {
+            timestamp: clock.millis(packet.header.timestamp),
+            keyframe,
+            data,
+        };
+
+        if media_tx.send(msg).is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_audio(track: Arc<TrackRemote>, start: Instant, media_tx: UnboundedSender<Media>) {
+    let mut clock = Clock::new(start, AUDIO_CLOCK_RATE);
+
+    // Durations are known once the next packet arrives.
+    let mut previous: Option<(u32, Vec<u8>)> = None;
+
+    while let Ok((packet, _)) = track.read_rtp().await {
+        let rtp_timestamp = packet.header.timestamp;
+
+        if let Some((timestamp, data)) = previous.take() {
+            let msg = Media::Audio {
+                timestamp: clock.millis(timestamp),
+                duration: rtp_timestamp.wrapping_sub(timestamp),
+                data,
+            };
+
+            if media_tx.send(msg).is_err() {
+                return;
+            }
+        }
+
+        previous = Some((rtp_timestamp, packet.payload.to_vec()));
+    }
+}
+
+/// Reassemble RTP payloads into length prefixed NAL units. RFC 6184
+#[derive(Default)]
+struct H264Depacketizer {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+
+    /// Parameter sets changed since the last configuration.
+    changed: bool,
+
+    frame: Vec<u8>,
+    keyframe: bool,
+
+    /// FU-A being reassembled.
+    fragment: Option<Vec<u8>>,
+}
+
+impl H264Depacketizer {
+    fn push(&mut self, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+
+        match payload[0] & 0x1F {
+            1..=23 => self.nal_unit(payload),
+            NAL_STAP_A => {
+                let mut rest = &payload[1..];
+
+                while rest.len() > 2 {
+                    let size = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+
+                    if rest.len() < 2 + size {
+                        break;
+                    }
+
+                    self.nal_unit(&rest[2..2 + size]);
+
+                    rest = &rest[2 + size..];
+                }
+            }
+            NAL_FU_A => {
+                if payload.len() < 2 {
+                    return;
+                }
+
+                let header = payload[1];
+
+                // Start bit
+                if header & 0x80 != 0 {
+                    let mut fragment = vec![(payload[0] & 0xE0) | (header & 0x1F)];
+                    fragment.extend_from_slice(&payload[2..]);
+
+                    self.fragment = Some(fragment);
+                } else if let Some(fragment) = self.fragment.as_mut() {
+                    fragment.extend_from_slice(&payload[2..]);
+                }
+
+                // End bit
+                if header & 0x40 != 0 {
+                    if let Some(fragment) = self.fragment.take() {
+                        self.nal_unit(&fragment);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn nal_unit(&mut self, nal: &[u8]) {
+        if nal.is_empty() {
+            return;
+        }
+
+        match nal[0] & 0x1F {
+            NAL_SPS => {
+                if self.sps.as_deref() != Some(nal) {
+                    self.sps = Some(nal.to_vec());
+                    self.changed = true;
+                }
+            }
+            NAL_PPS => {
+                if self.pps.as_deref() != Some(nal) {
+                    self.pps = Some(nal.to_vec());
+                    self.changed = true;
+                }
+            }
+            NAL_AUD => {}
+            nal_type => {
+                if nal_type == NAL_IDR {
+                    self.keyframe = true;
+                }
+
+                push_nal_unit(&mut self.frame, nal);
+            }
+        }
+    }
+
+    /// AVCDecoderConfigurationRecord if the parameter sets changed.
+    fn take_config(&mut self) -> Option<Vec<u8>> {
+        let (sps, pps) = match (self.sps.as_ref(), self.pps.as_ref()) {
+            (Some(sps), Some(pps)) if self.changed => (sps, pps),
+            _ => return None,
+        };
+
+        self.changed = false;
+
+        decoder_config(sps, pps)
+    }
+
+    fn take_frame(&mut self) -> Option<(bool, Vec<u8>)> {
+        if self.frame.is_empty() {
+            return None;
+        }
+
+        let keyframe = std::mem::replace(&mut self.keyframe, false);
+
+        Some((keyframe, std::mem::take(&mut self.frame)))
+    }
+}
+
+fn cors_headers(res: &mut Response<Body>) {
+    let headers = res.headers_mut();
+
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("POST, DELETE, OPTIONS"),
+    );
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("Content-Type, Authorization"),
+    );
+    headers.insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("Location"),
+    );
+}
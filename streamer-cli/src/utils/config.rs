@@ -1,18 +1,63 @@
 use tokio::fs;
 
+use std::convert::TryFrom;
 use std::io::Error;
 use std::net::SocketAddr;
 use std::str::FromStr;
 
-use serde::{Deserialize, Serialize};
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Debug)]
+use linked_data::codec::Codec;
+
+use cid::Cid;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ArchiveConfig {
     #[serde(skip)]
     pub archive_live_chat: bool, // get from argument not file
+
+    /// Title of the video added to the content feed once archived.
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Thumbnail image CID of the video added to the content feed once archived.
+    /// Checked when the file is read rather than after the stream.
+    #[serde(default)]
+    #[serde(serialize_with = "serialize_cid")]
+    #[serde(deserialize_with = "deserialize_cid")]
+    pub image: Option<Cid>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn serialize_cid<S>(cid: &Option<Cid>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match cid {
+        Some(cid) => serializer.serialize_some(&cid.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_cid<'de, D>(deserializer: D) -> Result<Option<Cid>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let cid_str: Option<String> = Deserialize::deserialize(deserializer)?;
+
+    match cid_str {
+        Some(cid_str) => match Cid::try_from(cid_str) {
+            Ok(cid) => Ok(Some(cid)),
+            Err(e) => Err(de::Error::custom(format!(
+                "Invalid archive image CID {}",
+                e
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VideoConfig {
     #[serde(skip)]
     pub pubsub_enable: bool, // get from argument not file
@@ -31,12 +76,67 @@ pub struct ChatConfig {
     pub bans: String,
 }
 
+/// One video quality produced by the encoder.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rendition {
+    /// Track name, also the folder of its segments. egg 720p30
+    pub name: String,
+
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+
+    /// Video bitrate in kbit/s.
+    pub bitrate: u32,
+
+    /// FFMPEG video encoder.
+    pub codec: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncoderConfig {
+    /// Program started with the generated arguments, FFMPEG or compatible.
+    pub command: String,
+
+    /// Address the encoder listen on for live streams. egg rtmp://localhost:2525
+    pub live_input: String,
+
+    /// Segment length in seconds.
+    pub segment_length: u32,
+
+    /// Video qualities. Audio is copied from the input.
+    pub renditions: Vec<Rendition>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Configuration {
     pub input_socket_addr: SocketAddr,
+
+    /// Secret first folder of every ingest URL. egg /<STREAM_KEY>/720p30/1.m4s
+    #[serde(default)]
+    pub stream_key: Option<String>,
+
+    /// Codec used for all new dag nodes.
+    #[serde(default)]
+    pub codec: Codec,
+
+    /// Messages each actor can have waiting before ingest slows down.
+    /// At least 1, a queue cannot be empty.
+    #[serde(default = "default_queue_depth")]
+    #[serde(deserialize_with = "deserialize_queue_depth")]
+    pub queue_depth: usize,
+
+    /// IDs of the other streams accepted besides the main one. egg /<STREAM_ID>/720p30/1.m4s
+    #[serde(default)]
+    pub streams: Vec<String>,
+
     pub archive: ArchiveConfig,
     pub video: VideoConfig,
     pub chat: ChatConfig,
+
+    /// Start the encoder with the daemons, if any.
+    #[serde(default)]
+    pub encoder: Option<EncoderConfig>,
 }
 
 const CONFIG_LOCATION: &str = "config.json";
@@ -49,6 +149,14 @@ impl Configuration {
         Ok(config)
     }
 
+    /// Codec from the configuration file or the default one.
+    pub async fn codec_from_file() -> Codec {
+        match Self::from_file().await {
+            Ok(config) => config.codec,
+            Err(_) => Codec::default(),
+        }
+    }
+
     pub async fn save_to_file(&self) -> Result<(), Error> {
         let data = serde_json::to_vec_pretty(&self)?;
 
@@ -61,8 +169,18 @@ impl Default for Configuration {
         Self {
             input_socket_addr: SocketAddr::from_str("127.0.0.1:2526").expect("Invalid Address"),
 
+            stream_key: None,
+
+            codec: Codec::default(),
+
+            queue_depth: default_queue_depth(),
+
+            streams: Vec::new(),
+
             archive: ArchiveConfig {
                 archive_live_chat: true,
+                title: None,
+                image: None,
             },
 
             video: VideoConfig {
@@ -75,6 +193,46 @@ impl Default for Configuration {
                 mods: String::default(),
                 bans: String::default(),
             },
+
+            encoder: Some(EncoderConfig {
+                command: "ffmpeg".into(),
+                live_input: "rtmp://localhost:2525".into(),
+                segment_length: 1,
+                renditions: vec![
+                    rendition("1080p60", 1920, 1080, 60, 6000),
+                    rendition("720p60", 1280, 720, 60, 4500),
+                    rendition("720p30", 1280, 720, 30, 3000),
+                    rendition("480p30", 854, 480, 30, 2000),
+                ],
+            }),
         }
     }
 }
+
+fn default_queue_depth() -> usize {
+    256
+}
+
+fn deserialize_queue_depth<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let depth: usize = Deserialize::deserialize(deserializer)?;
+
+    if depth < 1 {
+        return Err(de::Error::custom("Queue depth must be at least 1"));
+    }
+
+    Ok(depth)
+}
+
+fn rendition(name: &str, width: u32, height: u32, fps: u32, bitrate: u32) -> Rendition {
+    Rendition {
+        name: name.into(),
+        width,
+        height,
+        fps,
+        bitrate,
+        codec: "libx264".into(),
+    }
+}
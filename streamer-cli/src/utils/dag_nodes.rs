@@ -1,34 +1,29 @@
-use ipfs_api::response::KeyListResponse;
-use ipfs_api::response::KeyPair;
-use std::convert::TryFrom;
-use std::io::Cursor;
+use crate::backend::{DagStore, Naming};
+use crate::error::Error;
 
-use futures_util::TryStreamExt;
-
-use ipfs_api::response::Error;
-use ipfs_api::IpfsClient;
+use linked_data::codec::Codec;
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use cid::Cid;
 
-/// Serialize then add dag node to IPFS. Return a CID.
-pub async fn ipfs_dag_put_node_async<T>(ipfs: &IpfsClient, node: &T) -> Result<Cid, Error>
+/// Serialize with this codec then add dag node to IPFS. Return a CID.
+pub async fn ipfs_dag_put_node_async<S, T>(ipfs: &S, node: &T, codec: Codec) -> Result<Cid, Error>
 where
-    T: ?Sized + Serialize,
+    S: DagStore + ?Sized,
+    T: Serialize,
 {
     #[cfg(debug_assertions)]
     println!(
-        "Serde: Serialize => {}",
+        "Serde: Serialize {} => {}",
+        codec,
         serde_json::to_string_pretty(node).unwrap()
     );
 
-    let json_string = serde_json::to_string(node).expect("Serialization failed");
-
-    let response = ipfs.dag_put(Cursor::new(json_string)).await?;
+    let data = codec.encode(node)?;
 
-    let cid = Cid::try_from(response.cid.cid_string).expect("Invalid Cid");
+    let cid = ipfs.dag_put(data, codec).await?;
 
     #[cfg(debug_assertions)]
     println!("IPFS: dag put => {}", &cid);
@@ -37,20 +32,17 @@ where
 }
 
 /// Deserialize dag node from IPFS path. Return dag node.
-pub async fn ipfs_dag_get_node_async<T>(ipfs: &IpfsClient, path: &str) -> Result<T, Error>
+pub async fn ipfs_dag_get_node_async<S, T>(ipfs: &S, path: &str) -> Result<T, Error>
 where
+    S: DagStore + ?Sized,
     T: ?Sized + DeserializeOwned + Serialize,
 {
     #[cfg(debug_assertions)]
     println!("IPFS: dag get => {}", path);
 
-    let data = ipfs
-        .dag_get(path)
-        .map_ok(|chunk| chunk.to_vec())
-        .try_concat()
-        .await?;
+    let (codec, data) = ipfs.dag_get(path).await?;
 
-    let node = serde_json::from_slice::<T>(&data).expect("Deserialization failed");
+    let node = codec.decode::<T>(&data)?;
 
     #[cfg(debug_assertions)]
     println!(
@@ -62,52 +54,39 @@ where
 }
 
 /// Serialize the new node, pin it then publish it under this IPNS key.
-pub async fn update_ipns<T>(ipfs: &IpfsClient, key: &str, content: &T) -> Result<(), Error>
+pub async fn update_ipns<S, T>(ipfs: &S, key: &str, content: &T, codec: Codec) -> Result<(), Error>
 where
-    T: ?Sized + Serialize,
+    S: DagStore + Naming + ?Sized,
+    T: Serialize,
 {
-    let cid = ipfs_dag_put_node_async(ipfs, content).await?.to_string();
+    let cid = ipfs_dag_put_node_async(ipfs, content, codec).await?;
 
     ipfs.pin_add(&cid, false).await?;
 
-    ipfs.name_publish(&cid, true, Some("4320h"), None, Some(key)) // 6 months
-        .await?;
+    ipfs.name_publish(&cid, key).await?;
 
     Ok(())
 }
 
 /// Get node associated with IPNS key, unpin it then return it.
-pub async fn get_from_ipns<T>(ipfs: &IpfsClient, key: &str) -> Result<T, Error>
+pub async fn get_from_ipns<S, T>(ipfs: &S, key: &str) -> Result<T, Error>
 where
+    S: DagStore + Naming + ?Sized,
     T: ?Sized + DeserializeOwned + Serialize,
 {
-    let mut res = ipfs.key_list().await?;
-
-    let keypair = match search_keypairs(&key, &mut res) {
-        Some(kp) => kp,
-        None => return Err(Error::Uncategorized("Key Not Found".into())),
+    let id = match ipfs.key_id(key).await? {
+        Some(id) => id,
+        None => return Err(Error::KeyNotFound(key.to_owned())),
     };
 
     #[cfg(debug_assertions)]
-    println!("IPNS: key => {} {}", &keypair.name, &keypair.id);
-
-    let res = ipfs.name_resolve(Some(&keypair.id), false, false).await?;
+    println!("IPNS: key => {} {}", key, &id);
 
-    let cid = Cid::try_from(res.path).expect("Invalid Cid");
+    let cid = ipfs.name_resolve(&id).await?;
 
-    ipfs.pin_rm(&cid.to_string(), false).await?;
+    ipfs.pin_rm(&cid, false).await?;
 
     let node = ipfs_dag_get_node_async(ipfs, &cid.to_string()).await?;
 
     Ok(node)
 }
-
-pub fn search_keypairs(name: &str, res: &mut KeyListResponse) -> Option<KeyPair> {
-    for (i, keypair) in res.keys.iter_mut().enumerate() {
-        if keypair.name == name {
-            return Some(res.keys.remove(i));
-        }
-    }
-
-    None
-}
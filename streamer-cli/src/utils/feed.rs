@@ -0,0 +1,29 @@
+use crate::backend::{DagStore, Ipfs};
+use crate::error::Error;
+use crate::utils::dag_nodes::{get_from_ipns, update_ipns};
+
+use linked_data::codec::Codec;
+use linked_data::feed::Feed;
+
+use cid::Cid;
+
+pub const FEED_KEY: &str = "feed";
+
+pub async fn get_feed(ipfs: &Ipfs) -> Result<Feed, Error> {
+    get_from_ipns(ipfs, FEED_KEY).await
+}
+
+/// Pin the new media then append it to the content feed. Return its index.
+pub async fn add_content_to_feed(ipfs: &Ipfs, new_cid: Cid, codec: Codec) -> Result<usize, Error> {
+    println!("Updating Content Feed...");
+
+    let mut feed = get_feed(ipfs).await?;
+
+    ipfs.pin_add(&new_cid, true).await?;
+
+    feed.content.push(new_cid.into());
+
+    update_ipns(ipfs, FEED_KEY, &feed, codec).await?;
+
+    Ok(feed.content.len() - 1)
+}
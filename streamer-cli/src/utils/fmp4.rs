@@ -0,0 +1,409 @@
+//! Minimal fragmented MP4 writer, one track per file like HLS variants.
+
+/// Sample of a media segment.
+pub struct Sample {
+    /// Duration in track timescale.
+    pub duration: u32,
+
+    /// Presentation time minus decode time in track timescale.
+    pub composition_offset: i32,
+
+    pub keyframe: bool,
+
+    pub data: Vec<u8>,
+}
+
+const TRACK_ID: u32 = 1;
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+const KEYFRAME_FLAGS: u32 = 0x0200_0000;
+const NON_KEYFRAME_FLAGS: u32 = 0x0101_0000;
+
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// Samples to discard at the start, libopus default.
+const OPUS_PRE_SKIP: u16 = 312;
+
+/// Initialization segment of an H.264 track.
+pub fn video_init(avc_config: &[u8], width: u16, height: u16, timescale: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1024);
+
+    ftyp(&mut buf);
+
+    write_box(&mut buf, b"moov", |buf| {
+        mvhd(buf);
+
+        write_box(buf, b"trak", |buf| {
+            tkhd(buf, false, width, height);
+
+            write_box(buf, b"mdia", |buf| {
+                mdhd(buf, timescale);
+                hdlr(buf, b"vide", b"VideoHandler\0");
+
+                write_box(buf, b"minf", |buf| {
+                    write_full_box(buf, b"vmhd", 0, 1, |buf| buf.extend_from_slice(&[0; 8]));
+
+                    dinf(buf);
+
+                    stbl(buf, |buf| {
+                        write_box(buf, b"avc1", |buf| {
+                            sample_entry(buf);
+
+                            buf.extend_from_slice(&[0; 16]);
+                            buf.extend_from_slice(&width.to_be_bytes());
+                            buf.extend_from_slice(&height.to_be_bytes());
+                            buf.extend_from_slice(&0x0048_0000_u32.to_be_bytes());
+                            buf.extend_from_slice(&0x0048_0000_u32.to_be_bytes());
+                            buf.extend_from_slice(&[0; 4]);
+                            buf.extend_from_slice(&1_u16.to_be_bytes());
+                            buf.extend_from_slice(&[0; 32]);
+                            buf.extend_from_slice(&0x0018_u16.to_be_bytes());
+                            buf.extend_from_slice(&0xFFFF_u16.to_be_bytes());
+
+                            write_box(buf, b"avcC", |buf| buf.extend_from_slice(avc_config));
+                        });
+                    });
+                });
+            });
+        });
+
+        mvex(buf);
+    });
+
+    buf
+}
+
+/// Initialization segment of an AAC track, timescale is the sample rate.
+pub fn audio_init(audio_config: &[u8], channels: u16, sample_rate: u32) -> Vec<u8> {
+    audio_track(b"mp4a", channels, sample_rate, |buf| {
+        esds(buf, audio_config)
+    })
+}
+
+/// Initialization segment of an Opus track, timescale is always 48kHz.
+pub fn opus_init(channels: u16) -> Vec<u8> {
+    audio_track(b"Opus", channels, OPUS_SAMPLE_RATE, |buf| {
+        write_box(buf, b"dOps", |buf| {
+            buf.push(0);
+            buf.push(channels as u8);
+            buf.extend_from_slice(&OPUS_PRE_SKIP.to_be_bytes());
+            buf.extend_from_slice(&OPUS_SAMPLE_RATE.to_be_bytes());
+            // output gain & channel mapping family
+            buf.extend_from_slice(&[0; 3]);
+        });
+    })
+}
+
+/// Media segment containing one fragment.
+pub fn media_segment(sequence: u32, decode_time: u64, samples: &[Sample]) -> Vec<u8> {
+    let size: usize = samples.iter().map(|sample| sample.data.len()).sum();
+
+    let mut buf = Vec::with_capacity(size + 1024);
+
+    write_box(&mut buf, b"moof", |buf| {
+        write_full_box(buf, b"mfhd", 0, 0, |buf| {
+            buf.extend_from_slice(&sequence.to_be_bytes())
+        });
+
+        write_box(buf, b"traf", |buf| {
+            // default-base-is-moof
+            write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| {
+                buf.extend_from_slice(&TRACK_ID.to_be_bytes())
+            });
+
+            write_full_box(buf, b"tfdt", 1, 0, |buf| {
+                buf.extend_from_slice(&decode_time.to_be_bytes())
+            });
+
+            // data offset, duration, size, flags and composition offset of each sample
+            write_full_box(buf, b"trun", 1, 0x0F01, |buf| {
+                buf.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+
+                // Patched once the moof size is known
+                buf.extend_from_slice(&[0; 4]);
+
+                for sample in samples {
+                    let flags = if sample.keyframe {
+                        KEYFRAME_FLAGS
+                    } else {
+                        NON_KEYFRAME_FLAGS
+                    };
+
+                    buf.extend_from_slice(&sample.duration.to_be_bytes());
+                    buf.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+                    buf.extend_from_slice(&flags.to_be_bytes());
+                    buf.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                }
+            });
+        });
+    });
+
+    // moof(8) mfhd(16) traf(8) tfhd(16) tfdt(20) trun header(12) sample count(4)
+    let offset_position = 8 + 16 + 8 + 16 + 20 + 12 + 4;
+    let data_offset = (buf.len() + 8) as u32;
+
+    buf[offset_position..offset_position + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+    write_box(&mut buf, b"mdat", |buf| {
+        for sample in samples {
+            buf.extend_from_slice(&sample.data);
+        }
+    });
+
+    buf
+}
+
+fn audio_track<F: FnOnce(&mut Vec<u8>)>(
+    kind: &[u8; 4],
+    channels: u16,
+    sample_rate: u32,
+    decoder_config: F,
+) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1024);
+
+    ftyp(&mut buf);
+
+    write_box(&mut buf, b"moov", |buf| {
+        mvhd(buf);
+
+        write_box(buf, b"trak", |buf| {
+            tkhd(buf, true, 0, 0);
+
+            write_box(buf, b"mdia", |buf| {
+                mdhd(buf, sample_rate);
+                hdlr(buf, b"soun", b"SoundHandler\0");
+
+                write_box(buf, b"minf", |buf| {
+                    write_full_box(buf, b"smhd", 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+
+                    dinf(buf);
+
+                    stbl(buf, |buf| {
+                        write_box(buf, kind, |buf| {
+                            sample_entry(buf);
+
+                            buf.extend_from_slice(&[0; 8]);
+                            buf.extend_from_slice(&channels.to_be_bytes());
+                            buf.extend_from_slice(&16_u16.to_be_bytes());
+                            buf.extend_from_slice(&[0; 4]);
+                            buf.extend_from_slice(&(sample_rate << 16).to_be_bytes());
+
+                            decoder_config(buf);
+                        });
+                    });
+                });
+            });
+        });
+
+        mvex(buf);
+    });
+
+    buf
+}
+
+fn write_box<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, kind: &[u8; 4], content: F) {
+    let start = buf.len();
+
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(kind);
+
+    content(buf);
+
+    let size = (buf.len() - start) as u32;
+
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box<F: FnOnce(&mut Vec<u8>)>(
+    buf: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: F,
+) {
+    write_box(buf, kind, |buf| {
+        buf.push(version);
+        buf.extend_from_slice(&flags.to_be_bytes()[1..]);
+
+        content(buf);
+    });
+}
+
+fn ftyp(buf: &mut Vec<u8>) {
+    write_box(buf, b"ftyp", |buf| {
+        buf.extend_from_slice(b"iso5");
+        buf.extend_from_slice(&512_u32.to_be_bytes());
+        buf.extend_from_slice(b"iso5iso6mp41");
+    });
+}
+
+fn mvhd(buf: &mut Vec<u8>) {
+    write_full_box(buf, b"mvhd", 0, 0, |buf| {
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&1000_u32.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&0x0001_0000_u32.to_be_bytes());
+        buf.extend_from_slice(&0x0100_u16.to_be_bytes());
+        buf.extend_from_slice(&[0; 10]);
+        matrix(buf);
+        buf.extend_from_slice(&[0; 24]);
+        buf.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+    });
+}
+
+fn tkhd(buf: &mut Vec<u8>, audio: bool, width: u16, height: u16) {
+    // enabled and in movie
+    write_full_box(buf, b"tkhd", 0, 3, |buf| {
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&TRACK_ID.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&[0; 4]);
+
+        let volume: u16 = if audio { 0x0100 } else { 0 };
+
+        buf.extend_from_slice(&volume.to_be_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        matrix(buf);
+        buf.extend_from_slice(&((width as u32) << 16).to_be_bytes());
+        buf.extend_from_slice(&((height as u32) << 16).to_be_bytes());
+    });
+}
+
+fn mdhd(buf: &mut Vec<u8>, timescale: u32) {
+    write_full_box(buf, b"mdhd", 0, 0, |buf| {
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&timescale.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        // und
+        buf.extend_from_slice(&0x55C4_u16.to_be_bytes());
+        buf.extend_from_slice(&[0; 2]);
+    });
+}
+
+fn hdlr(buf: &mut Vec<u8>, handler: &[u8; 4], name: &[u8]) {
+    write_full_box(buf, b"hdlr", 0, 0, |buf| {
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(handler);
+        buf.extend_from_slice(&[0; 12]);
+        buf.extend_from_slice(name);
+    });
+}
+
+fn dinf(buf: &mut Vec<u8>) {
+    write_box(buf, b"dinf", |buf| {
+        write_full_box(buf, b"dref", 0, 0, |buf| {
+            buf.extend_from_slice(&1_u32.to_be_bytes());
+
+            // media data in the same file
+            write_full_box(buf, b"url ", 0, 1, |_| {});
+        });
+    });
+}
+
+/// Sample table without samples, they are in fragments.
+fn stbl<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, entry: F) {
+    write_box(buf, b"stbl", |buf| {
+        write_full_box(buf, b"stsd", 0, 0, |buf| {
+            buf.extend_from_slice(&1_u32.to_be_bytes());
+
+            entry(buf);
+        });
+
+        write_full_box(buf, b"stts", 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+        write_full_box(buf, b"stsc", 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+        write_full_box(buf, b"stsz", 0, 0, |buf| buf.extend_from_slice(&[0; 8]));
+        write_full_box(buf, b"stco", 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+    });
+}
+
+fn sample_entry(buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&[0; 6]);
+    buf.extend_from_slice(&1_u16.to_be_bytes());
+}
+
+fn esds(buf: &mut Vec<u8>, audio_config: &[u8]) {
+    write_full_box(buf, b"esds", 0, 0, |buf| {
+        let specific_len = audio_config.len() as u8;
+        let decoder_len = 13 + 2 + specific_len;
+        let es_len = 3 + 2 + decoder_len + 3;
+
+        // ES descriptor
+        buf.extend_from_slice(&[0x03, es_len, 0, 0, 0]);
+
+        // Decoder config descriptor, MPEG-4 audio stream
+        buf.extend_from_slice(&[0x04, decoder_len, 0x40, 0x15]);
+        buf.extend_from_slice(&[0; 11]);
+
+        // Decoder specific info
+        buf.extend_from_slice(&[0x05, specific_len]);
+        buf.extend_from_slice(audio_config);
+
+        // SL config descriptor
+        buf.extend_from_slice(&[0x06, 1, 0x02]);
+    });
+}
+
+fn mvex(buf: &mut Vec<u8>) {
+    write_box(buf, b"mvex", |buf| {
+        write_full_box(buf, b"trex", 0, 0, |buf| {
+            buf.extend_from_slice(&TRACK_ID.to_be_bytes());
+            buf.extend_from_slice(&1_u32.to_be_bytes());
+            buf.extend_from_slice(&[0; 12]);
+        });
+    });
+}
+
+fn matrix(buf: &mut Vec<u8>) {
+    for value in MATRIX.iter() {
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    /// moof, mfhd, traf, tfhd and tfdt before the trun box.
+    const TRUN: usize = 8 + 16 + 8 + 16 + 20;
+
+    #[test]
+    fn negative_composition_offset() {
+        let samples = [
+            Sample {
+                duration: 40,
+                composition_offset: -40,
+                keyframe: true,
+                data: vec![1, 2, 3],
+            },
+            Sample {
+                duration: 40,
+                composition_offset: 80,
+                keyframe: false,
+                data: vec![4],
+            },
+        ];
+
+        let segment = media_segment(3, 1000, &samples);
+
+        assert_eq!(&segment[TRUN + 4..TRUN + 8], b"trun");
+
+        // Version 1 for signed offsets
+        assert_eq!(segment[TRUN + 8], 1);
+
+        let first = TRUN + 20;
+        let second = first + 16;
+
+        assert_eq!(segment[first + 12..first + 16], (-40i32).to_be_bytes());
+        assert_eq!(segment[second + 12..second + 16], 80i32.to_be_bytes());
+
+        let data_offset = &segment[TRUN + 16..TRUN + 20];
+        let data_offset = u32::from_be_bytes(data_offset.try_into().unwrap()) as usize;
+
+        assert_eq!(segment[data_offset..], [1, 2, 3, 4]);
+    }
+}
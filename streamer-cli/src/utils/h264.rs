@@ -0,0 +1,293 @@
+//! H.264 bitstream helpers.
+
+use std::convert::TryFrom;
+
+pub const NAL_IDR: u8 = 5;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+
+/// AVCDecoderConfigurationRecord with one SPS and one PPS.
+pub fn decoder_config(sps: &[u8], pps: &[u8]) -> Option<Vec<u8>> {
+    if sps.len() < 4 {
+        return None;
+    }
+
+    let mut config = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    config.extend_from_slice(sps);
+    config.push(1);
+    config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    config.extend_from_slice(pps);
+
+    Some(config)
+}
+
+/// NAL units of an Annex B byte stream, without start codes.
+pub fn annex_b_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_zeros(&data[start..i]));
+            }
+
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(start) = start {
+        units.push(&data[start..]);
+    }
+
+    units.retain(|unit| !unit.is_empty());
+
+    units
+}
+
+/// Append a NAL unit to an MP4 sample, prefixed by its length on 4 bytes.
+pub fn push_nal_unit(sample: &mut Vec<u8>, unit: &[u8]) {
+    sample.extend_from_slice(&(unit.len() as u32).to_be_bytes());
+    sample.extend_from_slice(unit);
+}
+
+/// Remove the leading zero of 4 bytes start codes.
+fn trim_zeros(mut unit: &[u8]) -> &[u8] {
+    while let Some((0, rest)) = unit.split_last() {
+        unit = rest;
+    }
+
+    unit
+}
+
+/// Width and height from the first SPS of an AVCDecoderConfigurationRecord.
+pub fn dimensions(avc_config: &[u8]) -> Option<(u16, u16)> {
+    if avc_config.len() < 8 || avc_config[5] & 0x1F == 0 {
+        return None;
+    }
+
+    let len = u16::from_be_bytes([avc_config[6], avc_config[7]]) as usize;
+    let sps = avc_config.get(8..8 + len)?;
+
+    // Remove emulation prevention bytes
+    let mut rbsp = Vec::with_capacity(sps.len());
+    let mut zeros = 0;
+
+    for byte in sps.iter().skip(1) {
+        if zeros >= 2 && *byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+
+        rbsp.push(*byte);
+    }
+
+    let mut reader = BitReader::new(&rbsp);
+
+    let profile = reader.bits(8)?;
+    reader.bits(16)?; // constraints & level
+    reader.golomb()?; // sps id
+
+    let mut chroma_format = 1;
+
+    if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile) {
+        chroma_format = reader.golomb()?;
+
+        if chroma_format == 3 {
+            reader.bits(1)?; // separate colour plane
+        }
+
+        reader.golomb()?; // luma bit depth
+        reader.golomb()?; // chroma bit depth
+        reader.bits(1)?; // transform bypass
+
+        if reader.bits(1)? == 1 {
+            let count = if chroma_format == 3 { 12 } else { 8 };
+
+            for i in 0..count {
+                if reader.bits(1)? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    skip_scaling_list(&mut reader, size)?;
+                }
+            }
+        }
+    }
+
+    reader.golomb()?; // log2 max frame num
+
+    match reader.golomb()? {
+        0 => {
+            reader.golomb()?; // log2 max poc lsb
+        }
+        1 => {
+            reader.bits(1)?;
+            reader.signed_golomb()?;
+            reader.signed_golomb()?;
+
+            for _ in 0..reader.golomb()? {
+                reader.signed_golomb()?;
+            }
+        }
+        _ => {}
+    }
+
+    reader.golomb()?; // max ref frames
+    reader.bits(1)?; // gaps in frame num
+
+    let width_mbs = reader.golomb()? + 1;
+    let height_map_units = reader.golomb()? + 1;
+    let frame_mbs_only = reader.bits(1)?;
+
+    if frame_mbs_only == 0 {
+        reader.bits(1)?; // adaptive frame field
+    }
+
+    reader.bits(1)?; // direct 8x8 inference
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+
+    if reader.bits(1)? == 1 {
+        crop_left = reader.golomb()?;
+        crop_right = reader.golomb()?;
+        crop_top = reader.golomb()?;
+        crop_bottom = reader.golomb()?;
+    }
+
+    let (crop_x, crop_y) = match chroma_format {
+        0 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+
+    let width = width_mbs * 16 - crop_x * (crop_left + crop_right);
+    let height = (2 - frame_mbs_only) * height_map_units * 16 - crop_y * (crop_top + crop_bottom);
+
+    Some((u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+
+    for _ in 0..size {
+        if next != 0 {
+            let delta = reader.signed_golomb()?;
+            next = (last + delta + 256) % 256;
+        }
+
+        if next != 0 {
+            last = next;
+        }
+    }
+
+    Some(())
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bits(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+
+        for _ in 0..count {
+            let byte = self.data.get(self.position / 8)?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+
+        Some(value)
+    }
+
+    /// Unsigned Exp-Golomb code.
+    fn golomb(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+
+        while self.bits(1)? == 0 {
+            zeros += 1;
+
+            if zeros > 31 {
+                return None;
+            }
+        }
+
+        Some((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// Signed Exp-Golomb code.
+    fn signed_golomb(&mut self) -> Option<i32> {
+        let code = self.golomb()? as i32;
+
+        if code % 2 == 0 {
+            Some(-(code / 2))
+        } else {
+            Some((code + 1) / 2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Baseline profile 1280x720.
+    const SPS: [u8; 9] = [0x67, 0x42, 0x00, 0x28, 0xF4, 0x02, 0x80, 0x2D, 0xC8];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x3C, 0x80];
+
+    /// Access unit delimiter, IDR slice and slice, behind 4 and 3 bytes start codes.
+    const ANNEX_B: [u8; 18] = [
+        0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1, 0x65, 0xAA, 0, 0, 1, 0x41, 0xBB, 0xCC,
+    ];
+
+    #[test]
+    fn annex_b_units_split() {
+        let units = annex_b_units(&ANNEX_B);
+
+        assert_eq!(
+            units,
+            [&[0x09, 0xF0][..], &[0x65, 0xAA], &[0x41, 0xBB, 0xCC]]
+        );
+    }
+
+    #[test]
+    fn annex_b_to_length_prefixed() {
+        let mut sample = Vec::new();
+
+        for unit in annex_b_units(&ANNEX_B) {
+            push_nal_unit(&mut sample, unit);
+        }
+
+        let expected = [
+            0, 0, 0, 2, 0x09, 0xF0, 0, 0, 0, 2, 0x65, 0xAA, 0, 0, 0, 3, 0x41, 0xBB, 0xCC,
+        ];
+
+        assert_eq!(sample, expected);
+    }
+
+    #[test]
+    fn sps_dimensions() {
+        let config = decoder_config(&SPS, &PPS).expect("Invalid SPS");
+
+        assert_eq!(config[..6], [1, 0x42, 0x00, 0x28, 0xFF, 0xE1]);
+        assert_eq!(dimensions(&config), Some((1280, 720)));
+
+        assert_eq!(decoder_config(&SPS[..3], &PPS), None);
+    }
+}
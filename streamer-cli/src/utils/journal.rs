@@ -0,0 +1,204 @@
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use linked_data::video::SecondNode;
+use linked_data::IPLDLink;
+
+const JOURNAL_FILE: &str = "archive.journal";
+const OUTBOX_FILE: &str = "archive.outbox";
+
+/// CID of a node minted by the archivist.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalEntry {
+    Second(IPLDLink),
+    Minute(IPLDLink),
+    Hour(IPLDLink),
+}
+
+/// Seconds queued but not written yet, as of this many seconds in the journal.
+#[derive(Serialize, Deserialize)]
+struct Pending<N> {
+    written: usize,
+    nodes: Vec<N>,
+}
+
+/// Append-only log of the archive in progress, one JSON entry per line.
+/// Seconds waiting to be written are saved beside it.
+pub struct Journal {
+    file: File,
+    dir: PathBuf,
+
+    /// Second entries in the journal.
+    seconds: usize,
+}
+
+impl Journal {
+    /// Entries left in this directory by the previous archive, if it was not finalized.
+    pub async fn read(dir: &Path) -> Result<Vec<JournalEntry>, Error> {
+        let data = match fs::read(dir.join(JOURNAL_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = Vec::new();
+
+        for line in data.split(|byte| *byte == b'\n') {
+            // The last line may be incomplete after a power loss.
+            match serde_json::from_slice(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => break,
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Seconds the previous archive had not written yet, in order.
+    /// Those written since they were saved are already in these entries.
+    pub async fn read_pending(
+        dir: &Path,
+        entries: &[JournalEntry],
+    ) -> Result<Vec<SecondNode>, Error> {
+        let data = match fs::read(dir.join(OUTBOX_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let pending: Pending<SecondNode> = serde_json::from_slice(&data)?;
+
+        let seconds = entries
+            .iter()
+            .filter(|entry| matches!(entry, JournalEntry::Second(_)))
+            .count();
+
+        let written = seconds.saturating_sub(pending.written);
+
+        Ok(pending.nodes.into_iter().skip(written).collect())
+    }
+
+    /// Move the journal of the previous archive out of the way, keeping it for later recovery.
+    /// Return its new location.
+    pub async fn set_aside(dir: &Path) -> Result<PathBuf, Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        let location = dir.join(format!("archive.{}.journal", timestamp));
+
+        fs::rename(dir.join(JOURNAL_FILE), &location).await?;
+
+        let outbox = dir.join(format!("archive.{}.outbox", timestamp));
+
+        match fs::rename(dir.join(OUTBOX_FILE), outbox).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        Ok(location)
+    }
+
+    /// Start a new journal in this directory containing these entries and pending seconds.
+    pub async fn create(
+        dir: &Path,
+        entries: &[JournalEntry],
+        pending: &[SecondNode],
+    ) -> Result<Self, Error> {
+        let mut data = Vec::new();
+        let mut seconds = 0;
+
+        for entry in entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+
+            if let JournalEntry::Second(_) = entry {
+                seconds += 1;
+            }
+        }
+
+        // Renamed once complete so that a power loss leaves the previous journal
+        let temporary = dir.join(format!("{}.tmp", JOURNAL_FILE));
+
+        let mut file = File::create(&temporary).await?;
+
+        file.write_all(&data).await?;
+        file.sync_data().await?;
+
+        fs::rename(temporary, dir.join(JOURNAL_FILE)).await?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .await?;
+
+        let mut journal = Self {
+            file,
+            dir: dir.to_owned(),
+            seconds,
+        };
+
+        journal.write_pending(pending.iter().collect()).await?;
+
+        Ok(journal)
+    }
+
+    /// Append an entry and wait until it reaches the disk.
+    pub async fn write(&mut self, entry: &JournalEntry) -> Result<(), Error> {
+        let mut data = serde_json::to_vec(entry)?;
+        data.push(b'\n');
+
+        self.file.write_all(&data).await?;
+
+        self.file.sync_data().await?;
+
+        if let JournalEntry::Second(_) = entry {
+            self.seconds += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the saved seconds by these, waiting until they reach the disk.
+    pub async fn write_pending(&mut self, nodes: Vec<&SecondNode>) -> Result<(), Error> {
+        let pending = Pending {
+            written: self.seconds,
+            nodes,
+        };
+
+        let data = serde_json::to_vec(&pending)?;
+
+        // Renamed once complete so that a power loss leaves the previous version
+        let temporary = self.dir.join(format!("{}.tmp", OUTBOX_FILE));
+
+        let mut file = File::create(&temporary).await?;
+
+        file.write_all(&data).await?;
+        file.sync_data().await?;
+
+        fs::rename(temporary, self.dir.join(OUTBOX_FILE)).await
+    }
+
+    /// Delete the journal once the archive is complete.
+    pub async fn remove(self) -> Result<(), Error> {
+        drop(self.file);
+
+        remove_if_exists(&self.dir.join(OUTBOX_FILE)).await?;
+
+        fs::remove_file(self.dir.join(JOURNAL_FILE)).await
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
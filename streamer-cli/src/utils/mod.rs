@@ -1,2 +1,11 @@
 pub mod config;
 pub mod dag_nodes;
+pub mod feed;
+pub mod fmp4;
+pub mod h264;
+pub mod journal;
+pub mod mpegts;
+pub mod outbox;
+pub mod playlists;
+pub mod segment_names;
+pub mod segments;
@@ -0,0 +1,579 @@
+//! Remux MPEG-TS segments of one track into fragmented MP4.
+
+use crate::utils::fmp4::{audio_init, media_segment, video_init, Sample};
+use crate::utils::h264::{annex_b_units, decoder_config, dimensions, push_nal_unit};
+use crate::utils::h264::{NAL_AUD, NAL_IDR, NAL_PPS, NAL_SPS};
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+const PAT_PID: u16 = 0;
+
+const STREAM_TYPE_AAC: u8 = 0x0F;
+const STREAM_TYPE_H264: u8 = 0x1B;
+
+/// PES timestamps are in 90kHz.
+const TS_TIMESCALE: u32 = 90000;
+
+/// AAC frames always contain 1024 samples.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Initialization and media segment of the same track.
+pub struct Fmp4 {
+    pub init: Vec<u8>,
+    pub segment: Vec<u8>,
+}
+
+struct Pes {
+    pts: u64,
+    dts: u64,
+    data: Vec<u8>,
+}
+
+/// Remux the first H.264 or AAC stream of this segment.
+pub fn to_fmp4(data: &[u8], sequence: u32) -> Result<Fmp4, &'static str> {
+    let (stream_type, packets) = demux(data)?;
+
+    match stream_type {
+        STREAM_TYPE_H264 => remux_video(&packets, sequence),
+        STREAM_TYPE_AAC => remux_audio(&packets, sequence),
+        _ => Err("Unsupported stream type"),
+    }
+}
+
+/// Stream type and PES packets of the first supported elementary stream.
+fn demux(data: &[u8]) -> Result<(u8, Vec<Pes>), &'static str> {
+    if data.len() < PACKET_SIZE || data[0] != SYNC_BYTE {
+        return Err("Not a transport stream");
+    }
+
+    let mut pmt_pid = None;
+    let mut stream = None;
+
+    let mut packets = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+
+    for packet in data.chunks_exact(PACKET_SIZE) {
+        if packet[0] != SYNC_BYTE {
+            return Err("Lost sync");
+        }
+
+        let start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let adaptation = (packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+
+        if adaptation & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+
+        if adaptation & 0x01 == 0 || offset >= PACKET_SIZE {
+            continue;
+        }
+
+        let payload = &packet[offset..];
+
+        if pid == PAT_PID && start {
+            pmt_pid = parse_pat(payload);
+            continue;
+        }
+
+        if Some(pid) == pmt_pid && start && stream.is_none() {
+            stream = parse_pmt(payload);
+            continue;
+        }
+
+        let (stream_pid, _) = match stream {
+            Some(stream) => stream,
+            None => continue,
+        };
+
+        if pid != stream_pid {
+            continue;
+        }
+
+        if start {
+            if let Some(pes) = current.take().and_then(|data| parse_pes(&data)) {
+                packets.push(pes);
+            }
+
+            current = Some(Vec::with_capacity(PACKET_SIZE * 16));
+        }
+
+        if let Some(data) = current.as_mut() {
+            data.extend_from_slice(payload);
+        }
+    }
+
+    if let Some(pes) = current.and_then(|data| parse_pes(&data)) {
+        packets.push(pes);
+    }
+
+    match stream {
+        Some((_, stream_type)) if !packets.is_empty() => Ok((stream_type, packets)),
+        Some(_) => Err("No media"),
+        None => Err("No supported stream"),
+    }
+}
+
+/// PID of the first program map table.
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let table = psi_table(payload)?;
+
+    // Skip stream ID, version and section numbers
+    let entries = table.get(5..)?;
+
+    entries.chunks_exact(4).find_map(|entry| {
+        let program = u16::from_be_bytes([entry[0], entry[1]]);
+
+        if program == 0 {
+            return None;
+        }
+
+        Some(u16::from_be_bytes([entry[2] & 0x1F, entry[3]]))
+    })
+}
+
+/// PID and type of the first H.264 or AAC stream.
+fn parse_pmt(payload: &[u8]) -> Option<(u16, u8)> {
+    let table = psi_table(payload)?;
+
+    let info_len = u16::from_be_bytes([*table.get(7)? & 0x0F, *table.get(8)?]) as usize;
+
+    let mut streams = table.get(9 + info_len..)?;
+
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = u16::from_be_bytes([streams[1] & 0x1F, streams[2]]);
+        let es_info_len = u16::from_be_bytes([streams[3] & 0x0F, streams[4]]) as usize;
+
+        if stream_type == STREAM_TYPE_H264 || stream_type == STREAM_TYPE_AAC {
+            return Some((pid, stream_type));
+        }
+
+        streams = streams.get(5 + es_info_len..)?;
+    }
+
+    None
+}
+
+/// Section after the table header and before the CRC.
+fn psi_table(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+
+    let length = u16::from_be_bytes([*section.get(1)? & 0x0F, *section.get(2)?]) as usize;
+
+    if length < 4 {
+        return None;
+    }
+
+    section.get(3..3 + length - 4)
+}
+
+fn parse_pes(data: &[u8]) -> Option<Pes> {
+    if data.len() < 9 || data[0..3] != [0, 0, 1] {
+        return None;
+    }
+
+    let flags = data[7] >> 6;
+    let header_len = data[8] as usize;
+
+    let pts = match flags {
+        2 | 3 => timestamp(data.get(9..14)?),
+        _ => return None,
+    };
+
+    let dts = match flags {
+        3 => timestamp(data.get(14..19)?),
+        _ => pts,
+    };
+
+    let data = data.get(9 + header_len..)?.to_vec();
+
+    Some(Pes { pts, dts, data })
+}
+
+fn timestamp(bytes: &[u8]) -> u64 {
+    ((bytes[0] as u64 >> 1) & 0x07) << 30
+        | (bytes[1] as u64) << 22
+        | (bytes[2] as u64 >> 1) << 15
+        | (bytes[3] as u64) << 7
+        | bytes[4] as u64 >> 1
+}
+
+/// One access unit per PES packet.
+fn remux_video(packets: &[Pes], sequence: u32) -> Result<Fmp4, &'static str> {
+    let mut sps = None;
+    let mut pps = None;
+
+    let mut samples = Vec::with_capacity(packets.len());
+
+    for (i, pes) in packets.iter().enumerate() {
+        let mut data = Vec::with_capacity(pes.data.len());
+        let mut keyframe = false;
+
+        for unit in annex_b_units(&pes.data) {
+            match unit[0] & 0x1F {
+                NAL_SPS => sps = Some(unit),
+                NAL_PPS => pps = Some(unit),
+                NAL_AUD => {}
+                nal_type => {
+                    keyframe |= nal_type == NAL_IDR;
+
+                    push_nal_unit(&mut data, unit);
+                }
+            }
+        }
+
+        // Assume the last frame is as long as the previous one
+        let duration = match (packets.get(i + 1), i.checked_sub(1)) {
+            (Some(next), _) => next.dts.saturating_sub(pes.dts),
+            (None, Some(previous)) => pes.dts.saturating_sub(packets[previous].dts),
+            (None, None) => 0,
+        };
+
+        samples.push(Sample {
+            duration: duration as u32,
+            composition_offset: (pes.pts as i64 - pes.dts as i64) as i32,
+            keyframe,
+            data,
+        });
+    }
+
+    let config = match (sps, pps) {
+        (Some(sps), Some(pps)) => decoder_config(sps, pps).ok_or("Invalid SPS")?,
+        _ => return Err("No SPS or PPS"),
+    };
+
+    let (width, height) = dimensions(&config).ok_or("Invalid SPS")?;
+
+    Ok(Fmp4 {
+        init: video_init(&config, width, height, TS_TIMESCALE),
+        segment: media_segment(sequence, packets[0].dts, &samples),
+    })
+}
+
+/// ADTS frames, timescale is the sample rate.
+fn remux_audio(packets: &[Pes], sequence: u32) -> Result<Fmp4, &'static str> {
+    let mut header = None;
+    let mut samples = Vec::new();
+
+    for pes in packets.iter() {
+        let mut data = pes.data.as_slice();
+
+        while data.len() >= 7 && data[0] == 0xFF && data[1] & 0xF0 == 0xF0 {
+            let protection_absent = data[1] & 0x01 == 1;
+            let header_len = if protection_absent { 7 } else { 9 };
+
+            let frame_len = ((data[3] as usize & 0x03) << 11)
+                | (data[4] as usize) << 3
+                | (data[5] as usize) >> 5;
+
+            if frame_len < header_len || frame_len > data.len() {
+                break;
+            }
+
+            if header.is_none() {
+                header = Some([data[2], data[3]]);
+            }
+
+            samples.push(Sample {
+                duration: AAC_FRAME_SAMPLES,
+                composition_offset: 0,
+                keyframe: true,
+                data: data[header_len..frame_len].to_vec(),
+            });
+
+            data = &data[frame_len..];
+        }
+    }
+
+    let header = header.ok_or("No ADTS frame")?;
+
+    let object_type = (header[0] >> 6) + 1;
+    let rate_index = (header[0] >> 2) & 0x0F;
+    let channels = ((header[0] & 0x01) << 2) | (header[1] >> 6);
+
+    let sample_rate = *AAC_SAMPLE_RATES
+        .get(rate_index as usize)
+        .ok_or("Invalid sample rate")?;
+
+    // AudioSpecificConfig
+    let config = [
+        (object_type << 3) | (rate_index >> 1),
+        ((rate_index & 0x01) << 7) | (channels << 3),
+    ];
+
+    let decode_time = packets[0].pts * sample_rate as u64 / TS_TIMESCALE as u64;
+
+    Ok(Fmp4 {
+        init: audio_init(&config, channels as u16, sample_rate),
+        segment: media_segment(sequence, decode_time, &samples),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMT_PID: u16 = 0x1000;
+    const STREAM_PID: u16 = 0x0101;
+
+    /// Packets of one PSI section or PES packet, padded by adaptation fields.
+    fn packets(pid: u16, data: &[u8]) -> Vec<u8> {
+        let mut ts = Vec::new();
+
+        for (i, chunk) in data.chunks(PACKET_SIZE - 4).enumerate() {
+            let start = if i == 0 { 0x40 } else { 0 };
+
+            ts.extend_from_slice(&[SYNC_BYTE, start | (pid >> 8) as u8, pid as u8]);
+
+            let stuffing = PACKET_SIZE - 4 - chunk.len();
+
+            if stuffing == 0 {
+                ts.push(0x10);
+            } else {
+                ts.extend_from_slice(&[0x30, stuffing as u8 - 1]);
+
+                if stuffing > 1 {
+                    ts.push(0);
+                    ts.resize(ts.len() + stuffing - 2, 0xFF);
+                }
+            }
+
+            ts.extend_from_slice(chunk);
+        }
+
+        ts
+    }
+
+    /// Section with a pointer field and a dummy CRC.
+    fn section(table_id: u8, content: &[u8]) -> Vec<u8> {
+        let length = content.len() as u16 + 4;
+
+        let mut data = vec![0, table_id, 0xB0 | (length >> 8) as u8, length as u8];
+        data.extend_from_slice(content);
+        data.extend_from_slice(&[0; 4]);
+
+        data
+    }
+
+    fn pat() -> Vec<u8> {
+        section(
+            0,
+            &[
+                0,
+                1,
+                0xC1,
+                0,
+                0,
+                0,
+                1,
+                0xE0 | (PMT_PID >> 8) as u8,
+                PMT_PID as u8,
+            ],
+        )
+    }
+
+    /// Private data stream first, then the supported one.
+    fn pmt(stream_type: u8) -> Vec<u8> {
+        section(
+            2,
+            &[
+                0,
+                1,
+                0xC1,
+                0,
+                0,
+                0xE1,
+                0x00,
+                0xF0,
+                0, //
+                0x06,
+                0xE1,
+                0x02,
+                0xF0,
+                2,
+                0xAA,
+                0xBB, //
+                stream_type,
+                0xE1,
+                0x01,
+                0xF0,
+                0,
+            ],
+        )
+    }
+
+    fn timestamp_bytes(prefix: u8, value: u64) -> [u8; 5] {
+        [
+            prefix << 4 | (value >> 29) as u8 & 0x0E | 1,
+            (value >> 22) as u8,
+            (value >> 14) as u8 | 1,
+            (value >> 7) as u8,
+            (value << 1) as u8 | 1,
+        ]
+    }
+
+    fn pes(stream_id: u8, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0, 0, 1, stream_id, 0, 0, 0x80];
+
+        match dts {
+            Some(dts) => {
+                data.extend_from_slice(&[0xC0, 10]);
+                data.extend_from_slice(&timestamp_bytes(3, pts));
+                data.extend_from_slice(&timestamp_bytes(1, dts));
+            }
+            None => {
+                data.extend_from_slice(&[0x80, 5]);
+                data.extend_from_slice(&timestamp_bytes(2, pts));
+            }
+        }
+
+        data.extend_from_slice(payload);
+
+        data
+    }
+
+    /// AAC LC, 48kHz, stereo.
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() + 7;
+
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x4C,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            (len << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    fn segment(stream_type: u8, pes: &[Vec<u8>]) -> Vec<u8> {
+        let mut ts = packets(PAT_PID, &pat());
+        ts.extend(packets(PMT_PID, &pmt(stream_type)));
+
+        for pes in pes {
+            ts.extend(packets(STREAM_PID, pes));
+        }
+
+        ts
+    }
+
+    #[test]
+    fn program_tables_parsed() {
+        assert_eq!(parse_pat(&pat()), Some(PMT_PID));
+        assert_eq!(
+            parse_pmt(&pmt(STREAM_TYPE_AAC)),
+            Some((STREAM_PID, STREAM_TYPE_AAC))
+        );
+        assert_eq!(parse_pmt(&pmt(0x06)), None);
+    }
+
+    #[test]
+    fn pes_timestamps_parsed() {
+        let pts = (1 << 32) + 123_456;
+
+        let parsed = parse_pes(&pes(0xE0, pts + 3000, Some(pts), &[1, 2, 3])).expect("No PES");
+
+        assert_eq!(
+            (parsed.pts, parsed.dts, parsed.data),
+            (pts + 3000, pts, vec![1, 2, 3])
+        );
+
+        let parsed = parse_pes(&pes(0xC0, pts, None, &[4])).expect("No PES");
+
+        assert_eq!((parsed.pts, parsed.dts, parsed.data), (pts, pts, vec![4]));
+    }
+
+    #[test]
+    fn adts_frames_split() {
+        // First PES spans two packets, last frame truncated
+        let mut first = adts_frame(&[1; 200]);
+        first.extend(adts_frame(&[2; 10]));
+
+        let mut second = adts_frame(&[3; 20]);
+        second.extend_from_slice(&adts_frame(&[4; 20])[..15]);
+
+        let ts = segment(
+            STREAM_TYPE_AAC,
+            &[
+                pes(0xC0, 90000, None, &first),
+                pes(0xC0, 93840, None, &second),
+            ],
+        );
+
+        let fmp4 = to_fmp4(&ts, 7).expect("Remux failed");
+
+        let samples: Vec<Sample> = [vec![1; 200], vec![2; 10], vec![3; 20]]
+            .iter()
+            .map(|data| Sample {
+                duration: AAC_FRAME_SAMPLES,
+                composition_offset: 0,
+                keyframe: true,
+                data: data.clone(),
+            })
+            .collect();
+
+        assert_eq!(fmp4.init, audio_init(&[0x11, 0x90], 2, 48000));
+        assert_eq!(fmp4.segment, media_segment(7, 48000, &samples));
+    }
+
+    #[test]
+    fn video_remuxed_length_prefixed() {
+        let sps = [0x67, 0x42, 0x00, 0x28, 0xF4, 0x02, 0x80, 0x2D, 0xC8];
+        let pps = [0x68, 0xCE, 0x3C, 0x80];
+
+        let mut access_unit = vec![0, 0, 0, 1, 0x09, 0xF0, 0, 0, 0, 1];
+        access_unit.extend_from_slice(&sps);
+        access_unit.extend_from_slice(&[0, 0, 0, 1]);
+        access_unit.extend_from_slice(&pps);
+        access_unit.extend_from_slice(&[0, 0, 1, 0x65, 0xAA, 0xBB]);
+
+        let ts = segment(
+            STREAM_TYPE_H264,
+            &[
+                pes(0xE0, 6000, Some(3000), &access_unit),
+                pes(0xE0, 9000, None, &[0, 0, 1, 0x41, 0xCC]),
+            ],
+        );
+
+        let fmp4 = to_fmp4(&ts, 1).expect("Remux failed");
+
+        let samples = [
+            Sample {
+                duration: 6000,
+                composition_offset: 3000,
+                keyframe: true,
+                data: vec![0, 0, 0, 3, 0x65, 0xAA, 0xBB],
+            },
+            Sample {
+                duration: 6000,
+                composition_offset: 0,
+                keyframe: false,
+                data: vec![0, 0, 0, 2, 0x41, 0xCC],
+            },
+        ];
+
+        let config = decoder_config(&sps, &pps).expect("Invalid SPS");
+
+        assert_eq!(fmp4.init, video_init(&config, 1280, 720, TS_TIMESCALE));
+        assert_eq!(fmp4.segment, media_segment(1, 3000, &samples));
+    }
+
+    #[test]
+    fn not_transport_stream() {
+        assert!(to_fmp4(&[0; PACKET_SIZE], 1).is_err());
+        assert!(to_fmp4(&segment(0x06, &[]), 1).is_err());
+    }
+}
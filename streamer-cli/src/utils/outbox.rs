@@ -0,0 +1,172 @@
+use crate::backend::{DagStore, Ipfs};
+use crate::error::{Error, Policy};
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+
+use linked_data::codec::Codec;
+
+use serde::Serialize;
+
+use cid::Cid;
+
+/// Failed writes are attempted this many times before being given up.
+const MAX_ATTEMPTS: u32 = 8;
+
+/// Delay after the first failure, doubled after each one.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Queued nodes above which failed writes are given up without waiting.
+/// Nodes in front are given up to queue more than this many.
+const CAPACITY: usize = 600;
+
+/// Ordered queue of nodes to add to IPFS, retried with exponential backoff.
+/// Each node is tagged by the caller to know what it was once written.
+pub struct Outbox<K> {
+    ipfs: Ipfs,
+    codec: Codec,
+
+    queue: VecDeque<(K, Vec<u8>)>,
+
+    /// Failures of the node in front.
+    attempts: u32,
+    retry_at: Option<Instant>,
+}
+
+impl<K> Outbox<K> {
+    pub fn new(ipfs: Ipfs, codec: Codec) -> Self {
+        Self {
+            ipfs,
+            codec,
+
+            queue: VecDeque::with_capacity(64),
+
+            attempts: 0,
+            retry_at: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Tags of the queued nodes, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.queue.iter().map(|(key, _)| key)
+    }
+
+    /// Forget all queued nodes.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.attempts = 0;
+        self.retry_at = None;
+    }
+
+    /// Queue a node behind all others.
+    /// Return the node in front if it was given up to make room.
+    pub fn push_back<T: Serialize>(&mut self, key: K, node: &T) -> Result<Option<K>, Error> {
+        let data = self.codec.encode(node)?;
+
+        let given_up = if self.queue.len() >= CAPACITY {
+            eprintln!(
+                "❗ Outbox: {} nodes queued. Oldest given up",
+                self.queue.len()
+            );
+
+            self.pop(None).map(|(key, _)| key)
+        } else {
+            None
+        };
+
+        self.queue.push_back((key, data));
+
+        Ok(given_up)
+    }
+
+    /// Queue a node to be written next.
+    pub fn push_front<T: Serialize>(&mut self, key: K, node: &T) -> Result<(), Error> {
+        let data = self.codec.encode(node)?;
+
+        self.queue.push_front((key, data));
+
+        Ok(())
+    }
+
+    /// Completes once the node in front can be attempted again. Never if nothing failed.
+    pub async fn retry_due(&self) {
+        match self.retry_at {
+            Some(instant) if !self.queue.is_empty() => sleep_until(instant).await,
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// Write the node in front unless waiting to retry it.
+    /// Return its CID, or none if it was given up, or nothing if it is still queued.
+    pub async fn write_next(&mut self) -> Option<(K, Option<Cid>)> {
+        if let Some(instant) = self.retry_at {
+            if Instant::now() < instant {
+                return None;
+            }
+        }
+
+        let (_, data) = self.queue.front()?;
+
+        let error = match self.ipfs.dag_put(data.clone(), self.codec).await {
+            Ok(cid) => {
+                #[cfg(debug_assertions)]
+                println!("IPFS: dag put => {}", &cid);
+
+                return self.pop(Some(cid));
+            }
+            Err(e) => Error::from(e),
+        };
+
+        self.attempts += 1;
+
+        if error.policy() != Policy::Retry
+            || self.attempts >= MAX_ATTEMPTS
+            || self.queue.len() > CAPACITY
+        {
+            eprintln!(
+                "❗ Outbox: {}. Given up after {} attempts",
+                error, self.attempts
+            );
+
+            return self.pop(None);
+        }
+
+        let delay = BASE_DELAY * 2u32.pow(self.attempts - 1);
+        let delay = delay.min(MAX_DELAY);
+
+        eprintln!("❗ Outbox: {}. Retrying in {:?}", error, delay);
+
+        self.retry_at = Some(Instant::now() + delay);
+
+        None
+    }
+
+    /// Write the node in front, waiting between attempts until written or given up.
+    pub async fn write_now(&mut self) -> Option<(K, Option<Cid>)> {
+        while !self.queue.is_empty() {
+            if let Some(written) = self.write_next().await {
+                return Some(written);
+            }
+
+            self.retry_due().await;
+        }
+
+        None
+    }
+
+    fn pop(&mut self, cid: Option<Cid>) -> Option<(K, Option<Cid>)> {
+        self.attempts = 0;
+        self.retry_at = None;
+
+        let (key, _) = self.queue.pop_front()?;
+
+        Some((key, cid))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::server::{M4S, MP4};
+use crate::utils::segments::Segment;
+
+use std::fmt::Write;
+
+use linked_data::video::Track;
+
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+pub const MEDIA_PLAYLIST: &str = "index.m3u8";
+
+/// Generate master playlist, each variant at <track name>/index.m3u8.
+pub fn master_playlist(tracks: &[&Track]) -> String {
+    let audio = tracks.iter().find(|track| track.name == "audio");
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+    if audio.is_some() {
+        let _ = writeln!(
+            playlist,
+            r#"#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="audio",NAME="audio",DEFAULT=YES,AUTOSELECT=YES,URI="audio/{}""#,
+            MEDIA_PLAYLIST
+        );
+    }
+
+    for track in tracks.iter().filter(|track| track.name != "audio") {
+        let mut codecs = mime_codecs(&track.codec).to_owned();
+        let mut bandwidth = track.bandwidth;
+
+        if let Some(audio) = audio {
+            codecs = format!("{},{}", codecs, mime_codecs(&audio.codec));
+            bandwidth += audio.bandwidth;
+
+            let _ = writeln!(
+                playlist,
+                r#"#EXT-X-STREAM-INF:BANDWIDTH={},CODECS="{}",AUDIO="audio""#,
+                bandwidth, codecs
+            );
+        } else {
+            let _ = writeln!(
+                playlist,
+                r#"#EXT-X-STREAM-INF:BANDWIDTH={},CODECS="{}""#,
+                bandwidth, codecs
+            );
+        }
+
+        let _ = writeln!(playlist, "{}/{}", track.name, MEDIA_PLAYLIST);
+    }
+
+    playlist
+}
+
+/// Generate media playlist, adding a discontinuity each time the setup changes.
+/// Variants share the same segments, only the tracks differ.
+/// Live playlists are EVENT playlists without end so that players reload them.
+///
+/// Segment at index is <index>.m4s and its initialization segment <index>.mp4
+pub fn media_playlist(segments: &[Segment], live: bool) -> String {
+    let target_duration = segments
+        .iter()
+        .fold(1.0_f64, |max, segment| max.max(segment.duration))
+        .ceil();
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+
+    let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration);
+    playlist.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
+
+    if live {
+        playlist.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+    } else {
+        playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+    }
+
+    playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+    let mut setup = None;
+
+    for (index, segment) in segments.iter().enumerate() {
+        if segment.setup != setup {
+            if setup.is_some() {
+                playlist.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+
+            let _ = writeln!(playlist, r#"#EXT-X-MAP:URI="{}.{}""#, index, MP4);
+
+            setup = segment.setup;
+        }
+
+        let _ = writeln!(playlist, "#EXTINF:{:.3},", segment.duration);
+        let _ = writeln!(playlist, "{}.{}", index, M4S);
+    }
+
+    if !live {
+        playlist.push_str("#EXT-X-ENDLIST\n");
+    }
+
+    playlist
+}
+
+/// Extract codecs from a MIME type. audio/mp4; codecs="mp4a.40.2" => mp4a.40.2
+fn mime_codecs(mime: &str) -> &str {
+    match mime.split_once("codecs=") {
+        Some((_, codecs)) => codecs.trim_matches('"'),
+        None => mime,
+    }
+}
@@ -0,0 +1,124 @@
+//! Segment names produced by ffmpeg, in variant folders or not.
+//! 720p30/24.m4s, 720p30_024.ts, 720p30/init.mp4, init_720p30.mp4
+
+use crate::error::Error;
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentKind {
+    Init,
+    Media(usize),
+}
+
+/// Variant, if part of the name, and kind of segment from a file stem.
+/// "24" => (None, Media(24)), "720p30_024" => (Some("720p30"), Media(24))
+pub fn parse_stem(stem: &str) -> Option<(Option<&str>, SegmentKind)> {
+    if stem == "init" {
+        return Some((None, SegmentKind::Init));
+    }
+
+    if let Ok(index) = stem.parse::<usize>() {
+        return Some((None, SegmentKind::Media(index)));
+    }
+
+    if let Some(variant) = stem.strip_prefix("init_") {
+        return Some((Some(variant), SegmentKind::Init));
+    }
+
+    let (variant, suffix) = stem.rsplit_once('_')?;
+
+    if variant.is_empty() {
+        return None;
+    }
+
+    if suffix == "init" {
+        return Some((Some(variant), SegmentKind::Init));
+    }
+
+    let index = suffix.parse::<usize>().ok()?;
+
+    Some((Some(variant), SegmentKind::Media(index)))
+}
+
+/// Index of a media segment from its URI in a media playlist.
+pub fn media_index(uri: &str) -> Option<usize> {
+    let stem = Path::new(uri).file_stem()?.to_str()?;
+
+    match parse_stem(stem)? {
+        (_, SegmentKind::Media(index)) => Some(index),
+        (_, SegmentKind::Init) => None,
+    }
+}
+
+/// Variant name of a media playlist URI in a master playlist.
+/// 720p30/index.m3u8 or 720p30.m3u8
+pub fn playlist_variant(uri: &str) -> Option<&str> {
+    let path = Path::new(uri);
+
+    let name = match path.parent().and_then(|parent| parent.file_name()) {
+        Some(name) => name,
+        None => path.file_stem()?,
+    };
+
+    name.to_str()
+}
+
+/// Variant folder of a segment or media playlist path. 720p30/24.m4s => 720p30
+pub fn path_variant(path: &Path) -> Result<&str, Error> {
+    path.parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::Path(path.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_names_parsed() {
+        assert_eq!(
+            parse_stem("720p30_024"),
+            Some((Some("720p30"), SegmentKind::Media(24)))
+        );
+        assert_eq!(
+            parse_stem("init_720p30"),
+            Some((Some("720p30"), SegmentKind::Init))
+        );
+        assert_eq!(
+            parse_stem("audio_init"),
+            Some((Some("audio"), SegmentKind::Init))
+        );
+        assert_eq!(
+            parse_stem("stream_720p30_7"),
+            Some((Some("stream_720p30"), SegmentKind::Media(7)))
+        );
+
+        assert_eq!(media_index("720p30_024.ts"), Some(24));
+        assert_eq!(playlist_variant("720p30.m3u8"), Some("720p30"));
+    }
+
+    #[test]
+    fn folder_names_parsed() {
+        assert_eq!(parse_stem("24"), Some((None, SegmentKind::Media(24))));
+        assert_eq!(parse_stem("init"), Some((None, SegmentKind::Init)));
+
+        assert_eq!(media_index("720p30/24.m4s"), Some(24));
+        assert_eq!(media_index("720p30/init.mp4"), None);
+        assert_eq!(playlist_variant("720p30/index.m3u8"), Some("720p30"));
+
+        let path = Path::new("720p30/24.m4s");
+
+        assert_eq!(path_variant(path).ok(), Some("720p30"));
+        assert!(path_variant(Path::new("24.m4s")).is_err());
+    }
+
+    #[test]
+    fn unknown_names_rejected() {
+        assert_eq!(parse_stem("_24"), None);
+        assert_eq!(parse_stem("720p30"), None);
+        assert_eq!(parse_stem("720p30_abc"), None);
+        assert_eq!(media_index("index.m3u8"), None);
+    }
+}
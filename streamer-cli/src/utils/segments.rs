@@ -0,0 +1,132 @@
+use crate::backend::Ipfs;
+use crate::error::Error;
+use crate::utils::dag_nodes::ipfs_dag_get_node_async;
+
+use linked_data::video::{
+    is_gap, DayNode, HourNode, MinuteNode, SecondNode, TimecodeNode, VideoNode,
+};
+
+use cid::Cid;
+
+/// VideoNodes followed from the latest, a day of 1 second segments.
+const MAX_CHAIN_LENGTH: usize = 24 * 3600;
+
+/// One VideoNode of an archive or live chain.
+pub struct Segment {
+    pub video: Cid,
+    pub setup: Option<Cid>,
+
+    /// Duration in seconds.
+    pub duration: f64,
+}
+
+/// Segments of an archive or of a live chain.
+pub struct VideoSegments {
+    pub segments: Vec<Segment>,
+
+    /// Followed from a VideoNode, the stream may still be growing.
+    pub live: bool,
+}
+
+/// Video segments in order, from a TimecodeNode or from the latest VideoNode of a stream.
+pub async fn get_video_segments(ipfs: &Ipfs, cid: Cid) -> Result<VideoSegments, Error> {
+    match ipfs_dag_get_node_async::<_, TimecodeNode>(ipfs, &cid.to_string()).await {
+        Ok(root) => {
+            return Ok(VideoSegments {
+                segments: timecode_segments(ipfs, root).await?,
+                live: false,
+            })
+        }
+        // Not a TimecodeNode, daemon failures are not retried as a chain
+        Err(Error::LinkedData(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let latest = match ipfs_dag_get_node_async::<_, VideoNode>(ipfs, &cid.to_string()).await {
+        Ok(node) => node,
+        Err(Error::LinkedData(_)) => return Err(Error::Invalid(format!("{} is not a video", cid))),
+        Err(e) => return Err(e),
+    };
+
+    Ok(VideoSegments {
+        segments: chain_segments(ipfs, cid, latest).await?,
+        live: true,
+    })
+}
+
+/// Follow the timecode structure, one segment per distinct VideoNode. Gaps are skipped.
+async fn timecode_segments(ipfs: &Ipfs, root: TimecodeNode) -> Result<Vec<Segment>, Error> {
+    let days: DayNode = ipfs_dag_get_node_async(ipfs, &root.timecode.link.to_string()).await?;
+
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for ipld in days.links_to_hours.iter().filter(|ipld| !is_gap(ipld)) {
+        let hours: HourNode = ipfs_dag_get_node_async(ipfs, &ipld.link.to_string()).await?;
+
+        for ipld in hours.links_to_minutes.iter().filter(|ipld| !is_gap(ipld)) {
+            let minutes: MinuteNode = ipfs_dag_get_node_async(ipfs, &ipld.link.to_string()).await?;
+
+            for ipld in minutes.links_to_seconds.iter().filter(|ipld| !is_gap(ipld)) {
+                let second: SecondNode =
+                    ipfs_dag_get_node_async(ipfs, &ipld.link.to_string()).await?;
+
+                if is_gap(&second.link_to_video) {
+                    continue;
+                }
+
+                let video = second.link_to_video.link;
+
+                // Segments longer than 1 second are linked by consecutive SecondNodes
+                if let Some(last) = segments.last_mut() {
+                    if last.video == video {
+                        continue;
+                    }
+                }
+
+                let node: VideoNode = ipfs_dag_get_node_async(ipfs, &video.to_string()).await?;
+
+                segments.push(Segment {
+                    video,
+                    setup: node.setup.map(|ipld| ipld.link),
+                    duration: node.duration.unwrap_or(1.0),
+                });
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Follow VideoNodes from the latest to the first, at most MAX_CHAIN_LENGTH of them.
+async fn chain_segments(ipfs: &Ipfs, latest: Cid, node: VideoNode) -> Result<Vec<Segment>, Error> {
+    let mut segments = Vec::new();
+    let mut cid = latest;
+    let mut node = node;
+
+    loop {
+        segments.push(Segment {
+            video: cid,
+            setup: node.setup.map(|ipld| ipld.link),
+            duration: node.duration.unwrap_or(1.0),
+        });
+
+        cid = match node.previous {
+            Some(ipld) => ipld.link,
+            None => break,
+        };
+
+        if segments.len() >= MAX_CHAIN_LENGTH {
+            eprintln!(
+                "❗ Segments: chain longer than {} nodes, oldest left out",
+                MAX_CHAIN_LENGTH
+            );
+            break;
+        }
+
+        node = ipfs_dag_get_node_async(ipfs, &cid.to_string()).await?;
+    }
+
+    segments.reverse();
+
+    Ok(segments)
+}
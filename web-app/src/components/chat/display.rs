@@ -195,7 +195,7 @@ impl Display {
         #[cfg(debug_assertions)]
         ConsoleService::info("Signed Message Received");
 
-        let trusted = sign_msg.verify();
+        let verification = sign_msg.verify();
 
         self.mod_db.add_peer(
             &sign_msg.data.peer,
@@ -209,11 +209,10 @@ impl Display {
             return false;
         }
 
-        if !trusted {
+        if let Err(e) = verification {
             self.mod_db.ban_peer(&peer);
 
-            #[cfg(debug_assertions)]
-            ConsoleService::info("Verifiable => false");
+            ConsoleService::warn(&format!("Verifiable => false {}", e));
 
             return false;
         }
@@ -18,6 +18,7 @@ use cid::Cid;
 
 use linked_data::beacon::Beacon;
 use linked_data::chat::{ChatId, Message, MessageType, UnsignedMessage};
+use linked_data::codec::Codec;
 use linked_data::signature::SignedMessage;
 
 use web3::types::Address;
@@ -458,12 +459,12 @@ impl Inputs {
         };
 
         #[cfg(debug_assertions)]
-        ConsoleService::info(&format!("Verifiable => {}", &signed_msg.verify()));
+        ConsoleService::info(&format!("Verifiable => {:?}", &signed_msg.verify()));
 
         let cb = self.link.callback_once(Msg::Minted);
         let client = self.props.ipfs.clone();
 
-        spawn_local(async move { cb.emit(client.dag_put(&signed_msg).await) });
+        spawn_local(async move { cb.emit(client.dag_put(&signed_msg, Codec::DagJson).await) });
 
         false
     }
@@ -9,7 +9,7 @@ use yew_router::components::RouterAnchor;
 
 use linked_data::blog::{FullPost, MicroPost};
 use linked_data::feed::Media;
-use linked_data::video::VideoMetadata;
+use linked_data::video::{ClipNode, VideoMetadata};
 
 use cid::Cid;
 
@@ -71,8 +71,10 @@ impl Component for Thumbnail {
 
         match &self.metadata {
             Media::Video(metadata) => self.render_video(metadata),
+            Media::Clip(metadata) => self.render_clip(metadata),
             Media::Blog(metadata) => self.render_blog(metadata),
             Media::Statement(metadata) => self.render_statement(metadata),
+            Media::Unknown(_) => html! {},
         }
     }
 }
@@ -92,6 +94,14 @@ impl Thumbnail {
             return false;
         }
 
+        #[cfg(debug_assertions)]
+        if let Media::Unknown(unknown) = &metadata {
+            ConsoleService::warn(&format!(
+                "Unsupported Media {} version {}",
+                unknown.header.media_type, unknown.header.version
+            ));
+        }
+
         #[cfg(debug_assertions)]
         ConsoleService::info("Metadata Update");
 
@@ -117,6 +127,21 @@ impl Thumbnail {
         }
     }
 
+    fn render_clip(&self, metadata: &ClipNode) -> Html {
+        let (hour, minute, second) = seconds_to_timecode(metadata.duration());
+
+        let title = metadata.title.as_deref().unwrap_or("Clip");
+
+        html! {
+            <div class="thumbnail">
+                <Anchor route=AppRoute::Video(self.props.metadata_cid) classes="thumbnail_link">
+                    <div class="video_thumbnail_title"> { title } </div>
+                    <div class="video_thumbnail_duration"> {&format!("{}:{}:{}", hour, minute, second) } </div>
+                </Anchor>
+            </div>
+        }
+    }
+
     fn render_blog(&self, metadata: &FullPost) -> Html {
         html! {
             <div class="thumbnail">
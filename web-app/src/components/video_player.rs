@@ -11,13 +11,15 @@ use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::spawn_local;
 
-use web_sys::{HtmlMediaElement, MediaSource, MediaSourceReadyState, SourceBuffer, Url};
+use web_sys::{
+    HtmlMediaElement, MediaSource, MediaSourceReadyState, SourceBuffer, SourceBufferAppendMode, Url,
+};
 
 use yew::prelude::{html, Component, ComponentLink, Html, Properties, ShouldRender};
 use yew::services::ConsoleService;
 
 use linked_data::beacon::Beacon;
-use linked_data::video::{SetupNode, Track, VideoMetadata};
+use linked_data::video::{ClipNode, SetupNode, Track, VideoMetadata};
 
 use cid::Cid;
 
@@ -44,6 +46,14 @@ struct MediaBuffers {
     tracks: Vec<Track>,
 }
 
+/// Time range of the video DAG to play.
+struct VideoOnDemand {
+    video: Cid,
+
+    start: f64,
+    end: f64,
+}
+
 struct LiveStream {
     beacon: Rc<Beacon>,
 
@@ -56,7 +66,7 @@ pub struct VideoPlayer {
     link: ComponentLink<Self>,
 
     ipfs: IpfsService,
-    metadata: Option<Rc<VideoMetadata>>,
+    vod: Option<VideoOnDemand>,
     live_stream: Option<LiveStream>,
 
     media_element: Option<HtmlMediaElement>,
@@ -92,6 +102,7 @@ pub enum Msg {
 pub struct Props {
     pub ipfs: IpfsService,
     pub metadata: Option<Rc<VideoMetadata>>,
+    pub clip: Option<Rc<ClipNode>>,
     pub beacon: Option<Rc<Beacon>>,
 }
 
@@ -103,6 +114,7 @@ impl Component for VideoPlayer {
         let Props {
             ipfs,
             metadata,
+            clip,
             beacon,
         } = props;
 
@@ -133,6 +145,20 @@ impl Component for VideoPlayer {
             poster_link.push_str("bafkreicovb5qdvrine4vidt77xahhvovahmekvsojbiqewp7ih7pzvnn7i");
         }
 
+        let vod = match (metadata, clip) {
+            (Some(md), _) => Some(VideoOnDemand {
+                video: md.video.link,
+                start: 0.0,
+                end: md.duration,
+            }),
+            (None, Some(clip)) => Some(VideoOnDemand {
+                video: clip.video.link,
+                start: clip.start,
+                end: clip.end,
+            }),
+            (None, None) => None,
+        };
+
         let cb = link.callback(|_| Msg::SourceOpen);
         let closure = Closure::wrap(Box::new(move || cb.emit(())) as Box<dyn Fn()>);
         media_source.set_onsourceopen(Some(closure.as_ref().unchecked_ref()));
@@ -161,7 +187,7 @@ impl Component for VideoPlayer {
             link,
 
             ipfs,
-            metadata,
+            vod,
             live_stream,
 
             media_element: None,
@@ -246,7 +272,7 @@ impl Component for VideoPlayer {
 
             media_element.set_src(&self.object_url);
 
-            self.seeking_closure = match self.metadata.as_ref() {
+            self.seeking_closure = match self.vod.as_ref() {
                 Some(_) => {
                     let cb = self.link.callback(|_| Msg::Seeking);
                     let closure = Closure::wrap(Box::new(move || cb.emit(())) as Box<dyn Fn()>);
@@ -293,12 +319,16 @@ impl VideoPlayer {
         self.media_source.set_onsourceopen(None);
         self.source_open_closure = None;
 
-        if let Some(metadata) = self.metadata.as_ref() {
-            self.media_source.set_duration(metadata.duration);
+        if let Some(vod) = self.vod.as_ref() {
+            self.media_source.set_duration(vod.end);
+
+            if let Some(media_element) = self.media_element.as_ref() {
+                media_element.set_current_time(vod.start);
+            }
 
             let cb = self.link.callback_once(Msg::SetupNode);
             let client = self.ipfs.clone();
-            let cid = metadata.video.link;
+            let cid = vod.video;
 
             spawn_local(async move { cb.emit(client.dag_get(cid, Some(SETUP_PATH)).await) });
         }
@@ -379,6 +409,19 @@ impl VideoPlayer {
         #[cfg(debug_assertions)]
         ConsoleService::info("On Seeking");
 
+        if let (Some(vod), Some(media_element)) = (self.vod.as_ref(), self.media_element.as_ref()) {
+            let current_time = media_element.current_time();
+
+            // Seeking outside the range trigger another seek
+            if current_time < vod.start {
+                return media_element.set_current_time(vod.start);
+            }
+
+            if current_time > vod.end {
+                return media_element.set_current_time(vod.end);
+            }
+        }
+
         self.state = MachineState::Flush;
     }
 
@@ -494,6 +537,11 @@ impl VideoPlayer {
                 }
             };
 
+            // Segments are placed by timecode since edited videos reuse segments with other timestamps
+            if self.vod.is_some() {
+                source_buffer.set_mode(SourceBufferAppendMode::Sequence);
+            }
+
             if track.name == "audio" {
                 audio_buffer = Some(source_buffer);
             } else {
@@ -562,7 +610,7 @@ impl VideoPlayer {
 
     /// Load either live or VOD segment.
     fn load_segment(&mut self) {
-        if self.metadata.is_some() {
+        if self.vod.is_some() {
             return self.load_vod_segment();
         }
 
@@ -662,6 +710,19 @@ impl VideoPlayer {
             }
         }
 
+        let vod = match self.vod.as_ref() {
+            Some(vod) => vod,
+            None => {
+                #[cfg(debug_assertions)]
+                ConsoleService::error("No Video On Demand");
+                return;
+            }
+        };
+
+        if buff_end < vod.start {
+            buff_end = vod.start;
+        }
+
         let (hours, minutes, seconds) = seconds_to_timecode(buff_end);
 
         #[cfg(debug_assertions)]
@@ -670,14 +731,12 @@ impl VideoPlayer {
             hours, minutes, seconds
         ));
 
-        let cid_string = match self.metadata.as_ref() {
-            Some(metadata) => metadata.video.link.to_string(),
-            None => {
-                #[cfg(debug_assertions)]
-                ConsoleService::error("No Metadata");
-                return;
-            }
-        };
+        let timecode = (hours as u64 * 3600 + minutes as u64 * 60 + seconds as u64) as f64;
+
+        buffers.audio.set_timestamp_offset(timecode);
+        buffers.video.set_timestamp_offset(timecode);
+
+        let cid_string = vod.video.to_string();
 
         let audio_path = format!(
             "{}/time/hour/{}/minute/{}/second/{}/video/track/audio",
@@ -826,8 +885,21 @@ impl VideoPlayer {
             return self.flush_buffer();
         }
 
-        if let Some(metadata) = self.metadata.as_ref() {
-            if buff_end >= metadata.duration {
+        if let Some(vod) = self.vod.as_ref() {
+            if current_time >= vod.end {
+                if let Some(media_element) = self.media_element.as_ref() {
+                    if let Err(e) = media_element.pause() {
+                        ConsoleService::error(&format!("{:?}", e));
+                    }
+                }
+            }
+
+            if buff_end >= vod.end {
+                // Keep checking until playback reach the end of the range
+                if current_time < vod.end {
+                    return self.set_timeout();
+                }
+
                 #[cfg(debug_assertions)]
                 ConsoleService::info("End Of Video");
                 return;
@@ -8,7 +8,8 @@ use wasm_bindgen_futures::spawn_local;
 use yew::prelude::{html, Component, ComponentLink, Html, Properties, ShouldRender};
 use yew::services::ConsoleService;
 
-use linked_data::video::VideoMetadata;
+use linked_data::feed::Media;
+use linked_data::video::{ClipNode, VideoMetadata};
 
 use cid::Cid;
 
@@ -16,7 +17,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 enum State {
     Loading,
-    Ready(Rc<VideoMetadata>),
+    Video(Rc<VideoMetadata>),
+    Clip(Rc<ClipNode>),
     Error,
 }
 
@@ -32,7 +34,7 @@ pub struct Props {
 }
 
 pub enum Msg {
-    Metadata(Result<VideoMetadata>),
+    Metadata(Result<Media>),
 }
 
 impl Component for Video {
@@ -72,7 +74,8 @@ impl Component for Video {
             {
                 match &self.state {
                     State::Loading => html! { <Loading /> },
-                    State::Ready(md) => html! { <VideoPlayer ipfs=self.ipfs.clone() metadata=md.clone() /> },
+                    State::Video(md) => html! { <VideoPlayer ipfs=self.ipfs.clone() metadata=md.clone() /> },
+                    State::Clip(clip) => html! { <VideoPlayer ipfs=self.ipfs.clone() clip=clip.clone() /> },
                     State::Error => html! { <Error /> },
                 }
             }
@@ -82,9 +85,14 @@ impl Component for Video {
 }
 
 impl Video {
-    fn update_metadata(&mut self, response: Result<VideoMetadata>) -> bool {
+    fn update_metadata(&mut self, response: Result<Media>) -> bool {
         self.state = match response {
-            Ok(md) => State::Ready(Rc::from(md)),
+            Ok(Media::Video(md)) => State::Video(Rc::from(md)),
+            Ok(Media::Clip(clip)) => State::Clip(Rc::from(clip)),
+            Ok(_) => {
+                ConsoleService::error("Media Is Not A Video");
+                State::Error
+            }
             Err(e) => {
                 ConsoleService::error(&format!("{:#?}", e));
                 State::Error
@@ -17,7 +17,9 @@ use yew::Callback;
 use cid::multibase::Base;
 use cid::Cid;
 
-use reqwest::multipart::Form;
+use linked_data::codec::Codec;
+
+use reqwest::multipart::{Form, Part};
 use reqwest::{Client, Url};
 
 const DEFAULT_URI: &str = "http://localhost:5001/api/v0/";
@@ -104,27 +106,34 @@ impl IpfsService {
         Ok((audio_data.to_vec(), video_data.to_vec()))
     }
 
-    /// Serialize then add dag node to IPFS. Return a CID.
-    pub async fn dag_put<T>(&self, node: &T) -> Result<Cid>
+    /// Serialize with this codec then add dag node to IPFS. Return a CID.
+    pub async fn dag_put<T>(&self, node: &T, codec: Codec) -> Result<Cid>
     where
-        T: ?Sized + Serialize,
+        T: Serialize,
     {
         #[cfg(debug_assertions)]
         ConsoleService::info(&format!(
-            "Serde: Serialize => {}",
+            "Serde: Serialize {} => {}",
+            codec,
             serde_json::to_string_pretty(node).unwrap()
         ));
 
-        let data = serde_json::to_string(node)?;
+        let data = codec.encode(node)?;
 
         //Reqwest was hacked to properly format multipart request with text ONLY
-        let form = Form::new().text("object data", data);
+        let part = match codec {
+            Codec::DagJson => Part::text(String::from_utf8(data)?),
+            Codec::DagCbor => Part::bytes(data),
+        };
+
+        let form = Form::new().part("object data", part);
 
         let url = self.base_url.join("dag/put")?;
 
         let response: DagPutResponse = self
             .client
             .post(url)
+            .query(&[("input-codec", codec.name()), ("store-codec", codec.name())])
             .multipart(form)
             .send()
             .await?
@@ -140,6 +149,8 @@ impl IpfsService {
     }
 
     /// Deserialize dag node from IPFS path. Return dag node.
+    ///
+    /// IPFS output DAG-JSON whatever the codec the node was stored with.
     pub async fn dag_get<U, T>(&self, cid: Cid, path: Option<U>) -> Result<T>
     where
         U: Into<Cow<'static, str>>,